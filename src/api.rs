@@ -1,69 +1,117 @@
+mod auth;
 mod config;
+mod extraction;
 mod files;
+mod history;
+mod layer_cache;
 mod manual;
+mod manual_limiter;
+mod moonraker;
 mod print;
 mod update;
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use futures::{stream::BoxStream, StreamExt};
+use futures::{stream::BoxStream, SinkExt, StreamExt};
 use poem::{
     error::NotFound,
     listener::TcpListener,
     middleware::Cors,
-    web::{sse::Event, Data},
+    web::{
+        sse::Event,
+        websocket::{BoxWebSocketUpgraded, Message, WebSocket},
+        Data,
+    },
     EndpointExt, Result, Route, Server,
 };
 use poem_openapi::{
     payload::{EventStream, Json},
     types::ToJSON,
-    OpenApi, OpenApiService,
+    OpenApi, OpenApiService, Union,
 };
-use tokio::{
-    sync::{broadcast, mpsc, RwLock},
-    time::interval,
-};
-use tokio_stream::wrappers::BroadcastStream;
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 use tokio_util::sync::CancellationToken;
 use tracing::instrument;
 
 use crate::{
     api_objects::{
-        ExecutableVersion, FileMetadata, LocationCategory, PhysicalState, PrintMetadata,
-        PrinterState, PrinterStatus,
+        ApiMetrics, Capabilities, ExecutableVersion, FileMetadata, LocationCategory, PhysicalState,
+        PrintEvent, PrintMetadata, PrintStats, PrinterState, PrinterStatus, StreamLag,
+        ThumbnailDimensions, ThumbnailSize, UsbEvent,
     },
-    configuration::{ApiConfig, Configuration},
+    configuration::{ApiConfig, Configuration, LockedProfiles, ResinProfile},
+    display::DisplayPreview,
     error::OdysseyError,
-    printer::Operation,
-    printfile::PrintFile,
-    sl1::Sl1,
+    printer::{Operation, PrioritySender},
+    printfile::{open_print_file, SUPPORTED_EXTENSIONS},
+    serial_handler::GcodeLog,
     COMMIT_HASH, COMPILE_TARGET, VERSION,
 };
 
 #[derive(Debug)]
 struct Api;
 
+/// Messages carried by the status stream, either a full state update, a
+/// discrete print lifecycle event, a USB drive coming or going, or notice that
+/// the client fell behind and missed some of them
+#[derive(Clone, Debug, Union)]
+enum StatusStreamMessage {
+    Status(Box<PrinterState>),
+    Event(PrintEvent),
+    Usb(UsbEvent),
+    Lagged(StreamLag),
+}
+
+/// Total status stream messages dropped because a client fell behind
+#[derive(Default)]
+struct LaggedMessages(AtomicU64);
+
 #[OpenApi]
 impl Api {
-    #[instrument(ret, skip(operation_sender))]
+    #[instrument(ret, skip(priority_sender))]
     #[oai(path = "/shutdown", method = "post")]
     async fn shutdown(
         &self,
-        Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+        Data(priority_sender): Data<&PrioritySender>,
         Data(cancellation_token): Data<&CancellationToken>,
     ) -> Result<()> {
-        Self::send_statemachine_operation(operation_sender, Operation::Shutdown {}).await?;
+        Self::send_priority_operation(priority_sender, Operation::Shutdown {}).await?;
         cancellation_token.cancel();
         Ok(())
     }
 
+    /// Halt all motion and curing immediately, jumping ahead of any queued
+    /// operations. Odyssey must be restarted before the printer is usable again
+    #[instrument(ret, skip(priority_sender))]
+    #[oai(path = "/emergency_stop", method = "post")]
+    async fn emergency_stop(&self, Data(priority_sender): Data<&PrioritySender>) -> Result<()> {
+        Self::send_priority_operation(priority_sender, Operation::EmergencyStop).await?;
+        Ok(())
+    }
+
+    /// Send a safety critical operation ahead of any queued operations
+    async fn send_priority_operation(
+        priority_sender: &PrioritySender,
+        operation: Operation,
+    ) -> Result<(), OdysseyError> {
+        priority_sender.try_send(operation)
+    }
+
     async fn send_statemachine_operation(
         operation_sender: &mpsc::Sender<Operation>,
         operation: Operation,
     ) -> Result<(), OdysseyError> {
         operation_sender
-            .send(operation)
-            .await
+            .try_send(operation)
             .map_err(OdysseyError::from)
     }
 
@@ -77,6 +125,65 @@ impl Api {
         })
     }
 
+    /// Totals across every print in the history, such as print time, resin used
+    /// and how often prints succeed
+    #[instrument(ret, skip(configuration))]
+    #[oai(path = "/stats", method = "get")]
+    async fn stats(
+        &self,
+        Data(configuration): Data<&Arc<Configuration>>,
+    ) -> Result<Json<PrintStats>> {
+        history::HistoryApi::load_stats(configuration)
+            .await
+            .map(Json)
+    }
+
+    /// What the printer supports, from the current config file and the
+    /// features Odyssey was built with
+    #[instrument(ret, skip(full_config, state_ref))]
+    #[oai(path = "/capabilities", method = "get")]
+    async fn capabilities(
+        &self,
+        Data(full_config): Data<&Arc<Configuration>>,
+        Data(state_ref): Data<&Arc<RwLock<PrinterState>>>,
+    ) -> Result<Json<Capabilities>> {
+        let configuration = config::ConfigApi::current_config(full_config)?;
+        let (min_z, max_z) = configuration.printer.z_limits();
+
+        Ok(Json(Capabilities {
+            file_formats: SUPPORTED_EXTENSIONS
+                .iter()
+                .map(|extension| extension.to_string())
+                .collect(),
+            usb_files: !configuration.api.usb_glob.is_empty(),
+            thumbnail_sizes: ThumbnailSize::ALL
+                .into_iter()
+                .map(|size| {
+                    let (width, height) = size.dimensions();
+                    ThumbnailDimensions {
+                        size,
+                        width,
+                        height,
+                    }
+                })
+                .collect(),
+            frame_buffer_available: state_ref.read().await.frame_buffer_available,
+            display_backend: configuration.display.backend.unwrap_or_default(),
+            screen_width: configuration.display.screen_width,
+            screen_height: configuration.display.screen_height,
+            min_z,
+            max_z,
+            cure_zones: configuration
+                .gcode
+                .cure_zones()
+                .into_iter()
+                .map(|zone| zone.name)
+                .collect(),
+            moonraker: configuration.api.enable_moonraker.unwrap_or(false),
+            mqtt: cfg!(feature = "mqtt"),
+        }))
+    }
+
     #[instrument(ret, skip(state_ref))]
     #[oai(path = "/status", method = "get")]
     async fn get_status(
@@ -86,39 +193,272 @@ impl Api {
         Json(state_ref.read().await.clone())
     }
 
-    #[instrument(skip(state_receiver))]
+    #[instrument(skip(
+        state_receiver,
+        event_receiver,
+        usb_receiver,
+        state_ref,
+        lagged_messages
+    ))]
     #[oai(path = "/status/stream", method = "get")]
     async fn status_stream(
         &self,
         Data(state_receiver): Data<&Arc<broadcast::Receiver<PrinterState>>>,
-    ) -> EventStream<BoxStream<'static, Option<PrinterState>>> {
-        EventStream::new(Api::_status_stream(state_receiver))
-            .keep_alive(Duration::from_secs(15))
-            .to_event(|status| match status {
-                Some(status_update) => {
-                    Event::message(status_update.to_json_string()).event_type("status")
+        Data(event_receiver): Data<&Arc<broadcast::Receiver<PrintEvent>>>,
+        Data(usb_receiver): Data<&Arc<broadcast::Receiver<UsbEvent>>>,
+        Data(state_ref): Data<&Arc<RwLock<PrinterState>>>,
+        Data(lagged_messages): Data<&Arc<LaggedMessages>>,
+    ) -> EventStream<BoxStream<'static, StatusStreamMessage>> {
+        EventStream::new(
+            Api::_status_stream(
+                state_receiver,
+                event_receiver,
+                usb_receiver,
+                state_ref,
+                lagged_messages,
+            )
+            .await,
+        )
+        .keep_alive(Duration::from_secs(15))
+        .to_event(|message| match message {
+            StatusStreamMessage::Status(status_update) => {
+                Event::message(status_update.to_json_string()).event_type("status")
+            }
+            StatusStreamMessage::Event(print_event) => Event::message(print_event.to_json_string())
+                .event_type(print_event.event_type.name()),
+            StatusStreamMessage::Usb(usb_event) => {
+                Event::message(usb_event.to_json_string()).event_type(usb_event.event_type.name())
+            }
+            StatusStreamMessage::Lagged(lag) => {
+                Event::message(lag.to_json_string()).event_type("lagged")
+            }
+        })
+    }
+
+    #[instrument(skip(
+        websocket,
+        state_receiver,
+        event_receiver,
+        usb_receiver,
+        state_ref,
+        lagged_messages
+    ))]
+    #[oai(path = "/status/ws", method = "get")]
+    async fn status_websocket(
+        &self,
+        websocket: WebSocket,
+        Data(state_receiver): Data<&Arc<broadcast::Receiver<PrinterState>>>,
+        Data(event_receiver): Data<&Arc<broadcast::Receiver<PrintEvent>>>,
+        Data(usb_receiver): Data<&Arc<broadcast::Receiver<UsbEvent>>>,
+        Data(state_ref): Data<&Arc<RwLock<PrinterState>>>,
+        Data(lagged_messages): Data<&Arc<LaggedMessages>>,
+    ) -> BoxWebSocketUpgraded {
+        let state_receiver = state_receiver.clone();
+        let event_receiver = event_receiver.clone();
+        let usb_receiver = usb_receiver.clone();
+        let state_ref = state_ref.clone();
+        let lagged_messages = lagged_messages.clone();
+
+        websocket
+            .on_upgrade(move |socket| async move {
+                let mut updates = Api::_status_stream(
+                    &state_receiver,
+                    &event_receiver,
+                    &usb_receiver,
+                    &state_ref,
+                    &lagged_messages,
+                )
+                .await;
+                let (mut sink, mut incoming) = socket.split();
+
+                // Forward updates until either side goes away. Returning drops the
+                // stream, and with it the broadcast receivers
+                loop {
+                    tokio::select! {
+                        update = updates.next() => {
+                            let frame = match update {
+                                Some(StatusStreamMessage::Status(status_update)) => {
+                                    Api::_websocket_frame("status", status_update.to_json_string())
+                                }
+                                Some(StatusStreamMessage::Event(print_event)) => {
+                                    Api::_websocket_frame(
+                                        print_event.event_type.name(),
+                                        print_event.to_json_string(),
+                                    )
+                                }
+                                Some(StatusStreamMessage::Usb(usb_event)) => {
+                                    Api::_websocket_frame(
+                                        usb_event.event_type.name(),
+                                        usb_event.to_json_string(),
+                                    )
+                                }
+                                Some(StatusStreamMessage::Lagged(lag)) => {
+                                    Api::_websocket_frame("lagged", lag.to_json_string())
+                                }
+                                None => break,
+                            };
+                            if sink.send(frame).await.is_err() {
+                                break;
+                            }
+                        }
+                        message = incoming.next() => match message {
+                            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                            Some(Ok(_)) => {}
+                        }
+                    }
                 }
-                None => Event::Retry { retry: 1 },
+                tracing::info!("Status websocket closed");
             })
+            .boxed()
+    }
+
+    /// WebSocket frames have no event type of their own, so tag the payload with
+    /// the same name the SSE stream uses
+    fn _websocket_frame(event_type: &str, data: String) -> Message {
+        Message::Text(format!(
+            "{{\"event\":\"{}\",\"data\":{}}}",
+            event_type, data
+        ))
     }
 
-    fn _status_stream(
+    /// Stream status updates, print events and USB events, starting with the
+    /// current cached state so new clients don't have to wait for the next
+    /// state change. A client which falls behind is told how many messages it
+    /// missed
+    async fn _status_stream(
         state_receiver: &Arc<broadcast::Receiver<PrinterState>>,
-    ) -> BoxStream<'static, Option<PrinterState>> {
-        BroadcastStream::new(state_receiver.resubscribe())
-            .map(|result| result.ok())
-            .boxed()
+        event_receiver: &Arc<broadcast::Receiver<PrintEvent>>,
+        usb_receiver: &Arc<broadcast::Receiver<UsbEvent>>,
+        state_ref: &Arc<RwLock<PrinterState>>,
+        lagged_messages: &Arc<LaggedMessages>,
+    ) -> BoxStream<'static, StatusStreamMessage> {
+        // Subscribe before reading the cached state, so no updates are missed
+        let status_receiver = state_receiver.resubscribe();
+        let initial_state = state_ref.read().await.clone();
+
+        // Missed status updates are made up for with the cached state
+        let state_ref = state_ref.clone();
+        let status_lagged = lagged_messages.clone();
+        let status_stream = futures::stream::once(async { Ok(initial_state) })
+            .chain(BroadcastStream::new(status_receiver))
+            .then(move |result| {
+                let state_ref = state_ref.clone();
+                let lagged_messages = status_lagged.clone();
+                async move {
+                    match result {
+                        Ok(state) => vec![StatusStreamMessage::Status(Box::new(state))],
+                        Err(BroadcastStreamRecvError::Lagged(skipped)) => vec![
+                            Api::_lagged("status", skipped, &lagged_messages),
+                            StatusStreamMessage::Status(Box::new(state_ref.read().await.clone())),
+                        ],
+                    }
+                }
+            })
+            .flat_map(futures::stream::iter);
+
+        let event_lagged = lagged_messages.clone();
+        let event_stream =
+            BroadcastStream::new(event_receiver.resubscribe()).map(move |result| match result {
+                Ok(print_event) => StatusStreamMessage::Event(print_event),
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                    Api::_lagged("event", skipped, &event_lagged)
+                }
+            });
+
+        let usb_lagged = lagged_messages.clone();
+        let usb_stream =
+            BroadcastStream::new(usb_receiver.resubscribe()).map(move |result| match result {
+                Ok(usb_event) => StatusStreamMessage::Usb(usb_event),
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                    Api::_lagged("usb", skipped, &usb_lagged)
+                }
+            });
+
+        futures::stream::select(
+            status_stream,
+            futures::stream::select(event_stream, usb_stream),
+        )
+        .boxed()
+    }
+
+    fn _lagged(
+        stream: &str,
+        skipped: u64,
+        lagged_messages: &LaggedMessages,
+    ) -> StatusStreamMessage {
+        tracing::warn!(
+            "Status stream client fell behind, skipped {} {} messages",
+            skipped,
+            stream
+        );
+        lagged_messages.0.fetch_add(skipped, Ordering::Relaxed);
+        StatusStreamMessage::Lagged(StreamLag {
+            stream: stream.to_string(),
+            skipped,
+        })
+    }
+
+    #[instrument(ret, skip(lagged_messages))]
+    #[oai(path = "/metrics", method = "get")]
+    async fn metrics(&self, Data(lagged_messages): Data<&Arc<LaggedMessages>>) -> Json<ApiMetrics> {
+        Json(ApiMetrics {
+            lagged_messages: lagged_messages.0.load(Ordering::Relaxed),
+        })
+    }
+
+    #[instrument(skip(display_preview))]
+    #[oai(path = "/display/preview", method = "get")]
+    async fn display_preview(
+        &self,
+        Data(display_preview): Data<&DisplayPreview>,
+    ) -> Result<files::PngResponse> {
+        let preview = display_preview.get().ok_or(NotFound(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No frame has been displayed",
+        )))?;
+
+        Ok(files::PngResponse::new(preview, "preview.png", true))
     }
 
     fn _get_filedata(
         file_path: &str,
         location: LocationCategory,
+        directory_label: Option<&str>,
         configuration: &ApiConfig,
     ) -> Result<FileMetadata> {
-        tracing::info!("Getting file data");
+        files::FilesApi::_get_filedata(file_path, location, directory_label, configuration)
+    }
+
+    /// Look up the selected resin profile, or otherwise the one mapped to the
+    /// file's directory
+    async fn _resolve_profile(
+        profile: Option<String>,
+        file_data: &FileMetadata,
+        configuration: &Configuration,
+        resin_profiles: &LockedProfiles,
+    ) -> Result<Option<ResinProfile>> {
+        let name = match profile {
+            Some(name) => name,
+            None => match configuration.directory_profile(&file_data.path) {
+                Some(name) => {
+                    tracing::info!("Using resin profile {} for {}", name, file_data.path);
+                    name.clone()
+                }
+                None => return Ok(None),
+            },
+        };
 
-        // TODO handle USB _get_filedata
-        FileMetadata::from_path(file_path, &configuration.upload_path, location).map_err(NotFound)
+        Ok(Some(
+            resin_profiles
+                .read()
+                .await
+                .get(&name)
+                .cloned()
+                .ok_or(NotFound(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No resin profile named {}", name),
+                )))?,
+        ))
     }
 
     fn _get_print_metadata(
@@ -126,10 +466,10 @@ impl Api {
         location: LocationCategory,
         configuration: &ApiConfig,
     ) -> Result<PrintMetadata> {
-        let file_data = Api::_get_filedata(file_path, location, configuration)?;
+        let file_data = Api::_get_filedata(file_path, location, None, configuration)?;
         tracing::info!("Extracting print metadata");
 
-        Ok(Sl1::from_file(file_data).map_err(NotFound)?.get_metadata())
+        Ok(open_print_file(file_data).map_err(NotFound)?.get_metadata())
     }
 }
 
@@ -137,43 +477,59 @@ async fn run_state_listener(
     mut state_receiver: broadcast::Receiver<PrinterState>,
     state_ref: Arc<RwLock<PrinterState>>,
 ) {
-    let mut interv = interval(Duration::from_millis(1000));
-
-    let mut state: Result<PrinterState, broadcast::error::TryRecvError>;
-
+    // Keep the cached state current, as it seeds every new status stream
     loop {
-        state = state_receiver.try_recv();
-        if state.is_ok() {
-            let mut state_data = state_ref.write().await;
-            *state_data = state.clone().unwrap();
+        match state_receiver.recv().await {
+            Ok(state) => *state_ref.write().await = state,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("State listener lagged, skipped {} updates", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
         }
-
-        interv.tick().await;
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn start_api(
     full_config: Arc<Configuration>,
     operation_sender: mpsc::Sender<Operation>,
+    priority_sender: PrioritySender,
     state_receiver: broadcast::Receiver<PrinterState>,
+    event_receiver: broadcast::Receiver<PrintEvent>,
+    usb_receiver: broadcast::Receiver<UsbEvent>,
+    display_preview: DisplayPreview,
+    gcode_log: GcodeLog,
     cancellation_token: CancellationToken,
 ) {
+    // Nothing is known of the printer until the state machine first reports,
+    // so the position is left unknown as it is while shut down
     let state_ref = Arc::new(RwLock::new(PrinterState {
         print_data: None,
         paused: None,
         layer: None,
         physical_state: PhysicalState {
-            z: 0.0,
-            z_microns: 0,
-            curing: false,
+            z: f64::MAX,
+            z_microns: u32::MAX,
+            curing: BTreeMap::new(),
+            temperature: None,
+            firmware_version: None,
         },
-        status: PrinterStatus::Shutdown,
+        status: PrinterStatus::Initializing,
+        time_remaining_seconds: None,
+        resume_countdown_seconds: None,
+        print_settings: None,
+        print_queue: Vec::new(),
+        uv_on_seconds: 0.0,
+        print_started_at: None,
+        serial_connected: None,
+        frame_buffer_available: false,
+        error: None,
     }));
 
-    tokio::spawn(run_state_listener(
-        state_receiver.resubscribe(),
-        state_ref.clone(),
-    ));
+    // Status streams only need updates from when they connect, while the
+    // listener keeps the original receiver so it also sees any queued updates
+    let stream_receiver = state_receiver.resubscribe();
+    tokio::spawn(run_state_listener(state_receiver, state_ref.clone()));
 
     let addr = format!("0.0.0.0:{0}", full_config.api.port);
 
@@ -185,6 +541,7 @@ pub async fn start_api(
             update::UpdateApi,
             print::PrintApi,
             config::ConfigApi,
+            history::HistoryApi,
         ),
         "Odyssey API",
         "1.0",
@@ -192,20 +549,42 @@ pub async fn start_api(
 
     let ui = api_service.swagger_ui();
 
+    let resin_profiles: LockedProfiles = Arc::new(RwLock::new(full_config.resin_profiles.clone()));
+
+    let extraction_limiter = Arc::new(extraction::ExtractionLimiter::new(&full_config.api));
+    let manual_limiter = Arc::new(manual_limiter::ManualLimiter::new(&full_config.api));
+    let layer_cache = Arc::new(layer_cache::LayerCache::default());
+
     let mut app = Route::new().nest("/", api_service);
 
     if full_config.api.enable_docs.is_some_and(|enable| enable) || cfg!(debug_assertions) {
         app = app.nest("/docs", ui);
     }
 
+    if full_config.api.enable_moonraker.unwrap_or(false) {
+        app = moonraker::mount(app);
+    }
+
     let api_shutdown_trigger = cancellation_token.clone();
+    let api_key_auth = auth::ApiKeyAuth::new(full_config.api.api_key.clone());
 
     let app = app
         .data(operation_sender)
-        .data(Arc::new(state_receiver))
+        .data(priority_sender)
+        .data(Arc::new(stream_receiver))
+        .data(Arc::new(event_receiver))
+        .data(Arc::new(usb_receiver))
+        .data(display_preview)
+        .data(gcode_log)
         .data(state_ref.clone())
+        .data(extraction_limiter)
+        .data(manual_limiter)
+        .data(layer_cache)
+        .data(Arc::new(LaggedMessages::default()))
+        .data(resin_profiles)
         .data(full_config)
         .data(api_shutdown_trigger)
+        .with(api_key_auth)
         .with(Cors::new());
 
     match Server::new(TcpListener::bind(addr))
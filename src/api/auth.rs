@@ -0,0 +1,76 @@
+use std::io;
+
+use poem::{error::Unauthorized, http::Method, Endpoint, Middleware, Request, Result};
+
+/// Header carrying the API key, as an alternative to a bearer token
+const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Requires the configured API key on any request which could change the
+/// printer's state, such as starting a print or shutting down. Reads such as
+/// the status and its streams are left open. Without a configured key, every
+/// request is allowed
+pub struct ApiKeyAuth {
+    api_key: Option<String>,
+}
+
+impl ApiKeyAuth {
+    pub fn new(api_key: Option<String>) -> ApiKeyAuth {
+        ApiKeyAuth { api_key }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for ApiKeyAuth {
+    type Output = ApiKeyAuthEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ApiKeyAuthEndpoint {
+            ep,
+            api_key: self.api_key.clone(),
+        }
+    }
+}
+
+pub struct ApiKeyAuthEndpoint<E> {
+    ep: E,
+    api_key: Option<String>,
+}
+
+impl<E: Endpoint> Endpoint for ApiKeyAuthEndpoint<E> {
+    type Output = E::Output;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let read_only = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+        let authorized = self
+            .api_key
+            .as_ref()
+            .is_none_or(|api_key| has_api_key(&req, api_key));
+
+        if !read_only && !authorized {
+            tracing::warn!(
+                "Refusing {} {} without a valid API key",
+                req.method(),
+                req.uri().path()
+            );
+            return Err(Unauthorized(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Missing or invalid API key",
+            )));
+        }
+
+        self.ep.call(req).await
+    }
+}
+
+/// Whether the request carries the given API key, either in the X-Api-Key
+/// header or as a bearer token
+pub fn has_api_key(req: &Request, api_key: &str) -> bool {
+    let header_key = req.header(API_KEY_HEADER);
+    let bearer_key = req
+        .header("Authorization")
+        .and_then(|authorization| authorization.strip_prefix("Bearer "));
+
+    [header_key, bearer_key]
+        .into_iter()
+        .flatten()
+        .any(|key| key.trim() == api_key)
+}
@@ -1,15 +1,60 @@
-use std::sync::Arc;
+use std::{collections::HashMap, io, sync::Arc};
 
 use optional_struct::Applicable;
-use poem::{web::Data, Result};
-use poem_openapi::{payload::Json, OpenApi};
+use poem::{
+    error::{BadRequest, Conflict, InternalServerError, NotFound},
+    web::Data,
+    Result,
+};
+use poem_openapi::{
+    param::Query,
+    payload::{Json, PlainText},
+    ApiRequest, ApiResponse, OpenApi,
+};
+use tokio::sync::{mpsc, RwLock};
 use tracing::instrument;
 
-use crate::configuration::{Configuration, UpdateConfiguration};
+use crate::{
+    api_objects::{PrinterState, PrinterStatus, ResolvedGcode},
+    config_schema::{config_schema, ConfigFieldSchema},
+    configuration::{
+        refuse_errors, ConfigProblem, Configuration, LockedProfiles, ResinProfile,
+        UpdateConfiguration,
+    },
+    gcode,
+    printer::Operation,
+};
 
 #[derive(Debug)]
 pub struct ConfigApi;
 
+#[derive(ApiResponse)]
+pub enum YamlResponse {
+    #[oai(status = 200, content_type = "application/yaml")]
+    Yaml(
+        PlainText<String>,
+        #[oai(header = "Content-Disposition")] String,
+    ),
+}
+
+#[derive(Debug, ApiResponse)]
+#[allow(clippy::large_enum_variant)]
+pub enum PatchConfigResponse {
+    /// The configuration as written to the config file
+    #[oai(status = 200)]
+    Ok(Json<Configuration>),
+    /// Nothing was written, as the amended configuration would be refused
+    #[oai(status = 422)]
+    Invalid(Json<Vec<ConfigProblem>>),
+}
+
+#[derive(Debug, ApiRequest)]
+pub enum YamlRequest {
+    #[oai(content_type = "application/yaml")]
+    Yaml(PlainText<String>),
+    Text(PlainText<String>),
+}
+
 #[OpenApi(prefix_path = "/config")]
 impl ConfigApi {
     #[instrument(ret, skip(full_config))]
@@ -17,20 +62,220 @@ impl ConfigApi {
     async fn get_config(
         &self,
         Data(full_config): Data<&Arc<Configuration>>,
-    ) -> Json<Configuration> {
-        Json(full_config.as_ref().clone())
+    ) -> Result<Json<Configuration>> {
+        Ok(Json(Self::current_config(full_config)?.redacted()))
     }
 
+    /// Check the running configuration for settings likely to fail once
+    /// they're used, such as missing paths or empty gcode
     #[instrument(ret, skip(full_config))]
+    #[oai(path = "/validate", method = "get")]
+    async fn validate_config(
+        &self,
+        Data(full_config): Data<&Arc<Configuration>>,
+    ) -> Result<Json<Vec<ConfigProblem>>> {
+        Ok(Json(Self::current_config(full_config)?.validate()))
+    }
+
+    /// Type, unit and recommended range of every setting, for frontends to
+    /// render and check inputs without hardcoding the config layout
+    #[instrument]
+    #[oai(path = "/schema", method = "get")]
+    async fn get_schema(&self) -> Json<Vec<ConfigFieldSchema>> {
+        Json(config_schema())
+    }
+
+    /// Amend the configuration, writing it to the config file and handing it
+    /// to the printer. Mid-print, the printer takes it up once the print
+    /// finishes, and the serial port can't be changed at all
+    #[instrument(ret, skip(full_config, operation_sender, state_ref))]
     #[oai(path = "/", method = "patch")]
     async fn patch_config(
         &self,
         Data(full_config): Data<&Arc<Configuration>>,
+        Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+        Data(state_ref): Data<&Arc<RwLock<PrinterState>>>,
         Json(patch_config): Json<UpdateConfiguration>,
-    ) -> Result<Json<Configuration>> {
-        let ammend_config = patch_config.build(full_config.as_ref().clone());
+    ) -> Result<PatchConfigResponse> {
+        let current_config = Self::current_config(full_config)?;
+        let ammend_config = patch_config.build(current_config.clone());
+
+        Self::check_serial_unchanged(&current_config, &ammend_config, state_ref).await?;
+
+        let problems = ammend_config
+            .validate()
+            .into_iter()
+            .filter(ConfigProblem::is_error)
+            .collect::<Vec<_>>();
+        if !problems.is_empty() {
+            tracing::warn!("Refusing config patch: {:?}", problems);
+            return Ok(PatchConfigResponse::Invalid(Json(problems)));
+        }
         Configuration::overwrite_file(&ammend_config)?;
 
-        Ok(Json(ammend_config))
+        // Read back what was written, so the client sees exactly what will be
+        // loaded next time
+        let written = ammend_config
+            .config_file
+            .clone()
+            .map(Configuration::from_file)
+            .transpose()
+            .map_err(|e| InternalServerError(io::Error::other(e.to_string())))?
+            .unwrap_or(ammend_config);
+        Self::reload_printer(operation_sender, &written);
+
+        Ok(PatchConfigResponse::Ok(Json(written.redacted())))
+    }
+
+    /// Export the complete configuration as YAML, for backing up or copying
+    /// to another printer
+    #[instrument(skip(full_config))]
+    #[oai(path = "/export", method = "get")]
+    async fn export_config(
+        &self,
+        Data(full_config): Data<&Arc<Configuration>>,
+    ) -> Result<YamlResponse> {
+        let content = serde_yaml::to_string(&Self::current_config(full_config)?.redacted())
+            .map_err(InternalServerError)?;
+
+        Ok(YamlResponse::Yaml(
+            PlainText(content),
+            "attachment; filename=\"config.yaml\"".to_string(),
+        ))
+    }
+
+    /// Replace the complete configuration with an exported one. The existing
+    /// config file is backed up, as with any other change
+    #[instrument(ret, skip(full_config, operation_sender, state_ref, import))]
+    #[oai(path = "/import", method = "post")]
+    async fn import_config(
+        &self,
+        Data(full_config): Data<&Arc<Configuration>>,
+        Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+        Data(state_ref): Data<&Arc<RwLock<PrinterState>>>,
+        import: YamlRequest,
+    ) -> Result<Json<Configuration>> {
+        let (YamlRequest::Yaml(PlainText(content)) | YamlRequest::Text(PlainText(content))) =
+            import;
+
+        let mut imported_config: Configuration =
+            serde_yaml::from_str(&content).map_err(BadRequest)?;
+        refuse_errors(&imported_config.validate()).map_err(BadRequest)?;
+
+        let current_config = Self::current_config(full_config)?;
+        Self::check_serial_unchanged(&current_config, &imported_config, state_ref).await?;
+
+        // The config file location and API key are never exported, so keep our
+        // own unless the import sets a new key
+        imported_config.config_file = current_config.config_file.clone();
+        if imported_config.api.api_key.is_none() {
+            imported_config.api.api_key = current_config.api.api_key.clone();
+        }
+        Configuration::overwrite_file(&imported_config)?;
+        Self::reload_printer(operation_sender, &imported_config);
+
+        Ok(Json(imported_config.redacted()))
+    }
+
+    /// Resolve each configured gcode template with representative values, to
+    /// check what would be sent without touching the hardware
+    #[instrument(ret, skip(full_config))]
+    #[oai(path = "/test_gcode", method = "post")]
+    async fn test_gcode(
+        &self,
+        Data(full_config): Data<&Arc<Configuration>>,
+    ) -> Json<Vec<ResolvedGcode>> {
+        Json(gcode::resolve_templates(full_config))
+    }
+
+    #[instrument(ret, skip(resin_profiles))]
+    #[oai(path = "/profiles", method = "get")]
+    async fn get_profiles(
+        &self,
+        Data(resin_profiles): Data<&LockedProfiles>,
+    ) -> Json<HashMap<String, ResinProfile>> {
+        Json(resin_profiles.read().await.clone())
+    }
+
+    #[instrument(ret, skip(full_config, resin_profiles))]
+    #[oai(path = "/profiles", method = "put")]
+    async fn put_profile(
+        &self,
+        Query(name): Query<String>,
+        Json(profile): Json<ResinProfile>,
+        Data(full_config): Data<&Arc<Configuration>>,
+        Data(resin_profiles): Data<&LockedProfiles>,
+    ) -> Result<Json<HashMap<String, ResinProfile>>> {
+        let mut resin_profiles = resin_profiles.write().await;
+        resin_profiles.insert(name, profile);
+
+        Self::save_profiles(full_config, &resin_profiles)?;
+        Ok(Json(resin_profiles.clone()))
+    }
+
+    #[instrument(ret, skip(full_config, resin_profiles))]
+    #[oai(path = "/profiles", method = "delete")]
+    async fn delete_profile(
+        &self,
+        Query(name): Query<String>,
+        Data(full_config): Data<&Arc<Configuration>>,
+        Data(resin_profiles): Data<&LockedProfiles>,
+    ) -> Result<Json<HashMap<String, ResinProfile>>> {
+        let mut resin_profiles = resin_profiles.write().await;
+        resin_profiles.remove(&name).ok_or(NotFound(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No resin profile named {}", name),
+        )))?;
+
+        Self::save_profiles(full_config, &resin_profiles)?;
+        Ok(Json(resin_profiles.clone()))
+    }
+
+    fn save_profiles(
+        full_config: &Configuration,
+        resin_profiles: &HashMap<String, ResinProfile>,
+    ) -> Result<()> {
+        let mut config = Self::current_config(full_config)?;
+        config.resin_profiles = resin_profiles.clone();
+        Configuration::overwrite_file(&config)?;
+        Ok(())
+    }
+
+    /// The configuration as last written to the config file, so that each
+    /// change builds on the ones before it rather than the startup config
+    pub(crate) fn current_config(full_config: &Configuration) -> Result<Configuration> {
+        match &full_config.config_file {
+            Some(config_file) => Configuration::from_file(config_file.clone())
+                .map_err(|e| InternalServerError(io::Error::other(e.to_string()))),
+            None => Ok(full_config.clone()),
+        }
+    }
+
+    /// Refuse to change the serial port mid-print, as the change couldn't
+    /// be undone if the printer were then left unreachable
+    async fn check_serial_unchanged(
+        current_config: &Configuration,
+        new_config: &Configuration,
+        state_ref: &RwLock<PrinterState>,
+    ) -> Result<()> {
+        if (new_config.printer.serial != current_config.printer.serial
+            || new_config.printer.baudrate != current_config.printer.baudrate)
+            && matches!(state_ref.read().await.status, PrinterStatus::Printing)
+        {
+            return Err(Conflict(io::Error::other(
+                "Unable to change serial or baudrate while printing",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Hand a written configuration to the printer. It's already saved, so if
+    /// the printer can't take it now it's still loaded on the next restart
+    fn reload_printer(operation_sender: &mpsc::Sender<Operation>, configuration: &Configuration) {
+        if let Err(e) = operation_sender.try_send(Operation::ReloadConfiguration {
+            configuration: Box::new(configuration.clone()),
+        }) {
+            tracing::warn!("Unable to hand the new config to the printer: {}", e);
+        }
     }
 }
@@ -0,0 +1,115 @@
+use std::{
+    io,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use poem::{
+    error::{InternalServerError, ServiceUnavailable},
+    Result,
+};
+use tokio::{
+    sync::Semaphore,
+    task::spawn_blocking,
+    time::{timeout, Duration},
+};
+
+use crate::configuration::ApiConfig;
+
+const DEFAULT_MAX_CONCURRENT_EXTRACTIONS: usize = 2;
+const DEFAULT_MAX_QUEUED_EXTRACTIONS: usize = 16;
+
+/// Bounds how many print files are opened at once to extract metadata or
+/// thumbnails, queueing a limited number of requests beyond that
+pub struct ExtractionLimiter {
+    semaphore: Semaphore,
+    pending: AtomicUsize,
+    max_pending: usize,
+}
+
+impl ExtractionLimiter {
+    pub fn new(configuration: &ApiConfig) -> ExtractionLimiter {
+        let max_concurrent = configuration
+            .max_concurrent_extractions
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_EXTRACTIONS)
+            .max(1);
+        let max_queued = configuration
+            .max_queued_extractions
+            .unwrap_or(DEFAULT_MAX_QUEUED_EXTRACTIONS);
+
+        ExtractionLimiter {
+            semaphore: Semaphore::new(max_concurrent),
+            pending: AtomicUsize::new(0),
+            max_pending: max_concurrent + max_queued,
+        }
+    }
+
+    /// Run the given blocking extraction once a slot is free, or fail immediately
+    /// if the queue is already full
+    pub async fn run<F, R>(&self, extraction: F) -> Result<R>
+    where
+        F: FnOnce() -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.run_with_timeout(None, extraction).await
+    }
+
+    /// Run the given blocking extraction as with run, within an optional time
+    /// limit
+    pub async fn run_with_timeout<F, R>(
+        &self,
+        time_limit: Option<Duration>,
+        extraction: F,
+    ) -> Result<R>
+    where
+        F: FnOnce() -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        if self.pending.fetch_add(1, Ordering::SeqCst) >= self.max_pending {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            tracing::warn!("Extraction queue full, rejecting request");
+            return Err(ServiceUnavailable(io::Error::other(
+                "Too many pending file extractions",
+            )));
+        }
+        // Released however the request ends, including when it's dropped while
+        // still queued, such as by a client cancelling a thumbnail request
+        let _pending = PendingGuard(&self.pending);
+
+        match self.semaphore.acquire().await {
+            Ok(_permit) => run_blocking(time_limit, extraction).await,
+            Err(err) => Err(InternalServerError(err)),
+        }
+    }
+}
+
+/// Counts a request as pending until it's dropped
+struct PendingGuard<'a>(&'a AtomicUsize);
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Run blocking filesystem work on its own thread, giving up with a 503 if it
+/// hasn't finished within the time limit, such as when reading from a network
+/// share which has gone away. The stuck thread can't be stopped, but it no
+/// longer holds up the request
+pub async fn run_blocking<F, R>(time_limit: Option<Duration>, work: F) -> Result<R>
+where
+    F: FnOnce() -> Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    let task = spawn_blocking(work);
+    let joined = match time_limit {
+        Some(time_limit) => timeout(time_limit, task).await.map_err(|_| {
+            ServiceUnavailable(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out waiting for the filesystem",
+            ))
+        })?,
+        None => task.await,
+    };
+
+    joined.map_err(InternalServerError)?
+}
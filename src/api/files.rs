@@ -1,37 +1,47 @@
 use std::{
-    ffi::OsStr,
+    collections::BTreeMap,
     fs::File,
     io::{Error, ErrorKind, Read, Write},
     path::{Path, PathBuf},
     sync::Arc,
 };
 
+use futures::future::join_all;
 use glob::glob;
 use itertools::Itertools;
 use poem::{
     error::{
-        BadRequest, GetDataError, InternalServerError, MethodNotAllowedError, NotFound,
-        NotImplemented, Unauthorized,
+        BadRequest, Conflict, GetDataError, InsufficientStorage, InternalServerError,
+        MethodNotAllowedError, NotFound, ServiceUnavailable, Unauthorized, UnprocessableEntity,
     },
     web::Data,
     Result,
 };
 use poem_openapi::{
     param::Query,
-    payload::{Attachment, Json},
+    payload::{Attachment, Binary, Json},
     types::multipart::Upload,
-    Multipart, Object, OpenApi,
+    ApiResponse, Enum, Multipart, Object, OpenApi,
 };
 use serde::{Deserialize, Serialize};
-use tokio::fs;
+use tokio::{fs, time::Duration};
 use tracing::instrument;
 
 use crate::{
+    api::{
+        config::ConfigApi,
+        extraction::{run_blocking, ExtractionLimiter},
+        layer_cache::LayerCache,
+        Api,
+    },
     api_objects::{
-        FileMetadata, LocationCategory, PrintMetadata, ThumbnailSize, UpdatePrintUserMetadata,
+        ChecksumAlgorithm, FileChecksum, FileMetadata, FileSortField, LocationCategory,
+        PrintMetadata, SortOrder, StorageInfo, ThumbnailSize, UpdatePrintUserMetadata,
+        UploadDirectoryStatus,
     },
-    configuration::{ApiConfig, Configuration},
-    printfile::PrintFile,
+    checksum,
+    configuration::{ApiConfig, Configuration, LockedProfiles, PrintUploadDirectory},
+    printfile::{is_print_file, open_print_file, PrintFile},
     sl1::Sl1,
 };
 
@@ -41,6 +51,9 @@ pub struct FilesApi;
 #[derive(Debug, Multipart)]
 struct UploadPayload {
     file: Upload,
+    /// Optional digest to verify the upload against, as `sha256:<hex>` or
+    /// `md5:<hex>`. Bare digests are matched to an algorithm by length
+    checksum: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Object)]
@@ -48,54 +61,541 @@ pub struct FilesResponse {
     pub files: Vec<PrintMetadata>,
     pub dirs: Vec<FileMetadata>,
     pub next_index: Option<usize>,
+    /// Total size in bytes of everything under each listed directory, by path,
+    /// when requested
+    pub dir_sizes: Option<BTreeMap<String, u64>>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Enum)]
+#[serde(rename_all = "lowercase")]
+#[oai(rename_all = "lowercase")]
+pub enum FileOperationKind {
+    Move,
+    Copy,
+    Delete,
+}
+
+/// One step of a batch file operation, with paths relative to the upload
+/// directory
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct FileOperation {
+    pub op: FileOperationKind,
+    pub src: String,
+    /// Where to move or copy to, unused when deleting
+    pub dst: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct FailedFileOperation {
+    pub operation: FileOperation,
+    pub error: String,
+}
+
+/// Outcome of a batch file operation, which stops at the first failure
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct BatchResponse {
+    pub completed: Vec<FileOperation>,
+    pub failed: Option<FailedFileOperation>,
+}
+/// PNG image response, served either inline for direct display or as an attachment
+#[derive(Debug, ApiResponse)]
+pub enum PngResponse {
+    #[oai(status = 200, content_type = "image/png")]
+    Png(
+        Binary<Vec<u8>>,
+        #[oai(header = "Content-Disposition")] String,
+    ),
+}
+
+impl PngResponse {
+    pub fn new(data: Vec<u8>, file_name: &str, inline: bool) -> PngResponse {
+        let disposition = if inline { "inline" } else { "attachment" };
+        PngResponse::Png(
+            Binary(data),
+            format!("{disposition}; filename=\"{file_name}\""),
+        )
+    }
+}
+
+/// How a file listing is filtered and ordered
+#[derive(Clone, Debug)]
+struct ListingOptions {
+    sort_by: FileSortField,
+    order: SortOrder,
+    /// Case-insensitive substring the name must contain
+    filter: Option<String>,
+    favorites_only: bool,
+    /// Tag files must carry, ignoring case
+    tag: Option<String>,
 }
+
+impl ListingOptions {
+    /// Filter and sort a listing's entries, each flagged whether it's a
+    /// directory. Directories are kept ahead of files, and sorted separately
+    fn apply(&self, entries: Vec<(bool, FileMetadata)>) -> Vec<(bool, FileMetadata)> {
+        let filter = self.filter.as_ref().map(|filter| filter.to_lowercase());
+
+        let mut entries = entries
+            .into_iter()
+            .filter(|(_, file_data)| {
+                filter
+                    .as_ref()
+                    .is_none_or(|filter| file_data.name.to_lowercase().contains(filter))
+            })
+            // Only files may be favorites
+            .filter(|(is_dir, file_data)| {
+                !self.favorites_only || (!is_dir && is_favorite(file_data))
+            })
+            .filter(|(is_dir, file_data)| {
+                self.tag
+                    .as_ref()
+                    .is_none_or(|tag| !is_dir && has_tag(file_data, tag))
+            })
+            .collect_vec();
+
+        entries.sort_by(|(a_is_dir, a), (b_is_dir, b)| {
+            let ordering = match self.sort_by {
+                FileSortField::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+                FileSortField::Modified => a.last_modified.cmp(&b.last_modified),
+                FileSortField::Size => a.file_size.cmp(&b.file_size),
+            }
+            .then_with(|| a.name.cmp(&b.name));
+            let ordering = match self.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            };
+            b_is_dir.cmp(a_is_dir).then(ordering)
+        });
+        entries
+    }
+}
+
+fn is_favorite(file_data: &FileMetadata) -> bool {
+    file_data
+        .open_file()
+        .is_ok_and(|file| Sl1::get_favorite(&file))
+}
+
+fn has_tag(file_data: &FileMetadata, tag: &str) -> bool {
+    file_data.open_file().is_ok_and(|file| {
+        Sl1::get_tags(&file)
+            .iter()
+            .any(|file_tag| file_tag.eq_ignore_ascii_case(tag.trim()))
+    })
+}
+
 const DEFAULT_PAGE_INDEX: usize = 0;
 const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Deepest directory nesting below the upload directory searched for files
+const MAX_SEARCH_DEPTH: usize = 8;
+
+/// Most files returned by a search
+const MAX_SEARCH_RESULTS: usize = 100;
+
+/// How long a network share may take to answer before it's treated as
+/// unavailable
+const NETWORK_FS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Space which must be left free after an upload, so the SD card is never
+/// filled completely
+const UPLOAD_FREE_SPACE_MARGIN: u64 = 64 * 1024 * 1024;
 #[OpenApi]
 impl FilesApi {
     #[instrument(ret, skip(configuration))]
     #[oai(path = "/files", method = "post")]
     async fn upload_file(
         &self,
+        Query(subdirectory): Query<Option<String>>,
+        Query(directory_label): Query<Option<String>>,
         file_upload: UploadPayload,
         Data(configuration): Data<&Arc<Configuration>>,
     ) -> Result<()> {
         tracing::info!("Uploading file");
+        let upload_directory =
+            Self::available_directory(&configuration.api, directory_label.as_deref()).await?;
 
+        // File names may carry a relative path, when uploading a whole directory
         let file_name = file_upload
             .file
             .file_name()
             .map(|s| s.to_string().clone())
             .ok_or(BadRequest(GetDataError("Could not get file name")))?;
 
+        let relative_path = Path::new(subdirectory.as_deref().unwrap_or("")).join(file_name);
+        let file_path = upload_directory
+            .resolve(&relative_path)
+            .map_err(BadRequest)?;
+
+        let expected_checksum = file_upload
+            .checksum
+            .map(|checksum| {
+                checksum::parse(&checksum)
+                    .ok_or(BadRequest(GetDataError("Unable to parse checksum")))
+            })
+            .transpose()?;
+
         let bytes = file_upload.file.into_vec().await.map_err(BadRequest)?;
+        Self::check_free_space(&upload_directory.path, bytes.len() as u64)?;
 
-        let mut f = File::create(format!("{}/{file_name}", configuration.api.upload_path))
-            .map_err(InternalServerError)?;
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .map_err(InternalServerError)?;
+        }
+
+        let mut f = File::create(&file_path).map_err(InternalServerError)?;
         f.write_all(bytes.as_slice()).map_err(InternalServerError)?;
 
+        if let Some((algorithm, expected)) = expected_checksum {
+            let written = File::open(&file_path).map_err(InternalServerError)?;
+            let actual = checksum::compute(&algorithm, written).map_err(InternalServerError)?;
+
+            if actual != expected {
+                tracing::warn!(
+                    "Upload checksum mismatch for {:?}, expected {} but got {}",
+                    file_path,
+                    expected,
+                    actual
+                );
+                fs::remove_file(&file_path)
+                    .await
+                    .map_err(InternalServerError)?;
+                return Err(UnprocessableEntity(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Checksum mismatch, expected {expected} but got {actual}"),
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[instrument(ret, skip(configuration))]
+    #[oai(path = "/files/storage", method = "get")]
+    async fn get_storage(
+        &self,
+        Query(directory_label): Query<Option<String>>,
+        Data(configuration): Data<&Arc<Configuration>>,
+    ) -> Result<Json<StorageInfo>> {
+        let upload_path = Self::available_directory(&configuration.api, directory_label.as_deref())
+            .await?
+            .path;
+
+        Ok(Json(
+            StorageInfo::from_path(Path::new(&upload_path)).map_err(InternalServerError)?,
+        ))
+    }
+
+    #[instrument(ret, skip(configuration))]
+    #[oai(path = "/files/directories", method = "get")]
+    async fn get_upload_directories(
+        &self,
+        Data(configuration): Data<&Arc<Configuration>>,
+    ) -> Result<Json<Vec<UploadDirectoryStatus>>> {
+        let statuses =
+            configuration
+                .api
+                .print_upload_dirs()
+                .into_iter()
+                .map(|directory| async move {
+                    UploadDirectoryStatus {
+                        available: Self::check_available(&directory).await.is_ok(),
+                        kind: directory.kind.unwrap_or_default(),
+                        label: directory.label,
+                        path: directory.path,
+                    }
+                });
+
+        Ok(Json(join_all(statuses).await))
+    }
+
+    /// The path of the upload directory with the given label, or of the
+    /// default upload_path when no label is given
+    fn upload_directory(
+        configuration: &ApiConfig,
+        directory_label: Option<&str>,
+    ) -> Result<PrintUploadDirectory> {
+        configuration
+            .get_print_upload_dir(directory_label)
+            .ok_or(NotFound(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "No upload directory labelled {}",
+                    directory_label.unwrap_or_default()
+                ),
+            )))
+    }
+
+    /// The upload directory with the given label, once it's known to be
+    /// reachable
+    async fn available_directory(
+        configuration: &ApiConfig,
+        directory_label: Option<&str>,
+    ) -> Result<PrintUploadDirectory> {
+        let directory = Self::upload_directory(configuration, directory_label)?;
+        Self::check_available(&directory).await?;
+        Ok(directory)
+    }
+
+    /// Make sure a network share is mounted and answering, so a dead mount
+    /// fails fast with a 503 rather than hanging the request. Local
+    /// directories are taken as given
+    async fn check_available(directory: &PrintUploadDirectory) -> Result<()> {
+        if !directory.is_network() {
+            return Ok(());
+        }
+
+        let path = PathBuf::from(&directory.path);
+        run_blocking(Some(NETWORK_FS_TIMEOUT), move || {
+            std::fs::read_dir(path).map_err(ServiceUnavailable)
+        })
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            tracing::warn!(
+                "Upload directory {} at {} is unavailable: {}",
+                directory.label,
+                directory.path,
+                e
+            );
+            ServiceUnavailable(Error::other(format!(
+                "Upload directory {} is unavailable: {}",
+                directory.label, e
+            )))
+        })
+    }
+
+    /// How long filesystem calls on the directory holding a file may take,
+    /// after checking it's reachable
+    pub(crate) async fn filesystem_time_limit(
+        configuration: &ApiConfig,
+        location: &LocationCategory,
+        directory_label: Option<&str>,
+    ) -> Result<Option<Duration>> {
+        match location {
+            LocationCategory::Usb => Ok(None),
+            LocationCategory::Local => {
+                let directory = Self::available_directory(configuration, directory_label).await?;
+                Ok(directory.is_network().then_some(NETWORK_FS_TIMEOUT))
+            }
+        }
+    }
+
+    /// Refuse an upload which would leave less than the safety margin free,
+    /// rather than writing a truncated file
+    fn check_free_space(upload_path: &str, upload_size: u64) -> Result<()> {
+        let storage = match StorageInfo::from_path(Path::new(upload_path)) {
+            Ok(storage) => storage,
+            Err(e) => {
+                tracing::warn!("Unable to check free space in {}: {}", upload_path, e);
+                return Ok(());
+            }
+        };
+
+        if upload_size.saturating_add(UPLOAD_FREE_SPACE_MARGIN) > storage.free_bytes {
+            return Err(InsufficientStorage(Error::new(
+                ErrorKind::StorageFull,
+                format!(
+                    "Not enough free space for a {} byte upload, {} bytes are free",
+                    upload_size, storage.free_bytes
+                ),
+            )));
+        }
         Ok(())
     }
+
+    #[instrument(ret, skip(configuration))]
+    #[oai(path = "/file/checksum", method = "get")]
+    async fn get_file_checksum(
+        &self,
+        Query(file_path): Query<String>,
+        Query(location): Query<Option<LocationCategory>>,
+        Query(algorithm): Query<Option<ChecksumAlgorithm>>,
+        Query(directory_label): Query<Option<String>>,
+        Data(configuration): Data<&Arc<Configuration>>,
+    ) -> Result<Json<FileChecksum>> {
+        let location = location.unwrap_or(LocationCategory::Local);
+        let algorithm = algorithm.unwrap_or(ChecksumAlgorithm::Sha256);
+        let time_limit =
+            Self::filesystem_time_limit(&configuration.api, &location, directory_label.as_deref())
+                .await?;
+
+        let full_file_path = Self::get_file_path(
+            &configuration.api,
+            &file_path,
+            &location,
+            directory_label.as_deref(),
+        )?;
+
+        let checksum = run_blocking(time_limit, {
+            let algorithm = algorithm.clone();
+            move || {
+                let file = File::open(full_file_path).map_err(NotFound)?;
+                checksum::compute(&algorithm, file).map_err(InternalServerError)
+            }
+        })
+        .await?;
+
+        Ok(Json(FileChecksum {
+            algorithm,
+            checksum,
+        }))
+    }
+
     #[instrument(ret, skip(configuration))]
+    #[oai(path = "/files/batch", method = "post")]
+    async fn batch_file_operations(
+        &self,
+        Query(directory_label): Query<Option<String>>,
+        Json(operations): Json<Vec<FileOperation>>,
+        Data(configuration): Data<&Arc<Configuration>>,
+    ) -> Result<Json<BatchResponse>> {
+        let directory =
+            Self::available_directory(&configuration.api, directory_label.as_deref()).await?;
+        let time_limit = directory.is_network().then_some(NETWORK_FS_TIMEOUT);
+
+        Ok(Json(
+            run_blocking(time_limit, move || {
+                Ok(Self::_run_file_operations(&directory, operations))
+            })
+            .await?,
+        ))
+    }
+
+    /// Run each operation in turn, stopping at the first which fails so later
+    /// steps never act on the outcome of one which didn't happen
+    fn _run_file_operations(
+        directory: &PrintUploadDirectory,
+        operations: Vec<FileOperation>,
+    ) -> BatchResponse {
+        let mut completed = Vec::new();
+
+        for operation in operations {
+            tracing::info!("Running file operation {:?}", operation);
+            if let Err(e) = Self::_run_file_operation(directory, &operation) {
+                tracing::warn!("File operation {:?} failed: {}", operation, e);
+                return BatchResponse {
+                    completed,
+                    failed: Some(FailedFileOperation {
+                        operation,
+                        error: e.to_string(),
+                    }),
+                };
+            }
+            completed.push(operation);
+        }
+
+        BatchResponse {
+            completed,
+            failed: None,
+        }
+    }
+
+    fn _run_file_operation(
+        directory: &PrintUploadDirectory,
+        operation: &FileOperation,
+    ) -> std::result::Result<(), Error> {
+        let source = Path::new(&operation.src);
+        let destination = || {
+            operation.dst.as_deref().map(Path::new).ok_or(Error::new(
+                ErrorKind::InvalidInput,
+                "dst is required to move or copy",
+            ))
+        };
+
+        match operation.op {
+            FileOperationKind::Move => directory.move_file(source, destination()?),
+            FileOperationKind::Copy => directory.copy_file(source, destination()?),
+            FileOperationKind::Delete => {
+                directory.resolve(source)?;
+                FileMetadata::from_path(&operation.src, &directory.path, LocationCategory::Local)?
+                    .delete_file()
+            }
+        }
+    }
+
+    #[instrument(ret, skip(configuration))]
+    #[oai(path = "/files/mkdir", method = "post")]
+    async fn make_directory(
+        &self,
+        Query(path): Query<String>,
+        Query(directory_label): Query<Option<String>>,
+        Data(configuration): Data<&Arc<Configuration>>,
+    ) -> Result<Json<FileMetadata>> {
+        tracing::info!("Creating directory {:?}", path);
+
+        let directory = Self::available_directory(&configuration.api, directory_label.as_deref())
+            .await?
+            .resolve(Path::new(&path))
+            .map_err(BadRequest)?;
+
+        fs::create_dir_all(directory)
+            .await
+            .map_err(InternalServerError)?;
+
+        Ok(Json(Self::_get_filedata(
+            &path,
+            LocationCategory::Local,
+            directory_label.as_deref(),
+            &configuration.api,
+        )?))
+    }
+
+    #[instrument(ret, skip(configuration, extraction_limiter))]
     #[oai(path = "/files", method = "get")]
+    #[allow(clippy::too_many_arguments)]
     async fn get_files(
         &self,
         Query(subdirectory): Query<Option<String>>,
         Query(location): Query<Option<LocationCategory>>,
         Query(page_index): Query<Option<usize>>,
         Query(page_size): Query<Option<usize>>,
+        Query(dir_sizes): Query<Option<bool>>,
+        Query(sort_by): Query<Option<FileSortField>>,
+        Query(order): Query<Option<SortOrder>>,
+        Query(filter): Query<Option<String>>,
+        Query(favorites_only): Query<Option<bool>>,
+        Query(tag): Query<Option<String>>,
+        Query(directory_label): Query<Option<String>>,
         Data(configuration): Data<&Arc<Configuration>>,
+        Data(extraction_limiter): Data<&Arc<ExtractionLimiter>>,
     ) -> Result<Json<FilesResponse>> {
         let location = location.unwrap_or(LocationCategory::Local);
         let page_index = page_index.unwrap_or(DEFAULT_PAGE_INDEX);
         let page_size = page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+        let dir_sizes = dir_sizes.unwrap_or(false);
+        let options = ListingOptions {
+            sort_by: sort_by.unwrap_or(FileSortField::Name),
+            order: order.unwrap_or(SortOrder::Asc),
+            filter,
+            favorites_only: favorites_only.unwrap_or(false),
+            tag,
+        };
+        let api_config = configuration.api.clone();
 
         match location {
             LocationCategory::Local => {
-                Self::_get_local_files(subdirectory, page_index, page_size, &configuration.api)
+                let directory =
+                    Self::available_directory(&api_config, directory_label.as_deref()).await?;
+                let time_limit = directory.is_network().then_some(NETWORK_FS_TIMEOUT);
+                extraction_limiter
+                    .run_with_timeout(time_limit, move || {
+                        Self::_get_local_files(
+                            subdirectory,
+                            page_index,
+                            page_size,
+                            dir_sizes,
+                            &options,
+                            &directory.path,
+                        )
+                    })
+                    .await
             }
             LocationCategory::Usb => {
-                Self::_get_usb_files(page_index, page_size, &configuration.api)
+                extraction_limiter
+                    .run(move || Self::_get_usb_files(page_index, page_size, &options, &api_config))
+                    .await
             }
         }
     }
@@ -104,7 +604,9 @@ impl FilesApi {
         subdirectory: Option<String>,
         page_index: usize,
         page_size: usize,
-        configuration: &ApiConfig,
+        dir_sizes: bool,
+        options: &ListingOptions,
+        upload_string: &str,
     ) -> Result<Json<FilesResponse>> {
         let directory = subdirectory.unwrap_or("".to_string());
 
@@ -112,14 +614,12 @@ impl FilesApi {
             return Err(Unauthorized(MethodNotAllowedError));
         }
 
-        let upload_string = &configuration.upload_path;
-
-        let upload_path = Path::new(upload_string.as_str());
+        let upload_path = Path::new(upload_string);
         let full_path = upload_path.join(directory.as_str());
 
         let read_dir = full_path.read_dir();
 
-        let files_vec = read_dir
+        let entries = read_dir
             .map_err(InternalServerError)?
             .flatten()
             .filter_map(|f| {
@@ -128,83 +628,233 @@ impl FilesApi {
                     .map(|path_ref| path_ref.to_owned())
                     .ok()
             })
-            // TODO add sorting here
-            .filter(|f| f.is_dir() || f.extension().and_then(OsStr::to_str).eq(&Some("sl1")));
+            .filter(|f| upload_path.join(f).is_dir() || is_print_file(f))
+            .filter_map(|f| {
+                let is_dir = upload_path.join(&f).is_dir();
+                FileMetadata::from_path(f.to_str()?, upload_string, LocationCategory::Local)
+                    .ok()
+                    .map(|file_data| (is_dir, file_data))
+            })
+            .collect_vec();
 
-        let chunks = files_vec.chunks(page_size);
+        // Sorted before paging, so pages stay stable
+        let chunks = options.apply(entries).into_iter().chunks(page_size);
 
         let mut chunks_iterator = chunks.into_iter();
 
-        let paths = chunks_iterator
+        let (dirs, files): (Vec<_>, Vec<_>) = chunks_iterator
             .nth(page_index)
-            .map_or(Vec::new(), |dirs| dirs.collect_vec());
+            .map_or(Vec::new(), |entries| entries.collect_vec())
+            .into_iter()
+            .partition(|(is_dir, _)| *is_dir);
 
-        let dirs = paths
-            .iter()
-            .filter(|f| f.is_dir())
-            .filter_map(|f| f.as_os_str().to_str())
-            .flat_map(|f| Self::_get_filedata(f, LocationCategory::Local, configuration).ok())
+        let dirs = dirs
+            .into_iter()
+            .map(|(_, file_data)| file_data)
             .collect_vec();
-        let files = paths
-            .iter()
-            .filter(|f| !f.is_dir())
-            .filter_map(|f| f.as_os_str().to_str())
-            .flat_map(|f| Self::_get_print_metadata(f, LocationCategory::Local, configuration).ok())
+        let files = files
+            .into_iter()
+            .flat_map(|(_, file_data)| {
+                open_print_file(file_data)
+                    .map(|file| file.get_metadata())
+                    .ok()
+            })
             .collect_vec();
 
-        let next_index = Some(page_index + 1).filter(|_| chunks_iterator.next().is_some());
+        let next_index = chunks_iterator.next().is_some().then_some(page_index + 1);
+
+        let dir_sizes = dir_sizes.then(|| {
+            dirs.iter()
+                .map(|dir| (dir.path.clone(), directory_size(&dir.get_full_path())))
+                .collect()
+        });
 
         Ok(Json(FilesResponse {
             files,
             dirs,
             next_index,
+            dir_sizes,
         }))
     }
 
     fn _get_usb_files(
-        _page_index: usize,
-        _page_size: usize,
-        _configuration: &ApiConfig,
+        page_index: usize,
+        page_size: usize,
+        options: &ListingOptions,
+        configuration: &ApiConfig,
     ) -> Result<Json<FilesResponse>> {
-        Err(NotImplemented(MethodNotAllowedError))
+        let entries = Self::usb_files(&configuration.usb_glob)?
+            .iter()
+            .filter_map(|path| path.to_str())
+            .flat_map(|path| FileMetadata::from_path(path, "", LocationCategory::Usb).ok())
+            .map(|file_data| (false, file_data))
+            .collect_vec();
+
+        let chunks = options.apply(entries).into_iter().chunks(page_size);
+        let mut chunks_iterator = chunks.into_iter();
+
+        let files = chunks_iterator
+            .nth(page_index)
+            .map_or(Vec::new(), |entries| entries.collect_vec())
+            .into_iter()
+            .flat_map(|(_, file_data)| {
+                open_print_file(file_data)
+                    .map(|file| file.get_metadata())
+                    .ok()
+            })
+            .collect_vec();
+
+        let next_index = chunks_iterator.next().is_some().then_some(page_index + 1);
+
+        Ok(Json(FilesResponse {
+            files,
+            dirs: Vec::new(),
+            next_index,
+            dir_sizes: None,
+        }))
+    }
+
+    #[instrument(ret, skip(configuration, extraction_limiter))]
+    #[oai(path = "/files/search", method = "get")]
+    async fn search_files(
+        &self,
+        Query(q): Query<String>,
+        Query(limit): Query<Option<usize>>,
+        Data(configuration): Data<&Arc<Configuration>>,
+        Data(extraction_limiter): Data<&Arc<ExtractionLimiter>>,
+    ) -> Result<Json<Vec<PrintMetadata>>> {
+        if q.trim().is_empty() {
+            return Err(BadRequest(Error::new(
+                ErrorKind::InvalidInput,
+                "Search query must not be empty",
+            )));
+        }
+        let limit = limit.unwrap_or(MAX_SEARCH_RESULTS).min(MAX_SEARCH_RESULTS);
+        let api_config = configuration.api.clone();
+
+        // Shares which are down are left out, rather than failing the search
+        let mut directories = Vec::new();
+        for directory in api_config.print_upload_dirs() {
+            if Self::check_available(&directory).await.is_ok() {
+                directories.push(directory);
+            }
+        }
+
+        Ok(Json(
+            extraction_limiter
+                .run(move || Ok(Self::_search_files(&q, limit, &directories, &api_config)))
+                .await?,
+        ))
+    }
+
+    /// Find print files whose names contain the query, in any directory under
+    /// each upload directory and then on USB drives
+    fn _search_files(
+        query: &str,
+        limit: usize,
+        directories: &[PrintUploadDirectory],
+        configuration: &ApiConfig,
+    ) -> Vec<PrintMetadata> {
+        let query = query.to_lowercase();
+
+        let mut local_files = Vec::new();
+        for directory in directories {
+            let upload_path = Path::new(&directory.path);
+            let mut local_paths = Vec::new();
+            let remaining = limit.saturating_sub(local_files.len());
+            search_directory(
+                upload_path,
+                upload_path,
+                0,
+                &query,
+                remaining,
+                &mut local_paths,
+            );
 
-        /*
-        poem::web::Json(glob(&configuration.usb_glob)
-            .expect("Failed to read glob pattern")
-            .map(|result| result.expect("Error reading path"))
-            .map(|path| path.into_os_string().into_string().expect("Error parsing path"))
+            local_files.extend(local_paths.iter().filter_map(|path| {
+                FileMetadata::from_path(path.to_str()?, &directory.path, LocationCategory::Local)
+                    .ok()
+            }));
+        }
+
+        let usb_files = Self::usb_files(&configuration.usb_glob)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|path| matches_search(path, &query))
+            .filter_map(|path| {
+                FileMetadata::from_path(path.to_str()?, "", LocationCategory::Usb).ok()
+            });
+
+        local_files
+            .into_iter()
+            .chain(usb_files)
+            .take(limit)
+            .flat_map(|file_data| {
+                open_print_file(file_data)
+                    .map(|file| file.get_metadata())
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Every print file on mounted USB drives matched by the usb_glob
+    fn usb_files(usb_glob: &str) -> Result<Vec<PathBuf>> {
+        if usb_glob.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(glob(usb_glob)
+            .map_err(InternalServerError)?
+            .filter_map(|path| path.ok())
+            .filter(|path| path.is_file() && is_print_file(path))
             .collect_vec())
-        */
     }
 
     fn get_file_path(
         configuration: &ApiConfig,
         file_path: &str,
         location: &LocationCategory,
+        directory_label: Option<&str>,
     ) -> Result<PathBuf> {
         tracing::info!("Getting full file path {:?}, {:?}", location, file_path);
 
         match location {
             LocationCategory::Usb => Self::get_usb_file_path(&configuration.usb_glob, file_path),
             LocationCategory::Local => {
-                Self::get_local_file_path(&configuration.upload_path, file_path)
+                let directory = Self::upload_directory(configuration, directory_label)?;
+                Self::get_local_file_path(&directory.path, file_path)
             }
         }
     }
 
-    // Since USB paths are specified as a glob, find all and filter to file_name
-    fn get_usb_file_path(usb_glob: &str, file_name: &str) -> Result<PathBuf> {
-        let paths = glob(usb_glob).map_err(InternalServerError)?;
+    // Since USB paths are specified as a glob, find all and filter to the
+    // requested file. Files are listed by their full path, but a bare file name
+    // is accepted as long as only one drive has a file by that name
+    fn get_usb_file_path(usb_glob: &str, file_path: &str) -> Result<PathBuf> {
+        let paths = Self::usb_files(usb_glob)?;
 
-        let path_buf = paths
-            .filter_map(|path| path.ok())
-            .find(|path| path.ends_with(file_name))
-            .ok_or(InternalServerError(Error::new(
+        if let Some(path) = paths
+            .iter()
+            .find(|path| path.as_path() == Path::new(file_path))
+        {
+            return Ok(path.clone());
+        }
+
+        let mut matching = paths.into_iter().filter(|path| path.ends_with(file_path));
+        match (matching.next(), matching.next()) {
+            (Some(path), None) => Ok(path),
+            (Some(_), Some(_)) => Err(Conflict(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} was found on more than one USB drive, use its full path",
+                    file_path
+                ),
+            ))),
+            (None, _) => Err(NotFound(Error::new(
                 ErrorKind::NotFound,
                 "Unable to find USB file",
-            )))?;
-
-        Ok(path_buf)
+            ))),
+        }
     }
 
     // For Local files, look directly for specific file
@@ -219,26 +869,42 @@ impl FilesApi {
             )))
     }
 
-    fn _get_filedata(
+    pub(crate) fn _get_filedata(
         file_path: &str,
         location: LocationCategory,
+        directory_label: Option<&str>,
         configuration: &ApiConfig,
     ) -> Result<FileMetadata> {
         tracing::info!("Getting file data");
 
-        // TODO handle USB _get_filedata
-        FileMetadata::from_path(file_path, &configuration.upload_path, location).map_err(NotFound)
+        match location {
+            LocationCategory::Local => {
+                let directory = Self::upload_directory(configuration, directory_label)?;
+                FileMetadata::from_path(file_path, &directory.path, location).map_err(NotFound)
+            }
+            // USB files are kept by their full path, with no parent path
+            LocationCategory::Usb => {
+                let path = Self::get_usb_file_path(&configuration.usb_glob, file_path)?;
+                let path = path.to_str().ok_or(InternalServerError(Error::new(
+                    ErrorKind::InvalidData,
+                    "unable to parse file path",
+                )))?;
+
+                FileMetadata::from_path(path, "", location).map_err(NotFound)
+            }
+        }
     }
 
-    fn _get_print_metadata(
+    pub(crate) fn _get_print_metadata(
         file_path: &str,
         location: LocationCategory,
+        directory_label: Option<&str>,
         configuration: &ApiConfig,
     ) -> Result<PrintMetadata> {
-        let file_data = Self::_get_filedata(file_path, location, configuration)?;
+        let file_data = Self::_get_filedata(file_path, location, directory_label, configuration)?;
         tracing::info!("Extracting print metadata");
 
-        Ok(Sl1::from_file(file_data).map_err(NotFound)?.get_metadata())
+        Ok(open_print_file(file_data).map_err(NotFound)?.get_metadata())
     }
     #[instrument(ret, skip(configuration))]
     #[oai(path = "/file", method = "get")]
@@ -246,13 +912,22 @@ impl FilesApi {
         &self,
         Query(file_path): Query<String>,
         Query(location): Query<Option<LocationCategory>>,
+        Query(directory_label): Query<Option<String>>,
         Data(configuration): Data<&Arc<Configuration>>,
     ) -> Result<Attachment<Vec<u8>>> {
         let location = location.unwrap_or(LocationCategory::Local);
 
         tracing::info!("Getting file {:?} in {:?}", file_path, location);
+        let time_limit =
+            Self::filesystem_time_limit(&configuration.api, &location, directory_label.as_deref())
+                .await?;
 
-        let full_file_path = Self::get_file_path(&configuration.api, &file_path, &location)?;
+        let full_file_path = Self::get_file_path(
+            &configuration.api,
+            &file_path,
+            &location,
+            directory_label.as_deref(),
+        )?;
 
         let file_name = full_file_path
             .file_name()
@@ -260,32 +935,84 @@ impl FilesApi {
             .ok_or(InternalServerError(Error::new(
                 ErrorKind::NotFound,
                 "unable to parse file path",
-            )))?;
+            )))?
+            .to_owned();
 
-        let mut open_file = File::open(full_file_path.clone()).map_err(InternalServerError)?;
+        let data = run_blocking(time_limit, move || {
+            let mut open_file = File::open(full_file_path).map_err(InternalServerError)?;
 
-        let mut data: Vec<u8> = vec![];
-        open_file
-            .read_to_end(&mut data)
-            .map_err(InternalServerError)?;
+            let mut data: Vec<u8> = vec![];
+            open_file
+                .read_to_end(&mut data)
+                .map_err(InternalServerError)?;
+            Ok(data)
+        })
+        .await?;
 
         Ok(Attachment::new(data).filename(file_name))
     }
-    #[instrument(ret, skip(configuration))]
+    /// Describe a print file. With recompute, estimated_print_time is filled
+    /// in from the current config and the given resin profile, or otherwise
+    /// the one mapped to the file's directory
+    #[instrument(ret, skip(configuration, extraction_limiter, resin_profiles))]
     #[oai(path = "/file/metadata", method = "get")]
+    #[allow(clippy::too_many_arguments)]
     async fn get_file_metadata(
         &self,
         Query(file_path): Query<String>,
         Query(location): Query<Option<LocationCategory>>,
+        Query(directory_label): Query<Option<String>>,
+        Query(recompute): Query<Option<bool>>,
+        Query(profile): Query<Option<String>>,
         Data(configuration): Data<&Arc<Configuration>>,
+        Data(extraction_limiter): Data<&Arc<ExtractionLimiter>>,
+        Data(resin_profiles): Data<&LockedProfiles>,
     ) -> Result<Json<PrintMetadata>> {
         let location = location.unwrap_or(LocationCategory::Local);
+        let time_limit =
+            Self::filesystem_time_limit(&configuration.api, &location, directory_label.as_deref())
+                .await?;
+        let api_config = configuration.api.clone();
 
-        Ok(Json(Self::_get_print_metadata(
+        if !recompute.unwrap_or(false) {
+            return Ok(Json(
+                extraction_limiter
+                    .run_with_timeout(time_limit, move || {
+                        Self::_get_print_metadata(
+                            &file_path,
+                            location,
+                            directory_label.as_deref(),
+                            &api_config,
+                        )
+                    })
+                    .await?,
+            ));
+        }
+
+        let file_data = Self::_get_filedata(
             &file_path,
             location,
-            &configuration.api,
-        )?))
+            directory_label.as_deref(),
+            &api_config,
+        )?;
+        let current_config = ConfigApi::current_config(configuration)?;
+        let profile =
+            Api::_resolve_profile(profile, &file_data, &current_config, resin_profiles).await?;
+
+        Ok(Json(
+            extraction_limiter
+                .run_with_timeout(time_limit, move || {
+                    tracing::info!("Recomputing print time");
+                    let file = open_print_file(file_data).map_err(NotFound)?;
+                    let settings = file.print_settings(profile.as_ref(), &current_config.printer);
+
+                    let mut metadata = file.get_metadata();
+                    metadata.estimated_print_time =
+                        Some(file.recompute_print_time(&settings, &current_config.printer));
+                    Ok(metadata)
+                })
+                .await?,
+        ))
     }
 
     #[instrument(ret, skip(configuration))]
@@ -294,6 +1021,7 @@ impl FilesApi {
         &self,
         Query(file_path): Query<String>,
         Query(location): Query<Option<LocationCategory>>,
+        Query(directory_label): Query<Option<String>>,
         Json(patch_metadata): Json<UpdatePrintUserMetadata>,
         Data(configuration): Data<&Arc<Configuration>>,
     ) -> Result<Json<PrintMetadata>> {
@@ -304,41 +1032,125 @@ impl FilesApi {
             file_path,
             location
         );
+        let time_limit =
+            Self::filesystem_time_limit(&configuration.api, &location, directory_label.as_deref())
+                .await?;
 
-        let file_data = Self::_get_filedata(&file_path, location, &configuration.api)?;
+        let file_data = Self::_get_filedata(
+            &file_path,
+            location,
+            directory_label.as_deref(),
+            &configuration.api,
+        )?;
         tracing::info!("Extracting print metadata");
 
-        Sl1::set_user_metadata(&file_data.open_file().map_err(NotFound)?, patch_metadata)
-            .map_err(InternalServerError)?;
-
         Ok(Json(
-            Sl1::from_file(file_data).map_err(NotFound)?.get_metadata(),
+            run_blocking(time_limit, move || {
+                Sl1::set_user_metadata(&file_data.open_file().map_err(NotFound)?, patch_metadata)
+                    .map_err(file_operation_error)?;
+
+                Ok(open_print_file(file_data).map_err(NotFound)?.get_metadata())
+            })
+            .await?,
         ))
     }
 
-    #[instrument(ret, skip(configuration))]
+    #[instrument(ret, skip(configuration, extraction_limiter))]
     #[oai(path = "/file/thumbnail", method = "get")]
+    #[allow(clippy::too_many_arguments)]
     async fn get_thumbnail(
         &self,
         Query(file_path): Query<String>,
         Query(location): Query<Option<LocationCategory>>,
+        Query(directory_label): Query<Option<String>>,
         Query(size): Query<Option<ThumbnailSize>>,
+        Query(width): Query<Option<u32>>,
+        Query(height): Query<Option<u32>>,
+        Query(inline): Query<Option<bool>>,
         Data(configuration): Data<&Arc<Configuration>>,
-    ) -> Result<Attachment<Vec<u8>>> {
+        Data(extraction_limiter): Data<&Arc<ExtractionLimiter>>,
+    ) -> Result<PngResponse> {
         let location = location.unwrap_or(LocationCategory::Local);
-        let size = size.unwrap_or(ThumbnailSize::Small);
+        let (default_width, default_height) = size.unwrap_or(ThumbnailSize::Small).dimensions();
+        let width = width.unwrap_or(default_width);
+        let height = height.unwrap_or(default_height);
+        let inline = inline.unwrap_or(false);
 
         tracing::info!("Getting thumbnail from {:?} in {:?}", file_path, location);
+        let time_limit =
+            Self::filesystem_time_limit(&configuration.api, &location, directory_label.as_deref())
+                .await?;
 
-        let file_metadata = Self::_get_filedata(&file_path, location, &configuration.api)?;
+        let file_metadata = Self::_get_filedata(
+            &file_path,
+            location,
+            directory_label.as_deref(),
+            &configuration.api,
+        )?;
         tracing::info!("Extracting print thumbnail");
 
-        let file_data = Sl1::from_file(file_metadata)
-            .map_err(NotFound)?
-            .get_thumbnail(size)
-            .map_err(InternalServerError)?;
+        let file_data = extraction_limiter
+            .run_with_timeout(time_limit, move || {
+                open_print_file(file_metadata)
+                    .map_err(NotFound)?
+                    .get_thumbnail(width, height)
+                    .map_err(InternalServerError)
+            })
+            .await?;
 
-        Ok(Attachment::new(file_data.data).filename(file_data.name))
+        Ok(PngResponse::new(file_data.data, &file_data.name, inline))
+    }
+
+    /// Preview a single layer of a print file as a PNG, scaled down to fit
+    /// within width and height if given. Layers are counted from 0
+    #[instrument(ret, skip(configuration, extraction_limiter, layer_cache))]
+    #[oai(path = "/file/layer", method = "get")]
+    #[allow(clippy::too_many_arguments)]
+    async fn get_layer_preview(
+        &self,
+        Query(file_path): Query<String>,
+        Query(location): Query<Option<LocationCategory>>,
+        Query(directory_label): Query<Option<String>>,
+        Query(layer): Query<usize>,
+        Query(width): Query<Option<u32>>,
+        Query(height): Query<Option<u32>>,
+        Query(inline): Query<Option<bool>>,
+        Data(configuration): Data<&Arc<Configuration>>,
+        Data(extraction_limiter): Data<&Arc<ExtractionLimiter>>,
+        Data(layer_cache): Data<&Arc<LayerCache>>,
+    ) -> Result<PngResponse> {
+        let location = location.unwrap_or(LocationCategory::Local);
+        let size = (width.is_some() || height.is_some())
+            .then(|| (width.unwrap_or(u32::MAX), height.unwrap_or(u32::MAX)));
+        let inline = inline.unwrap_or(false);
+
+        let time_limit =
+            Self::filesystem_time_limit(&configuration.api, &location, directory_label.as_deref())
+                .await?;
+
+        let file_metadata = Self::_get_filedata(
+            &file_path,
+            location,
+            directory_label.as_deref(),
+            &configuration.api,
+        )?;
+        let file_name = format!(
+            "{}_layer_{}.png",
+            file_metadata
+                .name
+                .rsplit_once('.')
+                .map_or(file_metadata.name.as_str(), |(stem, _)| stem),
+            layer
+        );
+
+        let layer_cache = layer_cache.clone();
+        let data = extraction_limiter
+            .run_with_timeout(time_limit, move || {
+                layer_cache.get_layer(file_metadata, layer, size)
+            })
+            .await?;
+
+        Ok(PngResponse::new(data, &file_name, inline))
     }
 
     #[instrument(ret, skip(configuration))]
@@ -347,24 +1159,105 @@ impl FilesApi {
         &self,
         Query(file_path): Query<String>,
         Query(location): Query<Option<LocationCategory>>,
+        Query(directory_label): Query<Option<String>>,
         Data(configuration): Data<&Arc<Configuration>>,
     ) -> Result<Json<FileMetadata>> {
         let location = location.unwrap_or(LocationCategory::Local);
         tracing::info!("Deleting file {:?} in {:?}", file_path, location);
+        let time_limit =
+            Self::filesystem_time_limit(&configuration.api, &location, directory_label.as_deref())
+                .await?;
 
-        let metadata = Self::_get_filedata(&file_path, location, &configuration.api)?;
-        let full_file_path = metadata.get_full_path();
-
-        if full_file_path.is_dir() {
-            fs::remove_dir_all(full_file_path)
-                .await
-                .map_err(InternalServerError)?;
-        } else {
-            fs::remove_file(full_file_path)
-                .await
-                .map_err(InternalServerError)?;
+        // Local paths come from the client, so mustn't reach outside the
+        // upload directory or name the directory itself
+        if matches!(location, LocationCategory::Local) {
+            Self::upload_directory(&configuration.api, directory_label.as_deref())?
+                .resolve(Path::new(&file_path))
+                .map_err(BadRequest)?;
         }
 
+        let metadata = Self::_get_filedata(
+            &file_path,
+            location,
+            directory_label.as_deref(),
+            &configuration.api,
+        )?;
+
+        run_blocking(time_limit, {
+            let metadata = metadata.clone();
+            move || metadata.delete_file().map_err(file_operation_error)
+        })
+        .await?;
+
         Ok(Json(metadata))
     }
 }
+
+/// Map the error from a file operation onto the matching response status
+fn file_operation_error(error: Error) -> poem::Error {
+    match error.kind() {
+        ErrorKind::NotFound => NotFound(error),
+        ErrorKind::InvalidInput => BadRequest(error),
+        ErrorKind::AlreadyExists | ErrorKind::DirectoryNotEmpty => Conflict(error),
+        _ => InternalServerError(error),
+    }
+}
+
+/// Total size of every file under a directory, not following symlinks
+fn directory_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => directory_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+fn matches_search(path: &Path, query: &str) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.to_lowercase().contains(query))
+}
+
+/// Collect the paths, relative to the root, of print files matching a search
+/// query under a directory, up to MAX_SEARCH_DEPTH directories deep and until
+/// the limit is reached. Symlinked directories aren't followed
+fn search_directory(
+    root: &Path,
+    directory: &Path,
+    depth: usize,
+    query: &str,
+    limit: usize,
+    results: &mut Vec<PathBuf>,
+) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return;
+    };
+    let entries = entries.flatten().sorted_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        if results.len() >= limit {
+            return;
+        }
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => {
+                if depth < MAX_SEARCH_DEPTH {
+                    search_directory(root, &path, depth + 1, query, limit, results);
+                }
+            }
+            Ok(_) if is_print_file(&path) && matches_search(&path, query) => {
+                if let Ok(relative) = path.strip_prefix(root) {
+                    results.push(relative.to_path_buf());
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use itertools::Itertools;
+use poem::{error::InternalServerError, web::Data, Result};
+use poem_openapi::{param::Query, payload::Json, OpenApi};
+use tokio::task::spawn_blocking;
+use tracing::instrument;
+
+use crate::{
+    api_objects::{PrintHistoryResponse, PrintRecord, PrintStats},
+    configuration::Configuration,
+    print_history::{print_stats, PrintHistory},
+};
+
+const DEFAULT_PAGE_INDEX: usize = 0;
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+#[derive(Debug)]
+pub struct HistoryApi;
+
+#[OpenApi(prefix_path = "/history")]
+impl HistoryApi {
+    #[instrument(ret, skip(configuration))]
+    #[oai(path = "/", method = "get")]
+    async fn get_history(
+        &self,
+        Query(page_index): Query<Option<usize>>,
+        Query(page_size): Query<Option<usize>>,
+        Data(configuration): Data<&Arc<Configuration>>,
+    ) -> Result<Json<PrintHistoryResponse>> {
+        let page_index = page_index.unwrap_or(DEFAULT_PAGE_INDEX);
+        let page_size = page_size.unwrap_or(DEFAULT_PAGE_SIZE).max(1);
+
+        let records = Self::load_history(configuration).await?;
+
+        let chunks = records.into_iter().rev().chunks(page_size);
+        let mut chunks_iterator = chunks.into_iter();
+        let records = chunks_iterator
+            .nth(page_index)
+            .map_or(Vec::new(), |records| records.collect_vec());
+        let next_index = chunks_iterator.next().is_some().then_some(page_index + 1);
+
+        Ok(Json(PrintHistoryResponse {
+            records,
+            next_index,
+        }))
+    }
+
+    #[instrument(ret, skip(configuration))]
+    #[oai(path = "/stats", method = "get")]
+    async fn get_stats(
+        &self,
+        Data(configuration): Data<&Arc<Configuration>>,
+    ) -> Result<Json<PrintStats>> {
+        Self::load_stats(configuration).await.map(Json)
+    }
+
+    pub(super) async fn load_stats(configuration: &Configuration) -> Result<PrintStats> {
+        Ok(print_stats(&Self::load_history(configuration).await?))
+    }
+
+    async fn load_history(configuration: &Configuration) -> Result<Vec<PrintRecord>> {
+        let history = PrintHistory::new(&configuration.printer);
+
+        spawn_blocking(move || history.load())
+            .await
+            .map_err(InternalServerError)?
+            .map_err(InternalServerError)
+    }
+}
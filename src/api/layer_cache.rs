@@ -0,0 +1,137 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Error},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use futures::executor::block_on;
+use poem::{
+    error::{InternalServerError, NotFound},
+    Result,
+};
+
+use crate::{
+    api_objects::{FileMetadata, LocationCategory},
+    printfile::{open_print_file, PrintFile},
+    thumbnail::{clamp_dimensions, downscale_png},
+};
+
+/// Print files kept open, so scrubbing through layers doesn't reopen the archive
+const MAX_OPEN_FILES: usize = 2;
+/// Rendered layers kept, so going back over them doesn't extract them again
+const MAX_CACHED_LAYERS: usize = 32;
+
+/// Identifies the contents of a print file, so a replaced upload is reopened
+#[derive(Clone, Debug, PartialEq)]
+struct FileKey {
+    path: PathBuf,
+    last_modified: Option<u64>,
+    file_size: u64,
+}
+
+impl FileKey {
+    fn new(file_data: &FileMetadata) -> FileKey {
+        FileKey {
+            path: file_data.get_full_path(),
+            last_modified: file_data.last_modified,
+            file_size: file_data.file_size,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct LayerKey {
+    file: FileKey,
+    layer: usize,
+    size: Option<(u32, u32)>,
+}
+
+/// Recently opened print files and rendered layer previews
+#[derive(Default)]
+pub struct LayerCache {
+    files: Mutex<VecDeque<(FileKey, Box<dyn PrintFile + Send>)>>,
+    layers: Mutex<VecDeque<(LayerKey, Vec<u8>)>>,
+}
+
+impl LayerCache {
+    /// PNG of a single layer, scaled down to fit within size if given. This
+    /// blocks on reading the file, so is run through the ExtractionLimiter
+    pub fn get_layer(
+        &self,
+        file_data: FileMetadata,
+        layer: usize,
+        size: Option<(u32, u32)>,
+    ) -> Result<Vec<u8>> {
+        let size = size.map(|(width, height)| clamp_dimensions(width, height));
+        let key = LayerKey {
+            file: FileKey::new(&file_data),
+            layer,
+            size,
+        };
+
+        if let Some((_, data)) = self
+            .layers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(cached, _)| *cached == key)
+        {
+            return Ok(data.clone());
+        }
+
+        let data = self.extract_layer(file_data, layer)?;
+        let data = match size {
+            Some((width, height)) => {
+                downscale_png(data, width, height).map_err(InternalServerError)?
+            }
+            None => data,
+        };
+
+        let mut layers = self.layers.lock().unwrap();
+        if layers.len() >= MAX_CACHED_LAYERS {
+            layers.pop_front();
+        }
+        layers.push_back((key, data.clone()));
+        Ok(data)
+    }
+
+    // Read the layer from an already open copy of the file if there is one.
+    // The file is taken out of the cache while it's read, so other requests
+    // aren't held up, then put back as the most recently used
+    fn extract_layer(&self, file_data: FileMetadata, layer: usize) -> Result<Vec<u8>> {
+        let key = FileKey::new(&file_data);
+        let cached = {
+            let mut files = self.files.lock().unwrap();
+            files
+                .iter()
+                .position(|(cached, _)| *cached == key)
+                .and_then(|index| files.remove(index))
+        };
+        let mut file = match cached {
+            Some((_, file)) => file,
+            None => {
+                tracing::info!("Opening {} for layer previews", file_data.name);
+                open_print_file(file_data.clone()).map_err(NotFound)?
+            }
+        };
+
+        let layer_data = block_on(file.get_layer_data(layer));
+
+        // Files on USB drives are closed again, so they don't hold up unmounting
+        if matches!(file_data.location_category, LocationCategory::Local) {
+            let mut files = self.files.lock().unwrap();
+            if files.len() >= MAX_OPEN_FILES {
+                files.pop_front();
+            }
+            files.push_back((key, file));
+        }
+
+        layer_data.map(|layer| layer.data).ok_or_else(|| {
+            NotFound(Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no layer {}", file_data.name, layer),
+            ))
+        })
+    }
+}
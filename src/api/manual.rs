@@ -1,30 +1,75 @@
-use std::sync::Arc;
+use std::{io, sync::Arc};
 
-use poem::{web::Data, Result};
-use poem_openapi::{param::Query, OpenApi};
-use tokio::sync::mpsc;
+use poem::{
+    error::{BadRequest, Conflict, GetDataError},
+    web::Data,
+    Result,
+};
+use poem_openapi::{param::Query, payload::Json, OpenApi};
+use tokio::sync::{mpsc, RwLock};
 use tracing::instrument;
 
 use crate::{
-    api::Api,
-    api_objects::{DisplayTest, LocationCategory},
+    api::{
+        config::ConfigApi, extraction::ExtractionLimiter, files::FilesApi,
+        manual_limiter::ManualLimiter, Api,
+    },
+    api_objects::{
+        DisplayTest, ExposureTest, GcodeLogEntry, LocationCategory, PrinterState, PrinterStatus,
+    },
     configuration::Configuration,
     printer::Operation,
+    serial_handler::GcodeLog,
 };
 
+/// Most zones an exposure test may be split into
+const MAX_EXPOSURE_TEST_ZONES: usize = 10;
+const DEFAULT_EXPOSURE_TEST_ZONES: usize = 5;
+
+/// Most layers an exposure test may cure
+const MAX_EXPOSURE_TEST_LAYERS: usize = 10;
+
 #[derive(Debug)]
 pub struct ManualApi;
 
 #[OpenApi(prefix_path = "/manual")]
 impl ManualApi {
-    #[instrument(ret, skip(operation_sender))]
+    #[instrument(ret, skip(operation_sender, configuration, manual_limiter))]
     #[oai(path = "/", method = "post")]
     async fn manual_control(
         &self,
         Query(z): Query<Option<f64>>,
         Query(cure): Query<Option<bool>>,
+        Query(zone): Query<Option<String>>,
         Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+        Data(configuration): Data<&Arc<Configuration>>,
+        Data(manual_limiter): Data<&Arc<ManualLimiter>>,
     ) -> Result<()> {
+        // Reject unknown zones here, as hardware errors shut Odyssey down
+        if let Some(zone) = &zone {
+            if !configuration
+                .gcode
+                .cure_zones()
+                .iter()
+                .any(|cure_zone| &cure_zone.name == zone)
+            {
+                return Err(BadRequest(GetDataError("Unknown cure zone")));
+            }
+        }
+
+        if let Some(z) = z {
+            // Checked against the config file, as the limits may have been
+            // changed by a reload since startup
+            ConfigApi::current_config(configuration)?
+                .printer
+                .check_z(z)
+                .map_err(BadRequest)?;
+            manual_limiter.check_move()?;
+        }
+        if let Some(cure) = cure {
+            manual_limiter.check_cure(cure)?;
+        }
+
         if let Some(z) = z {
             Api::send_statemachine_operation(
                 operation_sender,
@@ -36,18 +81,23 @@ impl ManualApi {
         }
 
         if let Some(cure) = cure {
-            Api::send_statemachine_operation(operation_sender, Operation::ManualCure { cure })
-                .await?;
+            Api::send_statemachine_operation(
+                operation_sender,
+                Operation::ManualCure { cure, zone },
+            )
+            .await?;
         }
 
         Ok(())
     }
-    #[instrument(ret, skip(operation_sender))]
+    #[instrument(ret, skip(operation_sender, manual_limiter))]
     #[oai(path = "/home", method = "post")]
     async fn manual_home(
         &self,
         Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+        Data(manual_limiter): Data<&Arc<ManualLimiter>>,
     ) -> Result<()> {
+        manual_limiter.check_move()?;
         Ok(Api::send_statemachine_operation(operation_sender, Operation::ManualHome).await?)
     }
     #[instrument(ret, skip(operation_sender))]
@@ -65,6 +115,13 @@ impl ManualApi {
             .await?,
         )
     }
+    /// Recent lines sent to and received from the printer's serial
+    /// connection, oldest first
+    #[instrument(skip(gcode_log))]
+    #[oai(path = "/gcode_log", method = "get")]
+    async fn gcode_log(&self, Data(gcode_log): Data<&GcodeLog>) -> Json<Vec<GcodeLogEntry>> {
+        Json(gcode_log.entries())
+    }
     #[instrument(ret, skip(operation_sender))]
     #[oai(path = "/display_test", method = "post")]
     async fn manual_display_test(
@@ -78,24 +135,143 @@ impl ManualApi {
         )
         .await?)
     }
-    #[instrument(ret, skip(configuration, operation_sender))]
+    /// Show a single layer of a print file on the display, such as to check
+    /// focus or exposure. Layers are counted from 0
+    #[instrument(ret, skip(configuration, operation_sender, extraction_limiter))]
     #[oai(path = "/display_layer", method = "post")]
+    #[allow(clippy::too_many_arguments)]
     async fn manual_display_layer(
         &self,
         Query(file_path): Query<String>,
         Query(location): Query<Option<LocationCategory>>,
+        Query(directory_label): Query<Option<String>>,
         Query(layer): Query<usize>,
         Data(operation_sender): Data<&mpsc::Sender<Operation>>,
         Data(configuration): Data<&Arc<Configuration>>,
+        Data(extraction_limiter): Data<&Arc<ExtractionLimiter>>,
     ) -> Result<()> {
         let location = location.unwrap_or(LocationCategory::Local);
+        let time_limit = FilesApi::filesystem_time_limit(
+            &configuration.api,
+            &location,
+            directory_label.as_deref(),
+        )
+        .await?;
+        let api_config = configuration.api.clone();
 
-        let file_data = Api::_get_filedata(&file_path, location, &configuration.api)?;
+        // Check the layer exists here, as the printer can only log a failure
+        let metadata = extraction_limiter
+            .run_with_timeout(time_limit, move || {
+                FilesApi::_get_print_metadata(
+                    &file_path,
+                    location,
+                    directory_label.as_deref(),
+                    &api_config,
+                )
+            })
+            .await?;
+        if layer >= metadata.layer_count {
+            return Err(BadRequest(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} has {} layers, so there's no layer {}",
+                    metadata.file_data.name, metadata.layer_count, layer
+                ),
+            )));
+        }
 
         Ok(Api::send_statemachine_operation(
             operation_sender,
-            Operation::ManualDisplayLayer { file_data, layer },
+            Operation::ManualDisplayLayer {
+                file_data: metadata.file_data,
+                layer,
+            },
         )
         .await?)
     }
+    /// Cure a test pattern of bands of squares across the screen, starting at
+    /// base_exposure for the leftmost band and adding step for each band to its
+    /// right. The plate is homed, the pattern cured over the given number of
+    /// layers with a lift between each, then the plate is raised. Returns the
+    /// exposure of each band, to read off the result against
+    #[instrument(ret, skip(operation_sender, state_ref, configuration))]
+    #[oai(path = "/exposure_test", method = "post")]
+    #[allow(clippy::too_many_arguments)]
+    async fn manual_exposure_test(
+        &self,
+        Query(base_exposure): Query<f64>,
+        Query(step): Query<f64>,
+        Query(zones): Query<Option<usize>>,
+        Query(layers): Query<Option<usize>>,
+        Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+        Data(state_ref): Data<&Arc<RwLock<PrinterState>>>,
+        Data(configuration): Data<&Arc<Configuration>>,
+    ) -> Result<Json<ExposureTest>> {
+        let zones = zones.unwrap_or(DEFAULT_EXPOSURE_TEST_ZONES);
+        let layers = layers.unwrap_or(1);
+        let invalid =
+            |message: String| BadRequest(io::Error::new(io::ErrorKind::InvalidInput, message));
+
+        if !(base_exposure > 0.0 && base_exposure.is_finite()) {
+            return Err(invalid("base_exposure must be positive".to_string()));
+        }
+        if !(step > 0.0 && step.is_finite()) {
+            return Err(invalid("step must be positive".to_string()));
+        }
+        if !(2..=MAX_EXPOSURE_TEST_ZONES).contains(&zones) {
+            return Err(invalid(format!(
+                "zones must be between 2 and {}",
+                MAX_EXPOSURE_TEST_ZONES
+            )));
+        }
+        if !(1..=MAX_EXPOSURE_TEST_LAYERS).contains(&layers) {
+            return Err(invalid(format!(
+                "layers must be between 1 and {}",
+                MAX_EXPOSURE_TEST_LAYERS
+            )));
+        }
+        // Each exposure is a separate cure, so must stay within the watchdog
+        if let Some(max_cure) = configuration.printer.max_cure_seconds {
+            if base_exposure.max(step) > max_cure {
+                return Err(invalid(format!(
+                    "Exposures may be at most max_cure_seconds, {}s",
+                    max_cure
+                )));
+            }
+        }
+
+        {
+            let state = state_ref.read().await;
+            if !matches!(state.status, PrinterStatus::Idle) {
+                return Err(Conflict(io::Error::other(
+                    "Exposure tests can only be run while idle",
+                )));
+            }
+            if !state.frame_buffer_available
+                && !configuration
+                    .display
+                    .print_without_frame_buffer
+                    .unwrap_or(false)
+            {
+                return Err(Conflict(io::Error::other(format!(
+                    "framebuffer {} isn't available",
+                    configuration.display.frame_buffer
+                ))));
+            }
+        }
+
+        let test = ExposureTest::new(
+            configuration.display.screen_width,
+            base_exposure,
+            step,
+            zones,
+            layers,
+        );
+        Api::send_statemachine_operation(
+            operation_sender,
+            Operation::ExposureTest { test: test.clone() },
+        )
+        .await?;
+        Ok(Json(test))
+    }
 }
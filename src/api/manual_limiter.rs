@@ -0,0 +1,78 @@
+use std::{io, sync::Mutex};
+
+use poem::{error::TooManyRequests, Result};
+use tokio::time::{Duration, Instant};
+
+use crate::configuration::ApiConfig;
+
+const DEFAULT_MAX_MANUAL_MOVES_PER_SECOND: f64 = 5.0;
+const DEFAULT_MANUAL_CURE_DEBOUNCE_SECONDS: f64 = 0.5;
+
+/// Throttles manual control, so a misbehaving client can't flood the printer
+/// with moves or strobe the UV array
+pub struct ManualLimiter {
+    moves_per_second: f64,
+    /// Moves which may be made right now, refilled at moves_per_second up to a
+    /// burst of one second's worth
+    move_tokens: Mutex<(f64, Instant)>,
+    cure_debounce: Duration,
+    last_cure_change: Mutex<Option<Instant>>,
+}
+
+impl ManualLimiter {
+    pub fn new(configuration: &ApiConfig) -> ManualLimiter {
+        let moves_per_second = configuration
+            .max_manual_moves_per_second
+            .unwrap_or(DEFAULT_MAX_MANUAL_MOVES_PER_SECOND)
+            .max(f64::MIN_POSITIVE);
+        let cure_debounce = configuration
+            .manual_cure_debounce_seconds
+            .unwrap_or(DEFAULT_MANUAL_CURE_DEBOUNCE_SECONDS)
+            .max(0.0);
+
+        ManualLimiter {
+            moves_per_second,
+            move_tokens: Mutex::new((moves_per_second.max(1.0), Instant::now())),
+            cure_debounce: Duration::from_secs_f64(cure_debounce),
+            last_cure_change: Mutex::new(None),
+        }
+    }
+
+    /// Take a token for a manual move, failing if the move rate is exceeded
+    pub fn check_move(&self) -> Result<()> {
+        let mut move_tokens = self.move_tokens.lock().unwrap();
+        let (tokens, last_refill) = *move_tokens;
+        let now = Instant::now();
+
+        let burst = self.moves_per_second.max(1.0);
+        let tokens =
+            (tokens + (now - last_refill).as_secs_f64() * self.moves_per_second).min(burst);
+
+        if tokens < 1.0 {
+            *move_tokens = (tokens, now);
+            tracing::warn!("Manual move rate exceeded, rejecting request");
+            return Err(TooManyRequests(io::Error::other(
+                "Too many manual moves, slow down",
+            )));
+        }
+        *move_tokens = (tokens - 1.0, now);
+        Ok(())
+    }
+
+    /// Refuse to turn the UV array on again too soon after it last changed.
+    /// Turning it off is always allowed
+    pub fn check_cure(&self, cure: bool) -> Result<()> {
+        let mut last_cure_change = self.last_cure_change.lock().unwrap();
+        let now = Instant::now();
+
+        let too_soon = last_cure_change.is_some_and(|last| now - last < self.cure_debounce);
+        if cure && too_soon {
+            tracing::warn!("Manual cure toggled too quickly, rejecting request");
+            return Err(TooManyRequests(io::Error::other(
+                "UV array toggled too quickly, wait before curing again",
+            )));
+        }
+        *last_cure_change = Some(now);
+        Ok(())
+    }
+}
@@ -0,0 +1,466 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use futures::{SinkExt, StreamExt};
+use poem::{
+    error::{BadRequest, NotFound, Unauthorized},
+    get, handler,
+    http::Uri,
+    post,
+    web::{
+        websocket::{Message, WebSocket},
+        Data, Json, Query,
+    },
+    IntoResponse, Request, Result, Route,
+};
+use serde_json::{json, Map, Value};
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio_stream::wrappers::BroadcastStream;
+
+use crate::{
+    api::{auth::has_api_key, Api},
+    api_objects::{LocationCategory, PrinterState, PrinterStatus},
+    configuration::{Configuration, LockedProfiles},
+    printer::{Operation, PrioritySender},
+    printfile::is_print_file,
+    VERSION,
+};
+
+/// Printer objects which can be queried or subscribed to
+const PRINTER_OBJECTS: [&str; 6] = [
+    "webhooks",
+    "print_stats",
+    "virtual_sdcard",
+    "display_status",
+    "pause_resume",
+    "toolhead",
+];
+
+/// Moonraker's HTTP routes, each of which maps onto the JSON-RPC method of the
+/// same name
+const HTTP_METHODS: [&str; 6] = [
+    "/server/info",
+    "/server/files/list",
+    "/printer/info",
+    "/printer/objects/list",
+    "/printer/objects/query",
+    "/printer/objects/subscribe",
+];
+
+/// Routes which control the printer, only accepted as POST so they're covered
+/// by the API key
+const HTTP_ACTIONS: [&str; 4] = [
+    "/printer/print/start",
+    "/printer/print/pause",
+    "/printer/print/resume",
+    "/printer/print/cancel",
+];
+
+/// Mount a subset of Moonraker's API, enough for Klipper frontends such as
+/// Fluidd and Mainsail to start, pause, resume and cancel prints and follow
+/// their progress
+pub fn mount(mut route: Route) -> Route {
+    for path in HTTP_METHODS {
+        route = route.at(path, get(http_call).post(http_call));
+    }
+    for path in HTTP_ACTIONS {
+        route = route.at(path, post(http_call));
+    }
+    route.at("/websocket", get(websocket))
+}
+
+#[derive(Clone)]
+struct Moonraker {
+    operation_sender: mpsc::Sender<Operation>,
+    priority_sender: PrioritySender,
+    state_ref: Arc<RwLock<PrinterState>>,
+    configuration: Arc<Configuration>,
+    resin_profiles: LockedProfiles,
+    /// Whether the client may control the printer, rather than only read its
+    /// state
+    authorized: bool,
+}
+
+#[handler]
+async fn http_call(
+    uri: &Uri,
+    Query(query): Query<HashMap<String, String>>,
+    Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+    Data(priority_sender): Data<&PrioritySender>,
+    Data(state_ref): Data<&Arc<RwLock<PrinterState>>>,
+    Data(configuration): Data<&Arc<Configuration>>,
+    Data(resin_profiles): Data<&LockedProfiles>,
+) -> Result<Json<Value>> {
+    let method = uri.path().trim_start_matches('/').replace('/', ".");
+
+    // Object queries name each object as a parameter, with any attributes as
+    // a comma separated list
+    let params = if method.starts_with("printer.objects.") {
+        let objects: Map<String, Value> = query
+            .into_iter()
+            .map(|(object, attributes)| {
+                let attributes = match attributes.is_empty() {
+                    true => Value::Null,
+                    false => json!(attributes.split(',').collect::<Vec<_>>()),
+                };
+                (object, attributes)
+            })
+            .collect();
+        json!({ "objects": objects })
+    } else {
+        json!(query)
+    };
+
+    // Any request which got this far with an action has passed the API key check
+    let moonraker = Moonraker {
+        operation_sender: operation_sender.clone(),
+        priority_sender: priority_sender.clone(),
+        state_ref: state_ref.clone(),
+        configuration: configuration.clone(),
+        resin_profiles: resin_profiles.clone(),
+        authorized: true,
+    };
+    let result = moonraker.call(&method, &params).await?;
+
+    Ok(Json(json!({ "result": result })))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[handler]
+async fn websocket(
+    websocket: WebSocket,
+    req: &Request,
+    Query(query): Query<HashMap<String, String>>,
+    Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+    Data(priority_sender): Data<&PrioritySender>,
+    Data(state_receiver): Data<&Arc<broadcast::Receiver<PrinterState>>>,
+    Data(state_ref): Data<&Arc<RwLock<PrinterState>>>,
+    Data(configuration): Data<&Arc<Configuration>>,
+    Data(resin_profiles): Data<&LockedProfiles>,
+) -> impl IntoResponse {
+    // Websocket clients may pass the API key as a token, as they can't always
+    // set headers
+    let authorized = configuration
+        .api
+        .api_key
+        .as_ref()
+        .is_none_or(|api_key| has_api_key(req, api_key) || query.get("token") == Some(api_key));
+    let moonraker = Moonraker {
+        operation_sender: operation_sender.clone(),
+        priority_sender: priority_sender.clone(),
+        state_ref: state_ref.clone(),
+        configuration: configuration.clone(),
+        resin_profiles: resin_profiles.clone(),
+        authorized,
+    };
+    let state_receiver = state_receiver.clone();
+
+    websocket.on_upgrade(move |socket| async move {
+        let (mut sink, mut incoming) = socket.split();
+        let mut updates = BroadcastStream::new(state_receiver.resubscribe());
+        let mut subscription = Value::Null;
+        let mut last_status = Value::Null;
+
+        loop {
+            let reply = tokio::select! {
+                message = incoming.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        moonraker.handle_rpc(&text, &mut subscription).await
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+                update = updates.next() => match update {
+                    Some(Ok(state)) if !subscription.is_null() => {
+                        // Only notify when a subscribed object has changed
+                        let status = query_objects(&state, &subscription);
+                        if status == last_status {
+                            continue;
+                        }
+                        last_status = status.clone();
+                        json!({
+                            "jsonrpc": "2.0",
+                            "method": "notify_status_update",
+                            "params": [status, eventtime()],
+                        })
+                    }
+                    // Lagged updates are made up by the next one
+                    Some(_) => continue,
+                    None => break,
+                },
+            };
+            if sink.send(Message::Text(reply.to_string())).await.is_err() {
+                break;
+            }
+        }
+        tracing::info!("Moonraker websocket closed");
+    })
+}
+
+impl Moonraker {
+    /// Handle a single JSON-RPC request, keeping track of the objects the
+    /// connection has subscribed to
+    async fn handle_rpc(&self, text: &str, subscription: &mut Value) -> Value {
+        let request: Value = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => {
+                return json!({
+                    "jsonrpc": "2.0",
+                    "error": { "code": -32700, "message": e.to_string() },
+                    "id": Value::Null,
+                })
+            }
+        };
+        let id = request["id"].clone();
+        let method = request["method"].as_str().unwrap_or_default();
+        let params = &request["params"];
+
+        if method == "printer.objects.subscribe" {
+            *subscription = params["objects"].clone();
+        }
+
+        match self.call(method, params).await {
+            Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+            Err(e) => json!({
+                "jsonrpc": "2.0",
+                "error": { "code": e.status().as_u16(), "message": e.to_string() },
+                "id": id,
+            }),
+        }
+    }
+
+    async fn call(&self, method: &str, params: &Value) -> Result<Value> {
+        let state = self.state_ref.read().await.clone();
+        let klippy_state = match state.status {
+            PrinterStatus::Shutdown => "shutdown",
+            PrinterStatus::Initializing => "startup",
+            _ => "ready",
+        };
+
+        Ok(match method {
+            "server.info" => json!({
+                "klippy_connected": true,
+                "klippy_state": klippy_state,
+                "components": [],
+                "failed_components": [],
+                "registered_directories": ["gcodes"],
+                "warnings": [],
+                "moonraker_version": VERSION,
+                "api_version": [1, 0, 0],
+                "api_version_string": "1.0.0",
+            }),
+            "server.connection.identify" => json!({ "connection_id": 0 }),
+            "server.files.list" => self.list_files(params)?,
+            "printer.info" => json!({
+                "state": klippy_state,
+                "state_message": format!("Odyssey is {}", klippy_state),
+                "hostname": "odyssey",
+                "software_version": VERSION,
+            }),
+            "printer.objects.list" => json!({ "objects": PRINTER_OBJECTS }),
+            "printer.objects.query" | "printer.objects.subscribe" => json!({
+                "eventtime": eventtime(),
+                "status": query_objects(&state, &params["objects"]),
+            }),
+            "printer.print.start" => {
+                let file_name = params["filename"]
+                    .as_str()
+                    .ok_or(BadRequest(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "filename is required",
+                    )))?;
+                let file_data = Api::_get_filedata(
+                    file_name,
+                    LocationCategory::Local,
+                    None,
+                    &self.configuration.api,
+                )?;
+                let profile = Api::_resolve_profile(
+                    None,
+                    &file_data,
+                    &self.configuration,
+                    &self.resin_profiles,
+                )
+                .await?;
+                self.send(Operation::StartPrint { file_data, profile })
+                    .await?
+            }
+            "printer.print.pause" => self.send(Operation::PausePrint).await?,
+            "printer.print.resume" => self.send(Operation::ResumePrint).await?,
+            "printer.print.cancel" => self.send(Operation::StopPrint { retract: None }).await?,
+            _ => {
+                return Err(NotFound(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Method not found: {}", method),
+                )))
+            }
+        })
+    }
+
+    async fn send(&self, operation: Operation) -> Result<Value> {
+        if !self.authorized {
+            return Err(Unauthorized(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Missing or invalid API key",
+            )));
+        }
+        match operation.is_priority() {
+            true => Api::send_priority_operation(&self.priority_sender, operation).await?,
+            false => Api::send_statemachine_operation(&self.operation_sender, operation).await?,
+        }
+        Ok(json!("ok"))
+    }
+
+    /// List print files under the upload directory, which frontends know as
+    /// the gcodes root
+    fn list_files(&self, params: &Value) -> Result<Value> {
+        let root = params["root"].as_str().unwrap_or("gcodes");
+        if root != "gcodes" {
+            return Err(NotFound(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Root not available: {}", root),
+            )));
+        }
+
+        let upload_path = Path::new(&self.configuration.api.upload_path);
+        let mut files = Vec::new();
+        collect_files(upload_path, upload_path, &mut files);
+
+        Ok(json!(files))
+    }
+}
+
+fn collect_files(root: &Path, directory: &Path, files: &mut Vec<Value>) {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        if metadata.is_dir() {
+            collect_files(root, &path, files);
+        } else if is_print_file(&path) {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs_f64())
+                .unwrap_or_default();
+
+            files.push(json!({
+                "path": path.strip_prefix(root).unwrap_or(&path).to_string_lossy(),
+                "modified": modified,
+                "size": metadata.len(),
+                "permissions": "rw",
+            }));
+        }
+    }
+}
+
+/// Build the requested printer objects from the current state. Each object
+/// maps to a list of attributes to include, or null for all of them
+fn query_objects(state: &PrinterState, objects: &Value) -> Value {
+    let Some(objects) = objects.as_object() else {
+        return json!({});
+    };
+
+    let status: Map<String, Value> = objects
+        .iter()
+        .filter_map(|(name, attributes)| {
+            let object = printer_object(state, name)?;
+            let object = match attributes.as_array() {
+                Some(attributes) if !attributes.is_empty() => Value::Object(
+                    attributes
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .filter_map(|attribute| {
+                            Some((attribute.to_string(), object.get(attribute)?.clone()))
+                        })
+                        .collect(),
+                ),
+                _ => object,
+            };
+            Some((name.clone(), object))
+        })
+        .collect();
+
+    Value::Object(status)
+}
+
+fn printer_object(state: &PrinterState, name: &str) -> Option<Value> {
+    let paused = state.paused.unwrap_or(false);
+    let printing = matches!(state.status, PrinterStatus::Printing);
+    let print_data = state.print_data.as_ref();
+
+    let file_name = print_data
+        .map(|print_data| print_data.file_data.path.clone())
+        .unwrap_or_default();
+    let layer_count = print_data.map(|print_data| print_data.layer_count);
+    let progress = match (state.layer, layer_count) {
+        (Some(layer), Some(layer_count)) if layer_count > 0 => layer as f64 / layer_count as f64,
+        _ => 0.0,
+    };
+    let print_duration = match (print_data, state.time_remaining_seconds) {
+        (Some(print_data), Some(remaining)) => (print_data.print_time - remaining).max(0.0),
+        _ => 0.0,
+    };
+
+    Some(match name {
+        "webhooks" => json!({
+            "state": match state.status {
+                PrinterStatus::Shutdown => "shutdown",
+                PrinterStatus::Initializing => "startup",
+                _ => "ready",
+            },
+            "state_message": state.error.clone().unwrap_or_default(),
+        }),
+        "print_stats" => json!({
+            "filename": file_name,
+            "state": match state.status {
+                PrinterStatus::Printing if paused => "paused",
+                PrinterStatus::Printing => "printing",
+                PrinterStatus::Idle | PrinterStatus::Initializing => "standby",
+                PrinterStatus::Shutdown => "error",
+            },
+            "message": "",
+            "print_duration": print_duration,
+            "total_duration": print_duration,
+            "info": {
+                "current_layer": state.layer.map(|layer| layer + 1),
+                "total_layer": layer_count,
+            },
+        }),
+        "virtual_sdcard" => json!({
+            "file_path": file_name,
+            "progress": progress,
+            "is_active": printing && !paused,
+        }),
+        "display_status" => json!({
+            "progress": progress,
+            "message": Value::Null,
+        }),
+        "pause_resume" => json!({ "is_paused": printing && paused }),
+        "toolhead" => json!({
+            "position": [0.0, 0.0, state.physical_state.z, 0.0],
+            "homed_axes": "z",
+        }),
+        _ => return None,
+    })
+}
+
+/// Seconds since the epoch, standing in for Klipper's monotonic event time
+fn eventtime() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs_f64())
+        .unwrap_or_default()
+}
@@ -1,12 +1,19 @@
-use std::sync::Arc;
+use std::{io, sync::Arc};
 
-use poem::{web::Data, Result};
-use poem_openapi::{param::Query, OpenApi};
-use tokio::sync::mpsc;
+use poem::{
+    error::{BadRequest, GetDataError, NotFound},
+    web::Data,
+    Result,
+};
+use poem_openapi::{param::Query, payload::Json, OpenApi};
+use tokio::sync::{mpsc, RwLock};
 use tracing::instrument;
 
 use crate::{
-    api::Api, api_objects::LocationCategory, configuration::Configuration, printer::Operation,
+    api::Api,
+    api_objects::{FileMetadata, LocationCategory, PrintJob, PrinterState, PrinterStatus},
+    configuration::{Configuration, LockedProfiles},
+    printer::{Operation, PrioritySender},
 };
 
 #[derive(Debug)]
@@ -14,32 +21,193 @@ pub struct PrintApi;
 
 #[OpenApi(prefix_path = "/print")]
 impl PrintApi {
-    #[instrument(ret, skip(operation_sender, configuration))]
+    #[instrument(ret, skip(operation_sender, configuration, resin_profiles))]
     #[oai(path = "/start", method = "post")]
+    #[allow(clippy::too_many_arguments)]
     async fn start_print(
         &self,
         Query(file_path): Query<String>,
         Query(location): Query<Option<LocationCategory>>,
+        Query(directory_label): Query<Option<String>>,
+        Query(profile): Query<Option<String>>,
         Data(operation_sender): Data<&mpsc::Sender<Operation>>,
         Data(configuration): Data<&Arc<Configuration>>,
+        Data(resin_profiles): Data<&LockedProfiles>,
     ) -> Result<()> {
         let location = location.unwrap_or(LocationCategory::Local);
 
-        let file_data = Api::_get_filedata(&file_path, location, &configuration.api)?;
+        let file_data = Api::_get_filedata(
+            &file_path,
+            location,
+            directory_label.as_deref(),
+            &configuration.api,
+        )?;
+        let profile =
+            Api::_resolve_profile(profile, &file_data, configuration, resin_profiles).await?;
+
+        Ok(Api::send_statemachine_operation(
+            operation_sender,
+            Operation::StartPrint { file_data, profile },
+        )
+        .await?)
+    }
+
+    /// Describe the print in progress in full, or 404 if nothing is printing
+    #[instrument(ret, skip(state_ref))]
+    #[oai(path = "/current", method = "get")]
+    async fn get_current_print(
+        &self,
+        Data(state_ref): Data<&Arc<RwLock<PrinterState>>>,
+    ) -> Result<Json<PrintJob>> {
+        let state = state_ref.read().await.clone();
+
+        let print_data = match (state.status, state.print_data) {
+            (PrinterStatus::Printing, Some(print_data)) => print_data,
+            _ => {
+                return Err(NotFound(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No print in progress",
+                )))
+            }
+        };
 
+        Ok(Json(PrintJob {
+            print_data,
+            print_settings: state.print_settings,
+            layer: state.layer,
+            paused: state.paused.unwrap_or(false),
+            started_at: state.print_started_at,
+            time_remaining_seconds: state.time_remaining_seconds,
+            resume_countdown_seconds: state.resume_countdown_seconds,
+            print_queue: state.print_queue,
+        }))
+    }
+
+    #[instrument(ret, skip(state_ref))]
+    #[oai(path = "/queue", method = "get")]
+    async fn get_queue(
+        &self,
+        Data(state_ref): Data<&Arc<RwLock<PrinterState>>>,
+    ) -> Json<Vec<FileMetadata>> {
+        Json(state_ref.read().await.print_queue.clone())
+    }
+
+    #[instrument(ret, skip(operation_sender, configuration, resin_profiles))]
+    #[oai(path = "/queue", method = "post")]
+    #[allow(clippy::too_many_arguments)]
+    async fn enqueue_print(
+        &self,
+        Query(file_path): Query<String>,
+        Query(location): Query<Option<LocationCategory>>,
+        Query(directory_label): Query<Option<String>>,
+        Query(profile): Query<Option<String>>,
+        Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+        Data(configuration): Data<&Arc<Configuration>>,
+        Data(resin_profiles): Data<&LockedProfiles>,
+    ) -> Result<()> {
+        let location = location.unwrap_or(LocationCategory::Local);
+
+        let file_data = Api::_get_filedata(
+            &file_path,
+            location,
+            directory_label.as_deref(),
+            &configuration.api,
+        )?;
+        let profile =
+            Api::_resolve_profile(profile, &file_data, configuration, resin_profiles).await?;
+
+        Ok(Api::send_statemachine_operation(
+            operation_sender,
+            Operation::EnqueuePrint { file_data, profile },
+        )
+        .await?)
+    }
+
+    #[instrument(ret, skip(operation_sender))]
+    #[oai(path = "/queue", method = "delete")]
+    async fn remove_queued_print(
+        &self,
+        Query(index): Query<Option<usize>>,
+        Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+    ) -> Result<()> {
+        Ok(Api::send_statemachine_operation(
+            operation_sender,
+            Operation::RemoveQueuedPrint { index },
+        )
+        .await?)
+    }
+
+    #[instrument(ret, skip(operation_sender))]
+    #[oai(path = "/refresh", method = "post")]
+    async fn refresh_file(
+        &self,
+        Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+    ) -> Result<()> {
+        Ok(Api::send_statemachine_operation(operation_sender, Operation::RefreshFile).await?)
+    }
+
+    #[instrument(ret, skip(priority_sender))]
+    #[oai(path = "/pause", method = "post")]
+    async fn pause_print(&self, Data(priority_sender): Data<&PrioritySender>) -> Result<()> {
+        Ok(Api::send_priority_operation(priority_sender, Operation::PausePrint {}).await?)
+    }
+
+    #[instrument(ret, skip(operation_sender))]
+    #[oai(path = "/pause_at_layer", method = "post")]
+    async fn pause_at_layer(
+        &self,
+        Query(layer): Query<usize>,
+        Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+    ) -> Result<()> {
         Ok(
-            Api::send_statemachine_operation(operation_sender, Operation::StartPrint { file_data })
+            Api::send_statemachine_operation(operation_sender, Operation::PauseAtLayer { layer })
                 .await?,
         )
     }
 
     #[instrument(ret, skip(operation_sender))]
-    #[oai(path = "/pause", method = "post")]
-    async fn pause_print(
+    #[oai(path = "/waits", method = "post")]
+    async fn set_waits(
+        &self,
+        Query(settle): Query<Option<f64>>,
+        Query(before): Query<Option<f64>>,
+        Query(after): Query<Option<f64>>,
+        Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+    ) -> Result<()> {
+        if [settle, before, after]
+            .iter()
+            .flatten()
+            .any(|wait| *wait < 0.0)
+        {
+            return Err(BadRequest(GetDataError("Waits must not be negative")));
+        }
+
+        Ok(Api::send_statemachine_operation(
+            operation_sender,
+            Operation::SetWaits {
+                settle,
+                before,
+                after,
+            },
+        )
+        .await?)
+    }
+
+    #[instrument(ret, skip(operation_sender))]
+    #[oai(path = "/reexpose", method = "post")]
+    async fn reexpose_layers(
         &self,
+        Query(start): Query<usize>,
+        Query(count): Query<Option<usize>>,
         Data(operation_sender): Data<&mpsc::Sender<Operation>>,
     ) -> Result<()> {
-        Ok(Api::send_statemachine_operation(operation_sender, Operation::PausePrint {}).await?)
+        let count = count.unwrap_or(1);
+
+        Ok(Api::send_statemachine_operation(
+            operation_sender,
+            Operation::ReexposeLayers { start, count },
+        )
+        .await?)
     }
 
     #[instrument(ret, skip(operation_sender))]
@@ -51,12 +219,15 @@ impl PrintApi {
         Ok(Api::send_statemachine_operation(operation_sender, Operation::ResumePrint {}).await?)
     }
 
-    #[instrument(ret, skip(operation_sender))]
+    /// Cancel the print. With retract, the plate is raised out of the resin
+    /// before going idle, overriding retract_on_cancel
+    #[instrument(ret, skip(priority_sender))]
     #[oai(path = "/cancel", method = "post")]
     async fn cancel_print(
         &self,
-        Data(operation_sender): Data<&mpsc::Sender<Operation>>,
+        Query(retract): Query<Option<bool>>,
+        Data(priority_sender): Data<&PrioritySender>,
     ) -> Result<()> {
-        Ok(Api::send_statemachine_operation(operation_sender, Operation::StopPrint {}).await?)
+        Ok(Api::send_priority_operation(priority_sender, Operation::StopPrint { retract }).await?)
     }
 }
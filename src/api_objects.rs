@@ -1,14 +1,18 @@
 use std::{
-    fs::File,
+    collections::BTreeMap,
+    fs::{self, File},
     io,
     path::{Path, PathBuf},
     time::UNIX_EPOCH,
 };
 
+use nix::sys::statvfs::statvfs;
 use optional_struct::optional_struct;
 use poem_openapi::{Enum, Object};
 use serde::{Deserialize, Serialize};
 
+use crate::configuration::{DisplayBackend, UploadDirectoryKind};
+
 #[derive(Clone, Debug, Serialize, Deserialize, Enum)]
 pub enum LocationCategory {
     Local,
@@ -19,6 +23,8 @@ pub enum LocationCategory {
 pub struct FileData {
     pub name: String,
     pub data: Vec<u8>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Object)]
@@ -76,6 +82,57 @@ impl FileMetadata {
     pub fn open_file(&self) -> Result<File, io::Error> {
         File::open(self.get_full_path())
     }
+    /// Delete the file, or the directory if it's empty, so prints aren't
+    /// deleted in bulk by accident
+    pub fn delete_file(&self) -> Result<(), io::Error> {
+        let full_path = self.get_full_path();
+
+        if full_path.is_dir() {
+            if fs::read_dir(&full_path)?.next().is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::DirectoryNotEmpty,
+                    "Directory is not empty",
+                ));
+            }
+            fs::remove_dir(full_path)
+        } else {
+            fs::remove_file(full_path)
+        }
+    }
+}
+
+/// Space on the filesystem holding a path
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct StorageInfo {
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    /// Space available to unprivileged users, which excludes any reserved for root
+    pub free_bytes: u64,
+}
+
+impl StorageInfo {
+    // The statvfs field types differ between 32 and 64 bit targets
+    #[allow(clippy::useless_conversion)]
+    pub fn from_path(path: &Path) -> Result<Self, io::Error> {
+        let stats = statvfs(path)?;
+        let block_size = u64::from(stats.fragment_size());
+        let total_bytes = u64::from(stats.blocks()) * block_size;
+
+        Ok(StorageInfo {
+            total_bytes,
+            used_bytes: total_bytes - u64::from(stats.blocks_free()) * block_size,
+            free_bytes: u64::from(stats.blocks_available()) * block_size,
+        })
+    }
+}
+
+/// A configured upload directory, and whether it can currently be reached
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct UploadDirectoryStatus {
+    pub label: String,
+    pub path: String,
+    pub kind: UploadDirectoryKind,
+    pub available: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Object)]
@@ -87,6 +144,9 @@ pub struct PrintMetadata {
     pub layer_height_microns: u32,
     pub layer_count: usize,
     pub user_metadata: PrintUserMetadata,
+    /// Print time in seconds from Odyssey's own lift, speed and wait
+    /// settings, when recomputed. print_time is the slicer's estimate
+    pub estimated_print_time: Option<f64>,
 }
 
 #[optional_struct(UpdatePrintUserMetadata)]
@@ -95,6 +155,22 @@ pub struct PrintUserMetadata {
     pub print_count: u32,
     pub favorite: bool,
     pub rating: Option<u8>,
+    /// Free-form labels such as "calibration" or "minis", at most 32 of up to
+    /// 64 characters each
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Enum)]
+pub enum FileSortField {
+    Name,
+    Modified,
+    Size,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Enum)]
+pub enum SortOrder {
+    Asc,
+    Desc,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Enum)]
@@ -103,11 +179,48 @@ pub enum ThumbnailSize {
     Small,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, Object)]
+impl ThumbnailSize {
+    pub const ALL: [ThumbnailSize; 2] = [ThumbnailSize::Large, ThumbnailSize::Small];
+
+    /// Pixel dimensions of the named thumbnail size
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self {
+            ThumbnailSize::Large => (800, 480),
+            ThumbnailSize::Small => (400, 400),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
 pub struct PhysicalState {
     pub z: f64,
     pub z_microns: u32,
-    pub curing: bool,
+    /// Whether each cure zone is currently on, by zone name
+    pub curing: BTreeMap<String, bool>,
+    pub temperature: Option<f64>,
+    /// Controller firmware version, as reported at boot
+    pub firmware_version: Option<String>,
+}
+
+impl PhysicalState {
+    /// Whether any cure zone is currently on
+    pub fn is_curing(&self) -> bool {
+        self.curing.values().any(|curing| *curing)
+    }
+}
+
+/// Movement and timing values for a print, taken from the file or configured defaults
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Object)]
+pub struct PrintSettings {
+    pub layer_height: u32,
+    pub lift: u32,
+    pub up_speed: f64,
+    pub down_speed: f64,
+    pub settle_time: f64,
+    pub wait_before_exposure: f64,
+    pub wait_after_exposure: f64,
+    pub exposure_multiplier: f64,
+    pub first_layer_lift: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Object)]
@@ -117,6 +230,59 @@ pub struct PrinterState {
     pub layer: Option<usize>,
     pub physical_state: PhysicalState,
     pub status: PrinterStatus,
+    pub time_remaining_seconds: Option<f64>,
+    /// Seconds until a paused print resumes, while a resume countdown runs
+    pub resume_countdown_seconds: Option<f64>,
+    pub print_settings: Option<PrintSettings>,
+    /// Files waiting to be printed once the current print completes
+    pub print_queue: Vec<FileMetadata>,
+    /// Cumulative seconds the UV array has been on, across every print
+    pub uv_on_seconds: f64,
+    /// Unix time in seconds the current or last print started
+    pub print_started_at: Option<u64>,
+    /// Whether the serial connection to the printer's controller is up, or
+    /// None when running without one. A print is paused while it's down, and
+    /// can be resumed once it returns
+    pub serial_connected: Option<bool>,
+    /// Whether the framebuffer device is open. Without it, layers can't be
+    /// displayed and prints are refused
+    pub frame_buffer_available: bool,
+    /// Why the printer is being held in shutdown, such as homing failing at
+    /// boot
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Enum)]
+pub enum GcodeDirection {
+    Sent,
+    Received,
+}
+
+/// A single line sent to, or received from, the printer's serial connection
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct GcodeLogEntry {
+    /// Unix time in seconds, with millisecond precision
+    pub timestamp: f64,
+    pub direction: GcodeDirection,
+    pub line: String,
+}
+
+/// Everything describing the print in progress, so a client picking up part way
+/// through a print doesn't need to piece it together from several endpoints
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct PrintJob {
+    pub print_data: PrintMetadata,
+    /// Movement and timing values in use, after the resin profile, file and
+    /// configured defaults are resolved
+    pub print_settings: Option<PrintSettings>,
+    pub layer: Option<usize>,
+    pub paused: bool,
+    /// Unix time in seconds the print started
+    pub started_at: Option<u64>,
+    pub time_remaining_seconds: Option<f64>,
+    pub resume_countdown_seconds: Option<f64>,
+    /// Files waiting to be printed once this print completes
+    pub print_queue: Vec<FileMetadata>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Enum)]
@@ -124,6 +290,155 @@ pub enum PrinterStatus {
     Printing,
     Idle,
     Shutdown,
+    /// Odyssey has started, but hasn't yet found out whether the printer's
+    /// hardware is ready to boot
+    Initializing,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum PrintEventType {
+    PrintStarted,
+    LayerChanged,
+    PrintPaused,
+    PrintResumed,
+    PrintCompleted,
+    PrintCancelled,
+    PrintFailed,
+}
+
+impl PrintEventType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PrintEventType::PrintStarted => "print_started",
+            PrintEventType::LayerChanged => "layer_changed",
+            PrintEventType::PrintPaused => "print_paused",
+            PrintEventType::PrintResumed => "print_resumed",
+            PrintEventType::PrintCompleted => "print_completed",
+            PrintEventType::PrintCancelled => "print_cancelled",
+            PrintEventType::PrintFailed => "print_failed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum PrintOutcome {
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// A single finished print run, as kept in the print history
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct PrintRecord {
+    pub file_data: FileMetadata,
+    /// Unix time in seconds the print started
+    pub started_at: Option<u64>,
+    /// Unix time in seconds the print finished, failed or was cancelled
+    pub ended_at: u64,
+    pub layers_completed: usize,
+    pub layer_count: usize,
+    pub outcome: PrintOutcome,
+    /// Resin used in ml, in proportion to the layers printed
+    pub used_material: f64,
+}
+
+impl PrintRecord {
+    pub fn duration_seconds(&self) -> u64 {
+        self.started_at
+            .map_or(0, |started_at| self.ended_at.saturating_sub(started_at))
+    }
+}
+
+/// A page of the print history, newest first
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct PrintHistoryResponse {
+    pub records: Vec<PrintRecord>,
+    pub next_index: Option<usize>,
+}
+
+/// Totals across every print in the history
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Object)]
+pub struct PrintStats {
+    pub total_prints: usize,
+    pub completed_prints: usize,
+    pub failed_prints: usize,
+    pub cancelled_prints: usize,
+    pub total_print_seconds: u64,
+    /// Resin used in ml
+    pub total_material_used: f64,
+    /// Fraction of prints which completed, from 0 to 1, or None before any
+    /// print has finished
+    pub success_rate: Option<f64>,
+    /// Path of the file printed most often, the most recently printed of any
+    /// tied for most
+    pub most_printed_file: Option<String>,
+    /// Number of times the most printed file has been printed
+    pub most_printed_count: usize,
+}
+
+/// Discrete print lifecycle event, emitted alongside the full PrinterState updates
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct PrintEvent {
+    pub event_type: PrintEventType,
+    pub file_name: Option<String>,
+    pub layer: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum UsbEventType {
+    Inserted,
+    Removed,
+}
+
+impl UsbEventType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UsbEventType::Inserted => "usb_inserted",
+            UsbEventType::Removed => "usb_removed",
+        }
+    }
+}
+
+/// A USB drive matching the usb_glob being mounted or unmounted
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct UsbEvent {
+    pub event_type: UsbEventType,
+    pub mount_path: String,
+    /// Space available on the drive, when it was inserted
+    pub free_bytes: Option<u64>,
+}
+
+/// A configured gcode template, with its substitutions filled in
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct ResolvedGcode {
+    pub name: String,
+    pub template: String,
+    /// The command as it would be sent, if every substitution was available
+    pub command: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Sent on the status stream when a client fell behind and updates were
+/// dropped, so it knows to fetch the full state from /status
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct StreamLag {
+    /// Which updates were dropped: status, event or usb
+    pub stream: String,
+    pub skipped: u64,
+}
+
+/// Counters for monitoring the API
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct ApiMetrics {
+    /// Status stream messages dropped since startup, across every client,
+    /// because the client fell behind
+    pub lagged_messages: u64,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Enum)]
@@ -134,6 +449,109 @@ pub enum DisplayTest {
     Dimensions,
 }
 
+/// A band of the exposure test pattern, and the total time it's cured for on
+/// each layer
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct ExposureTestZone {
+    /// Counted from 0 at the left of the screen
+    pub zone: usize,
+    /// First pixel column of the band
+    pub start_x: u32,
+    /// Pixel column just past the end of the band
+    pub end_x: u32,
+    pub exposure_seconds: f64,
+}
+
+/// Plan of an exposure test, curing bands of squares across the screen with
+/// each band exposed for a step longer than the one to its left
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct ExposureTest {
+    pub base_exposure: f64,
+    pub step: f64,
+    pub layers: usize,
+    pub zones: Vec<ExposureTestZone>,
+}
+
+impl ExposureTest {
+    pub fn new(
+        screen_width: u32,
+        base_exposure: f64,
+        step: f64,
+        zones: usize,
+        layers: usize,
+    ) -> ExposureTest {
+        let zone_width = screen_width / zones.max(1) as u32;
+        ExposureTest {
+            base_exposure,
+            step,
+            layers,
+            zones: (0..zones)
+                .map(|zone| ExposureTestZone {
+                    zone,
+                    start_x: zone as u32 * zone_width,
+                    end_x: (zone as u32 + 1) * zone_width,
+                    exposure_seconds: base_exposure + zone as f64 * step,
+                })
+                .collect(),
+        }
+    }
+
+    /// Time to cure for after masking off each zone in turn, so that zone i
+    /// has been cured for base_exposure + i * step once it's masked off
+    pub fn exposure_steps(&self) -> impl Iterator<Item = f64> + '_ {
+        (0..self.zones.len()).map(|zone| match zone {
+            0 => self.base_exposure,
+            _ => self.step,
+        })
+    }
+}
+
+/// A named thumbnail size, which thumbnails are scaled to by default
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct ThumbnailDimensions {
+    pub size: ThumbnailSize,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What this build and configuration of Odyssey support, so a frontend can
+/// adapt to the machine it's driving
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct Capabilities {
+    /// Extensions of the print file formats which can be printed
+    pub file_formats: Vec<String>,
+    /// Whether print files can be read from USB drives, which needs a usb_glob
+    pub usb_files: bool,
+    pub thumbnail_sizes: Vec<ThumbnailDimensions>,
+    /// Whether the configured framebuffer could be opened
+    pub frame_buffer_available: bool,
+    pub display_backend: DisplayBackend,
+    pub screen_width: u32,
+    pub screen_height: u32,
+    /// Lowest Z the plate may be moved to, in mm
+    pub min_z: f64,
+    /// Highest Z the plate may be moved to, in mm
+    pub max_z: f64,
+    /// Names of the UV zones which can be cured individually
+    pub cure_zones: Vec<String>,
+    /// Whether the Moonraker compatible API is being served
+    pub moonraker: bool,
+    /// Whether this build can publish to MQTT
+    pub mqtt: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Enum)]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha256,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct FileChecksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub checksum: String,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Object)]
 pub struct ReleaseVersion {
     pub name: String,
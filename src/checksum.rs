@@ -0,0 +1,59 @@
+use std::io::{self, Read};
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::api_objects::ChecksumAlgorithm;
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Stream the given reader through the hasher for the chosen algorithm, and
+/// return the digest as a lowercase hex string
+pub fn compute<R: Read>(algorithm: &ChecksumAlgorithm, reader: R) -> io::Result<String> {
+    match algorithm {
+        ChecksumAlgorithm::Md5 => digest::<Md5, R>(reader),
+        ChecksumAlgorithm::Sha256 => digest::<Sha256, R>(reader),
+    }
+}
+
+fn digest<D: Digest, R: Read>(mut reader: R) -> io::Result<String> {
+    let mut hasher = D::new();
+    let mut buffer = vec![0; READ_CHUNK_SIZE];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Parse a client-supplied checksum, either prefixed with its algorithm
+/// (`sha256:<hex>`, `md5:<hex>`) or bare, in which case the algorithm is
+/// inferred from the digest length
+pub fn parse(checksum: &str) -> Option<(ChecksumAlgorithm, String)> {
+    let (algorithm, digest) = match checksum.trim().split_once(':') {
+        Some((prefix, digest)) => match prefix.to_lowercase().as_str() {
+            "md5" => (ChecksumAlgorithm::Md5, digest),
+            "sha256" => (ChecksumAlgorithm::Sha256, digest),
+            _ => return None,
+        },
+        None => match checksum.trim().len() {
+            32 => (ChecksumAlgorithm::Md5, checksum.trim()),
+            64 => (ChecksumAlgorithm::Sha256, checksum.trim()),
+            _ => return None,
+        },
+    };
+
+    digest
+        .chars()
+        .all(|c| c.is_ascii_hexdigit())
+        .then(|| (algorithm, digest.to_lowercase()))
+}
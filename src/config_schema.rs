@@ -0,0 +1,248 @@
+use poem_openapi::{
+    registry::{MetaSchema, MetaSchemaRef, Registry},
+    types::Type,
+    Object,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::{Configuration, MAX_PLAUSIBLE_Z};
+
+/// Description of a single config setting, for frontends to render an input
+/// for it and check values before sending a patch
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct ConfigFieldSchema {
+    /// Dotted path to the setting, such as printer.max_z
+    pub field: String,
+    /// OpenAPI type of the value: integer, number, string, boolean, array or
+    /// object
+    pub value_type: String,
+    /// OpenAPI format of the value, such as double or uint32
+    pub format: Option<String>,
+    /// OpenAPI type of each item, for arrays
+    pub item_type: Option<String>,
+    /// Whether the setting must always be present
+    pub required: bool,
+    pub description: Option<String>,
+    /// Accepted values, for settings which are one of a fixed set
+    pub options: Vec<String>,
+    pub unit: Option<String>,
+    /// Lowest recommended value
+    pub min: Option<f64>,
+    /// Highest recommended value
+    pub max: Option<f64>,
+}
+
+/// Unit and recommended range of a numeric setting, which the OpenAPI schema
+/// can't express
+pub struct FieldBounds {
+    pub field: &'static str,
+    pub unit: Option<&'static str>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+const fn bounds(
+    field: &'static str,
+    unit: Option<&'static str>,
+    min: f64,
+    max: Option<f64>,
+) -> FieldBounds {
+    FieldBounds {
+        field,
+        unit,
+        min: Some(min),
+        max,
+    }
+}
+
+const MM: Option<&str> = Some("mm");
+const MM_PER_SECOND: Option<&str> = Some("mm/s");
+const SECONDS: Option<&str> = Some("seconds");
+const PIXELS: Option<&str> = Some("pixels");
+
+/// Recommended ranges, chosen to cover any plausible machine rather than as
+/// hard limits, which are left to validation
+pub const FIELD_BOUNDS: &[FieldBounds] = &[
+    bounds("printer.gcode_log_size", Some("lines"), 0.0, Some(100000.0)),
+    bounds("printer.max_z", MM, 1.0, Some(MAX_PLAUSIBLE_Z)),
+    bounds("printer.min_z", MM, 0.0, Some(MAX_PLAUSIBLE_Z)),
+    bounds("printer.default_lift", MM, 0.5, Some(50.0)),
+    bounds("printer.default_up_speed", MM_PER_SECOND, 0.1, Some(20.0)),
+    bounds("printer.default_down_speed", MM_PER_SECOND, 0.1, Some(20.0)),
+    bounds("printer.default_settle_time", SECONDS, 0.0, Some(60.0)),
+    bounds(
+        "printer.default_wait_before_exposure",
+        SECONDS,
+        0.0,
+        Some(60.0),
+    ),
+    bounds(
+        "printer.default_wait_after_exposure",
+        SECONDS,
+        0.0,
+        Some(60.0),
+    ),
+    bounds("printer.pause_lift", MM, 0.0, Some(MAX_PLAUSIBLE_Z)),
+    bounds(
+        "printer.backlash_microns",
+        Some("microns"),
+        0.0,
+        Some(1000.0),
+    ),
+    bounds("printer.min_first_layer_area", PIXELS, 0.0, None),
+    bounds(
+        "printer.uv_lifespan_hours",
+        Some("hours"),
+        1.0,
+        Some(100000.0),
+    ),
+    bounds("printer.max_cure_seconds", SECONDS, 1.0, Some(600.0)),
+    bounds("printer.queue_cooldown_seconds", SECONDS, 0.0, Some(3600.0)),
+    bounds("printer.resume_countdown_seconds", SECONDS, 0.0, Some(60.0)),
+    bounds("printer.idle_tick_seconds", SECONDS, 0.1, Some(10.0)),
+    bounds(
+        "printer.status_heartbeat_seconds",
+        SECONDS,
+        1.0,
+        Some(3600.0),
+    ),
+    bounds("printer.resin_mixing.depth", MM, 0.0, Some(50.0)),
+    bounds("printer.resin_mixing.count", None, 0.0, Some(20.0)),
+    bounds("printer.resin_mixing.speed", MM_PER_SECOND, 0.1, Some(20.0)),
+    bounds("printer.exposure_multiplier", None, 0.1, Some(10.0)),
+    bounds(
+        "printer.wait_before_exposure_multiplier",
+        None,
+        0.1,
+        Some(10.0),
+    ),
+    bounds(
+        "printer.wait_after_exposure_multiplier",
+        None,
+        0.1,
+        Some(10.0),
+    ),
+    bounds("printer.top_fade.layers", Some("layers"), 1.0, Some(1000.0)),
+    bounds(
+        "printer.top_fade.final_exposure_factor",
+        None,
+        0.01,
+        Some(1.0),
+    ),
+    bounds("gcode.move_timeout", SECONDS, 1.0, Some(600.0)),
+    bounds("api.port", None, 1.0, Some(65535.0)),
+    bounds("api.max_concurrent_extractions", None, 1.0, Some(16.0)),
+    bounds("api.max_queued_extractions", None, 0.0, Some(100.0)),
+    bounds("api.max_queued_operations", None, 1.0, Some(1000.0)),
+    bounds("api.max_manual_moves_per_second", None, 0.1, Some(50.0)),
+    bounds("api.manual_cure_debounce_seconds", SECONDS, 0.0, Some(10.0)),
+    bounds("display.screen_width", PIXELS, 1.0, Some(16384.0)),
+    bounds("display.screen_height", PIXELS, 1.0, Some(16384.0)),
+    bounds(
+        "display.status_overlay.row_offset",
+        PIXELS,
+        0.0,
+        Some(16384.0),
+    ),
+    bounds("display.status_overlay.height", PIXELS, 1.0, Some(16384.0)),
+    bounds("display.write_retries", None, 0.0, Some(10.0)),
+    bounds("display.grid_pitch", PIXELS, 1.0, Some(1000.0)),
+    bounds("display.rotation", Some("degrees"), 0.0, Some(270.0)),
+    bounds("display.offset_x", PIXELS, 0.0, Some(16384.0)),
+    bounds("display.offset_y", PIXELS, 0.0, Some(16384.0)),
+    bounds("display.scale_x", None, 0.9, Some(1.1)),
+    bounds("display.scale_y", None, 0.9, Some(1.1)),
+    bounds("mqtt.port", None, 1.0, Some(65535.0)),
+];
+
+/// Fields which only exist at runtime, never set by a client
+const INTERNAL_FIELDS: &[&str] = &["config_file"];
+
+/// Every setting of the Configuration, in declaration order. Fields are taken
+/// from the OpenAPI schema so they can't drift from the structs, with units
+/// and ranges added from FIELD_BOUNDS
+pub fn config_schema() -> Vec<ConfigFieldSchema> {
+    let mut registry = Registry::new();
+    Configuration::register(&mut registry);
+
+    let mut fields = Vec::new();
+    collect_fields(
+        &registry,
+        resolve(&registry, &Configuration::schema_ref()).0,
+        "",
+        true,
+        &mut fields,
+    );
+    fields
+}
+
+fn collect_fields(
+    registry: &Registry,
+    schema: &MetaSchema,
+    prefix: &str,
+    section_required: bool,
+    fields: &mut Vec<ConfigFieldSchema>,
+) {
+    for (name, property) in &schema.properties {
+        if prefix.is_empty() && INTERNAL_FIELDS.contains(name) {
+            continue;
+        }
+        let field = format!("{}{}", prefix, name);
+        let (property, description) = resolve(registry, property);
+        // Settings of an optional section are only needed once it's present
+        let required = section_required && schema.required.contains(name);
+
+        // Sections and nested settings are flattened, while maps such as the
+        // resin profiles are left to be edited as a whole
+        if property.ty == "object" && !property.properties.is_empty() {
+            collect_fields(registry, property, &format!("{}.", field), required, fields);
+            continue;
+        }
+
+        let bounds = FIELD_BOUNDS.iter().find(|bounds| bounds.field == field);
+        fields.push(ConfigFieldSchema {
+            value_type: property.ty.to_string(),
+            format: property.format.map(str::to_string),
+            item_type: property
+                .items
+                .as_ref()
+                .map(|items| resolve(registry, items).0.ty.to_string()),
+            required,
+            description: description.map(str::to_string),
+            options: property
+                .enum_items
+                .iter()
+                .filter_map(|item| item.as_str().map(str::to_string))
+                .collect(),
+            unit: bounds.and_then(|bounds| bounds.unit.map(str::to_string)),
+            min: bounds.and_then(|bounds| bounds.min),
+            max: bounds.and_then(|bounds| bounds.max),
+            field,
+        });
+    }
+}
+
+/// The schema a reference points to, along with the description of the field
+/// itself. A documented field referring to another type is an inline schema
+/// holding the description, combined with the reference through all_of
+fn resolve<'a>(
+    registry: &'a Registry,
+    schema_ref: &'a MetaSchemaRef,
+) -> (&'a MetaSchema, Option<&'static str>) {
+    match schema_ref {
+        MetaSchemaRef::Reference(name) => {
+            let schema = &registry.schemas[name];
+            (schema, schema.description)
+        }
+        MetaSchemaRef::Inline(schema) => {
+            let referenced = schema
+                .all_of
+                .iter()
+                .find(|schema_ref| matches!(schema_ref, MetaSchemaRef::Reference(_)));
+            match referenced {
+                Some(referenced) => (resolve(registry, referenced).0, schema.description),
+                None => (schema, schema.description),
+            }
+        }
+    }
+}
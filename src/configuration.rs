@@ -1,37 +1,380 @@
+use nix::unistd::{access, AccessFlags};
 use optional_struct::*;
-use poem_openapi::Object;
+use poem_openapi::{Enum, Object};
 use serde::{Deserialize, Serialize};
-use std::{error::Error, fmt::Debug, fs, io, sync::Arc};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Debug,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::sync::RwLock;
 
+use crate::gcode::expand_macros;
+
 #[optional_struct(UpdatePrinterConfig)]
 #[derive(Clone, Debug, Serialize, Deserialize, Object)]
 pub struct PrinterConfig {
     pub serial: String,
     pub baudrate: u32,
+    /// Reload the config file on SIGHUP, such as from `systemctl reload`, rather
+    /// than exiting. Printer and gcode settings are taken up once any print in
+    /// progress finishes, while other sections need a restart. Defaults to true
+    pub reload_on_sighup: Option<bool>,
+    /// Most recent lines sent to and received from the serial connection kept
+    /// for /manual/gcode_log. Defaults to 500, and 0 disables the log
+    pub gcode_log_size: Option<usize>,
     pub max_z: f64,
+    /// Lowest Z position in mm the plate may be moved to, for machines whose
+    /// zero isn't the bottom of their travel. Defaults to 0
+    pub min_z: Option<f64>,
     pub default_lift: f64,
     pub default_up_speed: f64,
     pub default_down_speed: f64,
+    /// Time in seconds to let resin flow back and level after the plate is
+    /// lowered, before the wait before exposure begins
+    pub default_settle_time: Option<f64>,
     pub default_wait_before_exposure: f64,
     pub default_wait_after_exposure: f64,
     pub pause_lift: f64,
+    /// Raise the plate to max_z when a print is cancelled, rather than leaving
+    /// it in the resin. Defaults to false
+    pub retract_on_cancel: Option<bool>,
+    /// Home the plate once the boot gcode has run, holding the printer in
+    /// shutdown unless the home is confirmed by home_sync, or move_sync when
+    /// that's unset. Defaults to false
+    pub home_on_boot: Option<bool>,
+    /// Slack in the Z axis leadscrew, taken up by moving further whenever a move
+    /// reverses the direction of the last one. The reported position is
+    /// unaffected. Defaults to 0
+    pub backlash_microns: Option<u32>,
+    /// Minimum number of lit pixels in the first layer before a print is allowed
+    /// to start, guarding against models which aren't anchored to the plate
+    pub min_first_layer_area: Option<usize>,
+    /// Start the print at the first layer with any lit pixels, rather than
+    /// curing blank layers padded below the model. Defaults to false
+    pub skip_leading_empty_layers: Option<bool>,
+    /// Retry the whole layer once, moving, displaying and curing again, when
+    /// displaying or curing it fails. A second failure pauses the print as
+    /// usual. Defaults to false
+    pub retry_failed_layer: Option<bool>,
+    /// File the cumulative UV-on time is persisted to. Defaults to
+    /// uv_usage.yaml in the working directory
+    pub uv_usage_file: Option<String>,
+    /// File a record of each finished print is appended to, as JSON lines.
+    /// Defaults to print_history.jsonl in the working directory
+    pub print_history_file: Option<String>,
+    /// Hours of UV-on time the UV array is expected to last, after which a
+    /// warning is logged so a replacement can be planned
+    pub uv_lifespan_hours: Option<f64>,
+    /// Longest time in seconds the UV array may stay on before Odyssey forces it
+    /// off and shuts down
+    pub max_cure_seconds: Option<f64>,
+    /// Time in seconds to wait after a print completes before starting the next
+    /// queued print
+    pub queue_cooldown_seconds: Option<f64>,
+    /// Time in seconds to count down after a resume is requested before the
+    /// plate starts moving again
+    pub resume_countdown_seconds: Option<f64>,
+    /// Time in seconds between checks for new operations while idle or shut
+    /// down. Defaults to 1
+    pub idle_tick_seconds: Option<f64>,
+    /// Time in seconds between status updates sent while idle, even when
+    /// nothing has changed, so clients can tell Odyssey is still running. No
+    /// heartbeat is sent when unset
+    pub status_heartbeat_seconds: Option<f64>,
+    pub resin_mixing: Option<ResinMixingConfig>,
+    /// Factor applied to every layer's exposure time, to compensate for a
+    /// display whose UV output differs from the one the files were sliced for
+    pub exposure_multiplier: Option<f64>,
+    /// Factor applied to the wait before exposure
+    pub wait_before_exposure_multiplier: Option<f64>,
+    /// Factor applied to the wait after exposure
+    pub wait_after_exposure_multiplier: Option<f64>,
+    pub top_fade: Option<TopFadeConfig>,
+}
+
+/// Exposure reduction over the final layers of a print, ramping linearly from
+/// the normal exposure down to final_exposure_factor on the last layer
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Object)]
+pub struct TopFadeConfig {
+    /// Number of layers at the top of the print to fade over
+    pub layers: usize,
+    /// Fraction of the normal exposure used for the last layer
+    pub final_exposure_factor: f64,
+}
+
+impl TopFadeConfig {
+    /// Exposure factor for the given layer, counted from 0
+    pub fn exposure_factor(&self, layer: usize, layer_count: usize) -> f64 {
+        let layers_from_top = layer_count.saturating_sub(layer + 1);
+
+        if self.layers > 0 && layers_from_top < self.layers {
+            let fade_rate = (self.layers - layers_from_top) as f64 / self.layers as f64;
+            1.0 + (self.final_exposure_factor - 1.0) * fade_rate
+        } else {
+            1.0
+        }
+    }
+}
+
+impl PrinterConfig {
+    pub fn validate(&self) -> Result<(), io::Error> {
+        let multipliers = [
+            ("exposure_multiplier", self.exposure_multiplier),
+            (
+                "wait_before_exposure_multiplier",
+                self.wait_before_exposure_multiplier,
+            ),
+            (
+                "wait_after_exposure_multiplier",
+                self.wait_after_exposure_multiplier,
+            ),
+            (
+                "top_fade.final_exposure_factor",
+                self.top_fade
+                    .as_ref()
+                    .map(|top_fade| top_fade.final_exposure_factor),
+            ),
+        ];
+
+        let (min_z, max_z) = self.z_limits();
+        if !(min_z >= 0.0 && min_z < max_z) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "min_z must be at least 0 and below max_z, got {} and {}",
+                    min_z, max_z
+                ),
+            ));
+        }
+
+        let intervals = [
+            ("idle_tick_seconds", self.idle_tick_seconds),
+            ("status_heartbeat_seconds", self.status_heartbeat_seconds),
+        ];
+        for (name, interval) in intervals {
+            if let Some(interval) = interval {
+                if !(interval > 0.0 && interval.is_finite()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} must be positive, got {}", name, interval),
+                    ));
+                }
+            }
+        }
+
+        for (name, multiplier) in multipliers {
+            if let Some(multiplier) = multiplier {
+                if !(multiplier > 0.0 && multiplier.is_finite()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} must be positive, got {}", name, multiplier),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Time between checks for new operations while idle or shut down
+    pub fn idle_tick(&self) -> Duration {
+        Duration::from_secs_f64(self.idle_tick_seconds.unwrap_or(1.0))
+    }
+
+    /// Lowest and highest Z positions in mm the plate may be moved to
+    pub fn z_limits(&self) -> (f64, f64) {
+        (self.min_z.unwrap_or(0.0), self.max_z)
+    }
+
+    /// Check a Z position in mm lies within the soft limits
+    pub fn check_z(&self, z: f64) -> Result<(), io::Error> {
+        let (min_z, max_z) = self.z_limits();
+        if !(min_z..=max_z).contains(&z) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Z position {}mm is outside the limits of {}mm to {}mm",
+                    z, min_z, max_z
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Plate movements used to stir settled resin before each print
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Object)]
+pub struct ResinMixingConfig {
+    /// Height in mm to raise the plate to above the first layer
+    pub depth: f64,
+    /// Number of times to raise and lower the plate
+    pub count: u32,
+    /// Speed in mm/s of the mixing moves
+    pub speed: f64,
 }
 
 #[optional_struct(UpdateDisplayConfig)]
-#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Object)]
 pub struct DisplayConfig {
     pub frame_buffer: String,
+    /// How layers are written to the panel. With drm, frame_buffer is the DRM
+    /// card, such as /dev/dri/card0. Defaults to fbdev
+    pub backend: Option<DisplayBackend>,
     pub bit_depth: Vec<u8>,
     pub screen_width: u32,
     pub screen_height: u32,
+    pub status_overlay: Option<StatusOverlayConfig>,
+    pub strict_frame_buffer: Option<bool>,
+    /// Start prints even when frame_buffer couldn't be opened as a
+    /// framebuffer, writing layers to it as a plain file instead, for testing
+    /// without a display. Defaults to false, refusing such prints
+    pub print_without_frame_buffer: Option<bool>,
+    /// Blank the display as soon as each layer's cure ends, so the plate
+    /// moves in the dark, and only show the next layer once it's in
+    /// position. Defaults to false
+    pub blank_between_layers: Option<bool>,
+    /// Number of times to retry a failed framebuffer write before giving up
+    /// on the frame. Defaults to 1
+    pub write_retries: Option<u32>,
+    /// Spacing in pixels between lines of the grid test pattern. Defaults to 100
+    pub grid_pitch: Option<u32>,
+    /// How quantization error is handled when reducing 8-bit layers to the
+    /// display's bit depth. Defaults to none, which truncates each pixel
+    pub dithering: Option<DitherMode>,
+    /// Clockwise rotation in degrees from the slicer's orientation to the
+    /// panel's, one of 0, 90, 180 or 270. For 90 and 270, layers are expected
+    /// to be screen_height pixels wide and screen_width pixels high
+    pub rotation: Option<u16>,
+    /// Mirror layers left to right on the panel
+    pub mirror_x: Option<bool>,
+    /// Mirror layers top to bottom on the panel
+    pub mirror_y: Option<bool>,
+    /// Pixels from the left of the panel to place layers smaller than the
+    /// panel. Setting either offset enables placing layers into a sub-region
+    pub offset_x: Option<u32>,
+    /// Pixels from the top of the panel to place layers smaller than the panel
+    pub offset_y: Option<u32>,
+    /// Factor to scale layers by left to right on the panel, about their
+    /// centre, correcting the panel's measured scaling. Set to the intended
+    /// size over the printed size, so 1.006 for parts printing 0.6% small.
+    /// Defaults to 1
+    pub scale_x: Option<f64>,
+    /// Factor to scale layers by top to bottom on the panel. Defaults to 1
+    pub scale_y: Option<f64>,
+}
+
+impl DisplayConfig {
+    pub fn validate(&self) -> Result<(), io::Error> {
+        // Pixels are packed into whole bytes, in chunks of up to 64 bits
+        let bits_per_chunk: u32 = self.bit_depth.iter().map(|&bits| bits as u32).sum();
+        if self.bit_depth.iter().any(|bits| !(1..=8).contains(bits))
+            || bits_per_chunk == 0
+            || bits_per_chunk > 64
+            || !bits_per_chunk.is_multiple_of(8)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "bit_depth must be depths of 1 to 8 bits adding up to whole bytes, got {:?}",
+                    self.bit_depth
+                ),
+            ));
+        }
+
+        // DRM pixels hold one chunk each, in the formats the kernel knows
+        if self.backend == Some(DisplayBackend::Drm)
+            && (![8, 16, 24, 32].contains(&bits_per_chunk)
+                || !self
+                    .screen_width
+                    .is_multiple_of(self.bit_depth.len() as u32))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The drm backend needs bit_depth adding up to 8, 16, 24 or 32 bits and a screen_width divisible by its length, got {:?} and {}",
+                    self.bit_depth, self.screen_width
+                ),
+            ));
+        }
+
+        for (name, scale) in [("scale_x", self.scale_x), ("scale_y", self.scale_y)] {
+            if let Some(scale) = scale {
+                if !(scale > 0.0 && scale.is_finite()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} must be positive, got {}", name, scale),
+                    ));
+                }
+            }
+        }
+
+        if let Some(overlay) = &self.status_overlay {
+            if overlay.row_offset as u64 + overlay.height as u64 > self.screen_height as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "status_overlay rows {} to {} are beyond screen_height {}",
+                        overlay.row_offset,
+                        overlay.row_offset as u64 + overlay.height as u64,
+                        self.screen_height
+                    ),
+                ));
+            }
+        }
+
+        match self.rotation {
+            None | Some(0 | 90 | 180 | 270) => Ok(()),
+            Some(rotation) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("rotation must be 0, 90, 180 or 270, got {}", rotation),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum DisplayBackend {
+    /// Legacy framebuffer device, such as /dev/fb0
+    #[default]
+    Fbdev,
+    /// Dumb buffer scanned out through DRM/KMS, for kernels without fbdev
+    Drm,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum DitherMode {
+    #[default]
+    None,
+    FloydSteinberg,
+    Ordered,
+}
+
+/// Region of pixel rows outside of the print area, used to show print progress
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Object)]
+pub struct StatusOverlayConfig {
+    pub row_offset: u32,
+    pub height: u32,
 }
 
 #[optional_struct(UpdateGcodeConfig)]
 #[derive(Clone, Debug, Serialize, Deserialize, Object)]
 pub struct GcodeConfig {
     pub boot: String,
+    /// Sent on shutdown, including after a hardware error, one line at a time.
+    /// cure_end is always sent for every zone first
     pub shutdown: String,
+    /// Sent on an emergency stop to halt the controller immediately, such as
+    /// M112. Curing is stopped in every zone either way
+    pub emergency_gcode: Option<String>,
     pub home_command: String,
     pub move_command: String,
     pub manual_move_command: Option<String>,
@@ -44,15 +387,377 @@ pub struct GcodeConfig {
     pub move_timeout: u64,
     pub status_check: String,
     pub status_desired: String,
+    /// Wait for the firmware to echo back moves and cure commands before
+    /// proceeding, failing if the echo doesn't match what was sent
+    pub verify_echo: Option<bool>,
+    /// Command which reports temperature, sent periodically when set
+    pub temperature_check: Option<String>,
+    /// Pattern for extracting the temperature from the temperature_check
+    /// response, taken from the first capture group if there is one
+    pub temperature_regex: Option<String>,
+    /// Command which reports the controller's firmware version, sent once at
+    /// boot when set
+    pub firmware_version_command: Option<String>,
+    /// Pattern for extracting the firmware version from the
+    /// firmware_version_command response, taken from the first capture group if
+    /// there is one. Defaults to the FIRMWARE_NAME and following fields of an
+    /// M115 response
+    pub firmware_version_regex: Option<String>,
+    pub boot_sync: Option<CommandSync>,
+    pub home_sync: Option<CommandSync>,
+    pub print_start_sync: Option<CommandSync>,
+    pub print_end_sync: Option<CommandSync>,
+    /// Awaited after each line of the shutdown sequence
+    pub shutdown_sync: Option<CommandSync>,
+    /// Independently controlled UV zones. When unset, cure_start and cure_end
+    /// drive a single zone
+    pub cure_zones: Option<Vec<CureZone>>,
+    /// Reusable gcode sequences, referenced by name as {{name}} in any other
+    /// gcode, including other macros. Macros are expanded before substitution,
+    /// so may themselves use {variable} substitutions such as {z}
+    pub macros: Option<HashMap<String, String>>,
+    /// Sent after each line of gcode. Defaults to \r\n
+    pub line_ending: Option<String>,
+    /// Prefix each line with an incrementing line number and append its
+    /// checksum, as Marlin style firmware uses to detect corrupted lines and
+    /// request they're resent. Defaults to false
+    pub use_line_numbers: Option<bool>,
+}
+
+/// Name used for the single zone driven by cure_start and cure_end
+pub static DEFAULT_CURE_ZONE: &str = "default";
+
+impl GcodeConfig {
+    /// Check every macro reference resolves, without looping forever
+    pub fn validate(&self) -> Result<(), io::Error> {
+        let macros = self.macros.clone().unwrap_or_default();
+        let templates = [
+            Some(&self.boot),
+            Some(&self.shutdown),
+            self.emergency_gcode.as_ref(),
+            Some(&self.home_command),
+            Some(&self.move_command),
+            self.manual_move_command.as_ref(),
+            Some(&self.print_start),
+            Some(&self.print_end),
+            Some(&self.layer_start),
+            Some(&self.status_check),
+            self.temperature_check.as_ref(),
+            self.firmware_version_command.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .chain(
+            self.cure_zones()
+                .into_iter()
+                .flat_map(|zone| [zone.cure_start, zone.cure_end]),
+        )
+        .chain(macros.values().cloned());
+
+        for template in templates {
+            expand_macros(&template, &macros)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        }
+        Ok(())
+    }
+
+    /// All configured cure zones, or a single zone using cure_start and cure_end
+    pub fn cure_zones(&self) -> Vec<CureZone> {
+        match &self.cure_zones {
+            Some(zones) if !zones.is_empty() => zones.clone(),
+            _ => vec![CureZone {
+                name: DEFAULT_CURE_ZONE.to_string(),
+                cure_start: self.cure_start.clone(),
+                cure_end: self.cure_end.clone(),
+            }],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Object)]
+pub struct CureZone {
+    pub name: String,
+    pub cure_start: String,
+    pub cure_end: String,
+}
+
+/// Acknowledgment to wait for after sending a command, so the statemachine
+/// doesn't race ahead of the hardware
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Object)]
+pub struct CommandSync {
+    /// Text which the response must contain
+    pub sync: String,
+    /// Seconds to wait for the response, defaulting to move_timeout
+    pub timeout: Option<u64>,
 }
 
 #[optional_struct(UpdateApiConfig)]
-#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Object)]
 pub struct ApiConfig {
     pub upload_path: String,
     pub usb_glob: String,
     pub port: u16,
     pub enable_docs: Option<bool>,
+    /// Serve a subset of Moonraker's API, so Klipper frontends such as Fluidd
+    /// can drive prints. Defaults to false
+    pub enable_moonraker: Option<bool>,
+    pub max_concurrent_extractions: Option<usize>,
+    pub max_queued_extractions: Option<usize>,
+    /// Most operations which may be waiting for the printer to handle them
+    /// before further requests are refused. Defaults to 100
+    pub max_queued_operations: Option<usize>,
+    /// Most manual moves or homes accepted per second, beyond which requests
+    /// are refused. Defaults to 5
+    pub max_manual_moves_per_second: Option<f64>,
+    /// Seconds after the UV array is manually switched before it may be
+    /// switched on again, so it can't be strobed. Defaults to 0.5
+    pub manual_cure_debounce_seconds: Option<f64>,
+    /// Key required in the X-Api-Key header, or as a bearer token, by any
+    /// request which changes the printer's state. Reads stay open, and without
+    /// a key nothing is protected
+    pub api_key: Option<String>,
+    /// Further directories print files may be uploaded to and printed from,
+    /// such as a network share, selected by label. upload_path always comes
+    /// first, labelled "default", and is used when no label is given
+    pub upload_directories: Option<Vec<PrintUploadDirectory>>,
+}
+
+/// A directory print files may be uploaded to and printed from
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Object)]
+pub struct PrintUploadDirectory {
+    pub label: String,
+    pub path: String,
+    /// Whether the directory is a mounted network share, which may be slow or
+    /// unavailable. Defaults to Local
+    pub kind: Option<UploadDirectoryKind>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum UploadDirectoryKind {
+    #[default]
+    Local,
+    Network,
+}
+
+impl PrintUploadDirectory {
+    pub fn is_network(&self) -> bool {
+        self.kind.unwrap_or_default() == UploadDirectoryKind::Network
+    }
+
+    /// Join a client-supplied relative path onto the directory, rejecting
+    /// anything that could escape it
+    pub fn resolve(&self, relative_path: &Path) -> Result<PathBuf, io::Error> {
+        let escapes_root = relative_path
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+
+        if escapes_root || relative_path.as_os_str().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid upload path: {}", relative_path.display()),
+            ));
+        }
+
+        Ok(Path::new(&self.path).join(relative_path))
+    }
+
+    /// Move a file or directory within this directory, never replacing
+    /// anything already at the destination
+    pub fn move_file(&self, source: &Path, destination: &Path) -> Result<(), io::Error> {
+        let (source, destination) = self.resolve_transfer(source, destination)?;
+        fs::rename(source, destination)
+    }
+
+    /// Copy a file within this directory, never replacing anything already at
+    /// the destination
+    pub fn copy_file(&self, source: &Path, destination: &Path) -> Result<(), io::Error> {
+        let (source, destination) = self.resolve_transfer(source, destination)?;
+        if source.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only files can be copied",
+            ));
+        }
+        fs::copy(source, destination).map(|_| ())
+    }
+
+    fn resolve_transfer(
+        &self,
+        source: &Path,
+        destination: &Path,
+    ) -> Result<(PathBuf, PathBuf), io::Error> {
+        let source = self.resolve(source)?;
+        let destination = self.resolve(destination)?;
+
+        if !source.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} doesn't exist", source.display()),
+            ));
+        }
+        if destination.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", destination.display()),
+            ));
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        Ok((source, destination))
+    }
+}
+
+/// Highest max_z in mm which is plausible for a resin printer
+pub const MAX_PLAUSIBLE_Z: f64 = 1000.0;
+
+/// Thickest layer in microns which is plausible for a resin printer
+pub const MAX_PLAUSIBLE_LAYER_HEIGHT_MICRONS: u32 = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum ProblemSeverity {
+    /// The configuration would be refused
+    Error,
+    /// Likely to fail once the setting is used
+    Warning,
+}
+
+/// Something found wrong with a setting by Configuration::validate
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct ConfigProblem {
+    pub field: String,
+    pub message: String,
+    pub severity: ProblemSeverity,
+}
+
+impl ConfigProblem {
+    fn error(field: &str, message: impl Into<String>) -> ConfigProblem {
+        ConfigProblem {
+            field: field.to_string(),
+            message: message.into(),
+            severity: ProblemSeverity::Error,
+        }
+    }
+
+    fn warning(field: &str, message: impl Into<String>) -> ConfigProblem {
+        ConfigProblem {
+            field: field.to_string(),
+            message: message.into(),
+            severity: ProblemSeverity::Warning,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == ProblemSeverity::Error
+    }
+}
+
+/// Fail with every error among the problems, as the configuration they were
+/// found in would be refused
+pub fn refuse_errors(problems: &[ConfigProblem]) -> Result<(), io::Error> {
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.is_error())
+        .map(|problem| format!("{}: {}", problem.field, problem.message))
+        .collect::<Vec<_>>();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        errors.join("; "),
+    ))
+}
+
+/// Why the path can't be written to, if it can't
+fn check_writable(path: &str) -> Option<String> {
+    if !Path::new(path).exists() {
+        return Some(format!("{} doesn't exist", path));
+    }
+    access(Path::new(path), AccessFlags::W_OK)
+        .err()
+        .map(|e| format!("{} isn't writable: {}", path, e))
+}
+
+/// Label of the upload directory configured by upload_path
+pub static DEFAULT_UPLOAD_DIRECTORY: &str = "default";
+
+impl ApiConfig {
+    /// Check each configured upload directory has a unique label, and exists
+    /// as a writable directory
+    pub fn validate(&self) -> Result<(), io::Error> {
+        let mut labels = vec![DEFAULT_UPLOAD_DIRECTORY];
+        for directory in self.upload_directories.iter().flatten() {
+            if labels.contains(&directory.label.as_str()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Upload directory label {} is used twice", directory.label),
+                ));
+            }
+            labels.push(&directory.label);
+
+            // A share which is down shouldn't keep the printer from starting,
+            // it's reported as unavailable until it's back
+            if directory.is_network() {
+                if !Path::new(&directory.path).is_dir() {
+                    tracing::warn!(
+                        "Network upload directory {} at {} is unavailable",
+                        directory.label,
+                        directory.path
+                    );
+                }
+                continue;
+            }
+
+            if !Path::new(&directory.path).is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "Upload directory {} at {} doesn't exist",
+                        directory.label, directory.path
+                    ),
+                ));
+            }
+            access(Path::new(&directory.path), AccessFlags::W_OK).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "Upload directory {} at {} isn't writable: {}",
+                        directory.label, directory.path, e
+                    ),
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Every upload directory, starting with the default upload_path
+    pub fn print_upload_dirs(&self) -> Vec<PrintUploadDirectory> {
+        let default = PrintUploadDirectory {
+            label: DEFAULT_UPLOAD_DIRECTORY.to_string(),
+            path: self.upload_path.clone(),
+            kind: None,
+        };
+
+        std::iter::once(default)
+            .chain(self.upload_directories.iter().flatten().cloned())
+            .collect()
+    }
+
+    /// The upload directory with the given label, or the default upload_path
+    /// if no label is given
+    pub fn get_print_upload_dir(&self, label: Option<&str>) -> Option<PrintUploadDirectory> {
+        let label = label.unwrap_or(DEFAULT_UPLOAD_DIRECTORY);
+        self.print_upload_dirs()
+            .into_iter()
+            .find(|directory| directory.label == label)
+    }
 }
 
 impl Default for ApiConfig {
@@ -62,10 +767,34 @@ impl Default for ApiConfig {
             usb_glob: "".to_string(),
             port: 12357,
             enable_docs: Some(false),
+            enable_moonraker: Some(false),
+            max_concurrent_extractions: Some(2),
+            max_queued_extractions: Some(16),
+            max_queued_operations: Some(100),
+            max_manual_moves_per_second: Some(5.0),
+            manual_cure_debounce_seconds: Some(0.5),
+            api_key: None,
+            upload_directories: None,
         }
     }
 }
 
+/// Connection to an MQTT broker, for publishing printer state to home
+/// automation systems. Only used when built with the mqtt feature
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Object)]
+pub struct MqttConfig {
+    pub host: String,
+    /// Defaults to 1883
+    pub port: Option<u16>,
+    /// Prefix for the status, layer, progress and command topics. Defaults to
+    /// odyssey
+    pub topic_prefix: Option<String>,
+    /// Defaults to odyssey
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
 #[optional_struct(UpdateConfiguration)]
 #[derive(Clone, Debug, Serialize, Deserialize, Object)]
 pub struct Configuration {
@@ -85,6 +814,18 @@ pub struct Configuration {
     #[optional_rename(UpdateDisplayConfig)]
     pub display: DisplayConfig,
 
+    /// Named resin profiles which can be selected when starting a print
+    #[serde(default)]
+    pub resin_profiles: HashMap<String, ResinProfile>,
+
+    /// Resin profile applied to prints started from each directory, relative
+    /// to the upload or USB directory, when none is selected. Files in a
+    /// subdirectory take the profile of their nearest mapped directory
+    #[serde(default)]
+    pub directory_profiles: HashMap<String, String>,
+
+    pub mqtt: Option<MqttConfig>,
+
     #[serde(skip_serializing)]
     pub config_file: Option<String>,
 }
@@ -94,10 +835,144 @@ impl Configuration {
         let mut config: Configuration =
             serde_yaml::from_reader(io::BufReader::new(fs::File::open(&config_file)?))?;
         config.config_file = Some(config_file);
+        refuse_errors(&config.validate())?;
 
         Ok(config)
     }
 
+    /// Every problem found with the settings. Errors would have the
+    /// configuration refused, while warnings are settings which parse but are
+    /// likely to fail once they're used, such as paths which don't exist, and
+    /// don't stop Odyssey starting
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        let mut problems: Vec<ConfigProblem> = [
+            ("printer", self.printer.validate()),
+            ("display", self.display.validate()),
+            ("gcode", self.gcode.validate()),
+            ("api", self.api.validate()),
+            ("directory_profiles", self.validate_directory_profiles()),
+        ]
+        .into_iter()
+        .filter_map(|(field, result)| {
+            result
+                .err()
+                .map(|e| ConfigProblem::error(field, e.to_string()))
+        })
+        .collect();
+        problems.extend(self.warnings());
+        problems
+    }
+
+    fn validate_directory_profiles(&self) -> Result<(), io::Error> {
+        for (directory, profile) in &self.directory_profiles {
+            if !self.resin_profiles.contains_key(profile) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Directory {} is mapped to unknown resin profile {}",
+                        directory, profile
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn warnings(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+
+        if !Path::new(&self.printer.serial).exists() {
+            problems.push(ConfigProblem::warning(
+                "printer.serial",
+                format!("Serial port {} doesn't exist", self.printer.serial),
+            ));
+        }
+        if !(self.printer.max_z > 0.0 && self.printer.max_z <= MAX_PLAUSIBLE_Z) {
+            problems.push(ConfigProblem::warning(
+                "printer.max_z",
+                format!(
+                    "max_z of {}mm is outside the plausible range of 0 to {}mm",
+                    self.printer.max_z, MAX_PLAUSIBLE_Z
+                ),
+            ));
+        }
+        if let Some(backlash) = self
+            .printer
+            .backlash_microns
+            .filter(|&backlash| backlash > MAX_PLAUSIBLE_LAYER_HEIGHT_MICRONS)
+        {
+            problems.push(ConfigProblem::warning(
+                "printer.backlash_microns",
+                format!(
+                    "backlash of {}µm is larger than the plausible layer height of {}µm",
+                    backlash, MAX_PLAUSIBLE_LAYER_HEIGHT_MICRONS
+                ),
+            ));
+        }
+
+        for (field, path) in [
+            ("api.upload_path", &self.api.upload_path),
+            ("display.frame_buffer", &self.display.frame_buffer),
+        ] {
+            if let Some(message) = check_writable(path) {
+                problems.push(ConfigProblem::warning(field, message));
+            }
+        }
+
+        if self.display.screen_width == 0 || self.display.screen_height == 0 {
+            problems.push(ConfigProblem::warning(
+                "display",
+                "screen_width and screen_height must both be above 0",
+            ));
+        }
+
+        let gcode = &self.gcode;
+        let mut required_gcode = vec![
+            ("gcode.home_command", &gcode.home_command),
+            ("gcode.move_command", &gcode.move_command),
+            ("gcode.move_sync", &gcode.move_sync),
+            ("gcode.status_check", &gcode.status_check),
+        ];
+        // Separately controlled zones replace the single zone's commands
+        if gcode.cure_zones.is_none() {
+            required_gcode.push(("gcode.cure_start", &gcode.cure_start));
+            required_gcode.push(("gcode.cure_end", &gcode.cure_end));
+        }
+        for (field, gcode) in required_gcode {
+            if gcode.trim().is_empty() {
+                problems.push(ConfigProblem::warning(field, "Required gcode is empty"));
+            }
+        }
+
+        problems
+    }
+
+    /// Name of the resin profile mapped to the directory holding the file at
+    /// the given path, or to its nearest mapped parent directory
+    pub fn directory_profile(&self, file_path: &str) -> Option<&String> {
+        Path::new(file_path)
+            .ancestors()
+            .skip(1)
+            .find_map(|directory| {
+                self.directory_profiles
+                    .iter()
+                    .find_map(|(mapped, profile)| {
+                        (Path::new(mapped.trim_matches('/')) == directory).then_some(profile)
+                    })
+            })
+    }
+
+    /// Copy of the configuration without secrets, safe to return from
+    /// endpoints which don't require the API key
+    pub fn redacted(&self) -> Configuration {
+        let mut config = self.clone();
+        config.api.api_key = None;
+        if let Some(mqtt) = config.mqtt.as_mut() {
+            mqtt.password = None;
+        }
+        config
+    }
+
     pub fn overwrite_file(config: &Configuration) -> Result<(), Box<dyn Error + Send + Sync>> {
         if let Some(config_file) = &config.config_file.clone() {
             Configuration::write_to_file(config_file, config)
@@ -110,11 +985,14 @@ impl Configuration {
             .into())
         }
     }
+    /// Write the config file, keeping a timestamped copy of the existing one.
+    /// The new file is written alongside and renamed into place, so the config
+    /// file is never left missing or half written
     pub fn write_to_file(
         config_file: &String,
         config: &Configuration,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let content = serde_yaml::to_string(&config).unwrap();
+        let content = serde_yaml::to_string(&config)?;
 
         let timestamp = std::time::SystemTime::now()
             .duration_since(std::time::SystemTime::UNIX_EPOCH)?
@@ -124,8 +1002,8 @@ impl Configuration {
 
         if fs::exists(config_file)? {
             let old_config = format!("{}.{}.old", config_file, timestamp);
-            log::info!("Moving existing config file to {}", old_config);
-            fs::rename(config_file, old_config).map_err(|err| {
+            log::info!("Copying existing config file to {}", old_config);
+            fs::copy(config_file, old_config).map_err(|err| {
                 io::Error::new(
                     err.kind(),
                     format!("Unable to backup existing config file {:?}", err),
@@ -133,10 +1011,29 @@ impl Configuration {
             })?;
         }
 
-        fs::write(config_file, content)?;
+        let temp_file = format!("{}.tmp", config_file);
+        fs::write(&temp_file, content)?;
+        fs::rename(&temp_file, config_file)?;
 
         Ok(())
     }
 }
 
+/// Print settings for a particular resin. Any values set take precedence over
+/// those from the print file, which in turn take precedence over the configured
+/// defaults
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Object)]
+pub struct ResinProfile {
+    pub lift: Option<f64>,
+    pub up_speed: Option<f64>,
+    pub down_speed: Option<f64>,
+    pub settle_time: Option<f64>,
+    pub wait_before_exposure: Option<f64>,
+    pub wait_after_exposure: Option<f64>,
+    /// Factor applied to every layer's exposure time
+    pub exposure_multiplier: Option<f64>,
+}
+
+pub type LockedProfiles = Arc<RwLock<HashMap<String, ResinProfile>>>;
+
 pub type LockedConfig = Arc<RwLock<Configuration>>;
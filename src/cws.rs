@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Error, Read},
+};
+
+use async_trait::async_trait;
+use itertools::Itertools;
+use regex::Regex;
+use zip::ZipArchive;
+
+use crate::{
+    api_objects::{FileData, FileMetadata, PrintMetadata},
+    printfile::{Layer, PrintFile},
+    thumbnail,
+};
+
+const GCODE_EXTENSION: &str = ".gcode";
+
+const LAYER_THICKNESS: &str = "Layer Thickness";
+const LAYER_TIME: &str = "Layer Time";
+const BOTTOM_LAYER_TIME: &str = "Bottom Layers Time";
+const BOTTOM_LAYER_COUNT: &str = "Number of Bottom Layers";
+const BLANKING_TIME: &str = "Blanking Layer Time";
+const LIFT_DISTANCE: &str = "Lift Distance";
+const LIFT_FEED_RATE: &str = "Z Lift Feed Rate";
+const LIFT_RETRACT_RATE: &str = "Z Lift Retract Rate";
+
+/// Slicing parameters read from the header comments of the gcode embedded in a
+/// `.cws` file, which take the form `;(Layer Thickness = 0.05000 mm )`
+#[derive(Debug)]
+pub struct CwsConfig {
+    layer_height: f64,
+    exp_time: f64,
+    exp_time_bottom: f64,
+    num_bottom: usize,
+    blanking_time: Option<f64>,
+    lift: Option<f64>,
+    up_speed: Option<f64>,
+    down_speed: Option<f64>,
+}
+
+impl CwsConfig {
+    /// Compute the exposure time of the given frame index
+    fn exposure_time(&self, index: usize) -> f64 {
+        if index < self.num_bottom {
+            self.exp_time_bottom
+        } else {
+            self.exp_time
+        }
+    }
+
+    /// Read the CwsConfig from the contents of the embedded gcode
+    fn from_gcode(contents: &str) -> Result<Self, io::Error> {
+        let re = Regex::new(r"^;\((?P<key>[^=]+?)\s*=\s*(?P<value>[0-9.]+)").unwrap();
+
+        let parameters: HashMap<String, f64> = contents
+            .lines()
+            .filter_map(|line| re.captures(line.trim()))
+            .filter_map(|caps| {
+                caps["value"]
+                    .parse::<f64>()
+                    .ok()
+                    .map(|value| (caps["key"].trim().to_string(), value))
+            })
+            .collect();
+
+        let required = |key: &str| {
+            parameters.get(key).copied().ok_or(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("CWS gcode missing required parameter: {key}"),
+            ))
+        };
+
+        // Times are stored in milliseconds
+        Ok(CwsConfig {
+            layer_height: required(LAYER_THICKNESS)?,
+            exp_time: required(LAYER_TIME)? / 1000.0,
+            exp_time_bottom: required(BOTTOM_LAYER_TIME)? / 1000.0,
+            num_bottom: required(BOTTOM_LAYER_COUNT)? as usize,
+            blanking_time: parameters.get(BLANKING_TIME).map(|time| time / 1000.0),
+            lift: parameters.get(LIFT_DISTANCE).copied(),
+            up_speed: parameters.get(LIFT_FEED_RATE).copied(),
+            down_speed: parameters.get(LIFT_RETRACT_RATE).copied(),
+        })
+    }
+}
+
+/// The sliced .cws-format (Creation Workshop) model, with the slicing parameters
+/// from the embedded gcode and the numbered PNG slices
+pub struct Cws {
+    config: CwsConfig,
+    archive: ZipArchive<File>,
+    frame_list: Vec<String>,
+    metadata: PrintMetadata,
+}
+
+impl Cws {
+    /// Slices are numbered without padding in some exports, so order them by
+    /// the trailing number rather than by name
+    fn slice_number(name: &str) -> Option<usize> {
+        let re = Regex::new(r"(?P<number>\d+)\.png$").unwrap();
+        re.captures(name)
+            .and_then(|caps| caps["number"].parse::<usize>().ok())
+    }
+}
+
+#[async_trait]
+impl PrintFile for Cws {
+    /// Instantiate the Cws from the given file
+    fn from_file(file_data: FileMetadata) -> Result<Cws, io::Error> {
+        tracing::info!("Loading PrintFile from CWS {:?}", file_data);
+
+        let file = File::open(file_data.get_full_path())?;
+
+        let user_metadata = Cws::get_user_metadata(&file);
+
+        let mut archive = ZipArchive::new(file)?;
+
+        let gcode_name = archive
+            .file_names()
+            .find(|name| name.ends_with(GCODE_EXTENSION))
+            .map(String::from)
+            .ok_or(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "CWS file contains no gcode",
+            ))?;
+
+        let mut gcode_contents = String::new();
+        archive
+            .by_name(gcode_name.as_str())?
+            .read_to_string(&mut gcode_contents)?;
+
+        let config = CwsConfig::from_gcode(&gcode_contents)?;
+
+        let frame_list: Vec<String> = archive
+            .file_names()
+            .filter(|name| name.ends_with(".png") && !name.contains('/'))
+            .filter_map(|name| Cws::slice_number(name).map(|number| (number, name.to_string())))
+            .sorted()
+            .map(|(_, name)| name)
+            .collect();
+
+        let layer_count = frame_list.len();
+        let print_time = (0..layer_count)
+            .map(|index| config.exposure_time(index) + config.blanking_time.unwrap_or(0.0))
+            .sum();
+
+        let metadata = PrintMetadata {
+            file_data,
+            used_material: 0.0,
+            print_time,
+            layer_height: config.layer_height,
+            layer_height_microns: ((config.layer_height * 1000.0).trunc() as u32),
+            layer_count,
+            user_metadata,
+            estimated_print_time: None,
+        };
+
+        Ok(Cws {
+            config,
+            archive,
+            frame_list,
+            metadata,
+        })
+    }
+
+    async fn get_layer_data(&mut self, index: usize) -> Option<Layer> {
+        if index < self.frame_list.len() {
+            let frame_file = self.archive.by_name(self.frame_list[index].as_str());
+
+            if let Ok(mut frame_file) = frame_file {
+                let mut ret: Vec<u8> = Vec::new();
+
+                // A corrupt layer is treated as missing, rather than taking
+                // down the print
+                if let Err(e) = frame_file.read_to_end(&mut ret) {
+                    tracing::error!(
+                        "Error reading layer {} from archive: {}",
+                        self.frame_list[index],
+                        e
+                    );
+                    return None;
+                }
+
+                return Some(Layer {
+                    file_name: self.frame_list[index].clone(),
+                    data: ret,
+                    exposure_time: self.config.exposure_time(index),
+                });
+            }
+        }
+        None
+    }
+
+    fn get_layer_count(&self) -> usize {
+        self.frame_list.len()
+    }
+
+    fn get_exposure_time(&self, index: usize) -> f64 {
+        self.config.exposure_time(index)
+    }
+
+    fn get_layer_height(&self) -> u32 {
+        (self.config.layer_height * 1000.0).trunc() as u32
+    }
+
+    fn get_metadata(&self) -> PrintMetadata {
+        self.metadata.clone()
+    }
+
+    fn get_thumbnail(&mut self, width: u32, height: u32) -> Result<FileData, Error> {
+        // CWS files don't embed thumbnails
+        let (width, height) = thumbnail::clamp_dimensions(width, height);
+        thumbnail::placeholder_thumbnail(width, height)
+    }
+
+    fn get_lift(&self) -> Option<u32> {
+        self.config.lift.map(|lift| (lift * 1000.0).trunc() as u32)
+    }
+
+    fn get_up_speed(&self) -> Option<f64> {
+        self.config.up_speed
+    }
+
+    fn get_down_speed(&self) -> Option<f64> {
+        self.config.down_speed
+    }
+
+    fn get_wait_before_exposure(&self) -> Option<f64> {
+        self.config.blanking_time
+    }
+}
@@ -1,42 +1,150 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Cursor},
+    os::unix::fs::FileTypeExt,
+    sync::{Arc, RwLock},
+};
+
 use framebuffer::Framebuffer;
+use image::{GrayImage, ImageFormat};
 use png::Decoder;
 
 use crate::{
-    api_objects::DisplayTest, configuration::DisplayConfig, wrapped_framebuffer::WrappedFramebuffer,
+    api_objects::{DisplayTest, ExposureTestZone},
+    configuration::{DisplayBackend, DisplayConfig, DitherMode},
+    drm_display::DrmDisplay,
+    error::OdysseyError,
+    status_overlay,
+    wrapped_framebuffer::WrappedFramebuffer,
 };
 
+const DEFAULT_GRID_PITCH: u32 = 100;
+const DIMENSIONS_LINE_WIDTH: usize = 4;
+const DIMENSIONS_SQUARE_SIZE: usize = 1000;
+
+// Thresholds used by ordered dithering, out of 16
+const BAYER_MATRIX: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
 #[derive(Clone)]
 pub struct Frame {
     pub file_name: String,
     pub buffer: Vec<u8>,
     pub exposure_time: f64,
     pub bit_depth: u8,
+    pub width: u32,
+    pub height: u32,
 }
 
 impl Frame {
-    pub fn from_vec(name: String, exposure_time: f64, data: Vec<u8>) -> Frame {
+    pub fn from_vec(
+        name: String,
+        exposure_time: f64,
+        data: Vec<u8>,
+    ) -> Result<Frame, OdysseyError> {
         let decoder = Decoder::new(data.as_slice());
 
-        let mut png_reader = decoder.read_info().expect("Unable to read PNG metadata");
+        let mut png_reader = decoder
+            .read_info()
+            .map_err(|err| OdysseyError::file_error(Box::new(err), 400))?;
 
         let mut f = Frame {
             file_name: name,
             buffer: vec![0; png_reader.output_buffer_size()],
             exposure_time,
             bit_depth: png_reader.info().bit_depth as u8,
+            width: png_reader.info().width,
+            height: png_reader.info().height,
         };
 
         png_reader
             .next_frame(f.buffer.as_mut())
-            .expect("Error reading PNG");
+            .map_err(|err| OdysseyError::file_error(Box::new(err), 400))?;
+
+        Ok(f)
+    }
+
+    /// Count the pixels in this frame which are lit at all during exposure
+    pub fn exposed_pixels(&self) -> usize {
+        match self.bit_depth {
+            8 => self.buffer.iter().filter(|&&value| value > 0).count(),
+            16 => self
+                .buffer
+                .as_chunks::<2>()
+                .0
+                .iter()
+                .filter(|sample| sample.iter().any(|&byte| byte > 0))
+                .count(),
+            // Sub-byte depths (1, 2 or 4 bits) pack several pixels per byte
+            bit_depth => {
+                let bits = bit_depth as usize;
+                let mask = (1u8 << bits) - 1;
+                self.buffer
+                    .iter()
+                    .map(|byte| {
+                        (0..8 / bits)
+                            .filter(|i| (byte >> (i * bits)) & mask > 0)
+                            .count()
+                    })
+                    .sum()
+            }
+        }
+    }
+}
 
-        f
+/// Width in pixels of the preview generated for each displayed frame
+const PREVIEW_WIDTH: usize = 320;
+
+/// Downscaled PNG of the last frame sent to the display, shared with the API so
+/// prints can be monitored remotely
+#[derive(Clone, Default)]
+pub struct DisplayPreview(Arc<RwLock<Option<Vec<u8>>>>);
+
+impl DisplayPreview {
+    pub fn get(&self) -> Option<Vec<u8>> {
+        self.0.read().ok().and_then(|preview| preview.clone())
+    }
+
+    fn set(&self, preview: Option<Vec<u8>>) {
+        if let Ok(mut current) = self.0.write() {
+            *current = preview;
+        }
     }
+
+    /// Sample an 8-bit grayscale buffer of the given width down to the preview
+    /// size, and encode it as a PNG
+    fn render(buffer: &[u8], width: usize) -> Option<Vec<u8>> {
+        let height = buffer.len().checked_div(width)?;
+        let preview_width = width.min(PREVIEW_WIDTH);
+        let preview_height = (height * preview_width / width).max(1);
+
+        let preview = GrayImage::from_fn(preview_width as u32, preview_height as u32, |x, y| {
+            let source_x = x as usize * width / preview_width;
+            let source_y = y as usize * height / preview_height;
+            image::Luma([buffer[source_y * width + source_x]])
+        });
+
+        let mut data = Vec::new();
+        preview
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .ok()?;
+        Some(data)
+    }
+}
+
+/// Somewhere packed frames, already encoded to the configured bit layout, can
+/// be written to be shown on the panel
+pub trait DisplayDevice: Send {
+    fn write_frame(&mut self, frame: &[u8]) -> Result<(), io::Error>;
+
+    /// Whether frames reach a real display, rather than a stand-in file
+    fn is_device(&self) -> bool;
 }
 
 pub struct PrintDisplay {
-    pub frame_buffer: WrappedFramebuffer,
+    pub frame_buffer: Box<dyn DisplayDevice>,
     pub config: DisplayConfig,
+    pub status_text: Option<String>,
+    pub preview: DisplayPreview,
 }
 
 impl PrintDisplay {
@@ -45,6 +153,17 @@ impl PrintDisplay {
             return buffer;
         }
 
+        let buffer = match self.config.dithering.unwrap_or_default() {
+            DitherMode::None => buffer,
+            // Dithering works on one byte per pixel, so only 8-bit frames are supported
+            _ if bit_depth != 8 => {
+                tracing::debug!("Skipping dithering for {}-bit frame", bit_depth);
+                buffer
+            }
+            DitherMode::FloydSteinberg => self.dither_floyd_steinberg(buffer),
+            DitherMode::Ordered => self.dither_ordered(buffer),
+        };
+
         let chunk_size: u8 = self.config.bit_depth.iter().sum(); //8
         let pixels_per_chunk = self.config.bit_depth.len(); //1
         tracing::info!("Re-encoding frame with bit-depth {} into {} pixels in {} bits, with the following bit layout: {:?}", bit_depth, pixels_per_chunk, chunk_size, self.config.bit_depth);
@@ -57,13 +176,12 @@ impl PrintDisplay {
                 // raw binary chunk of pixels, to be broken into bytes and repacked in the Vector later
                 let mut raw_chunk = 0b0;
                 let mut pos_shift = chunk_size;
-                for i in 0..pixels_per_chunk {
-                    let depth_difference = bit_depth - self.config.bit_depth[i];
-                    pos_shift -= self.config.bit_depth[i];
+                for (pixel, pixel_depth) in pixel_chunk.iter().zip(self.config.bit_depth.iter()) {
+                    let depth_difference = bit_depth - pixel_depth;
+                    pos_shift -= pixel_depth;
 
                     // Truncate the pixel data to the display's bit depth, then shift it into place in the raw chunk
-                    let shifted_pixel: u64 =
-                        ((pixel_chunk[i] as u64) >> depth_difference) << (pos_shift);
+                    let shifted_pixel: u64 = ((*pixel as u64) >> depth_difference) << (pos_shift);
                     raw_chunk |= shifted_pixel;
                 }
 
@@ -77,23 +195,432 @@ impl PrintDisplay {
         new_buffer
     }
 
-    pub fn display_frame(&mut self, frame: Frame) {
-        self.display_bytes(frame.buffer, frame.bit_depth);
+    // Number of levels below full brightness the display can show for the pixel
+    // at the given index, or None if it needs no reduction from 8 bits
+    fn dither_levels(&self, index: usize) -> Option<f32> {
+        let depth = self.config.bit_depth[index % self.config.bit_depth.len()];
+        (depth < 8).then(|| ((1u32 << depth) - 1) as f32)
+    }
+
+    // Store the quantized level in the top bits of the pixel, where the
+    // truncation in re_encode will pick it up unchanged
+    fn dither_store(value: f32, levels: f32) -> (u8, f32) {
+        let level = (value * levels / 255.0).round().clamp(0.0, levels);
+        let shift = 8 - (levels as u32 + 1).ilog2();
+        let displayed = level * 255.0 / levels;
+        (((level as u32) << shift) as u8, value - displayed)
+    }
+
+    fn dither_floyd_steinberg(&self, buffer: Vec<u8>) -> Vec<u8> {
+        let width = self.config.screen_width as usize;
+        if width == 0 {
+            return buffer;
+        }
+        let height = buffer.len() / width;
+
+        let mut values: Vec<f32> = buffer.iter().map(|&pixel| pixel as f32).collect();
+        let mut dithered = buffer;
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let Some(levels) = self.dither_levels(index) else {
+                    continue;
+                };
+                let (pixel, error) = Self::dither_store(values[index], levels);
+                dithered[index] = pixel;
+
+                // Push the quantization error onto the neighbouring pixels
+                // which haven't been quantized yet
+                if x + 1 < width {
+                    values[index + 1] += error * 7.0 / 16.0;
+                }
+                if y + 1 < height {
+                    if x > 0 {
+                        values[index + width - 1] += error * 3.0 / 16.0;
+                    }
+                    values[index + width] += error * 5.0 / 16.0;
+                    if x + 1 < width {
+                        values[index + width + 1] += error / 16.0;
+                    }
+                }
+            }
+        }
+
+        dithered
     }
 
-    fn display_bytes(&mut self, buffer: Vec<u8>, bit_depth: u8) {
-        self.frame_buffer
-            .write_frame(&self.re_encode(buffer, bit_depth));
+    fn dither_ordered(&self, buffer: Vec<u8>) -> Vec<u8> {
+        let width = self.config.screen_width as usize;
+        if width == 0 {
+            return buffer;
+        }
+
+        let mut dithered = buffer;
+        for (index, pixel) in dithered.iter_mut().enumerate() {
+            let Some(levels) = self.dither_levels(index) else {
+                continue;
+            };
+            let threshold = BAYER_MATRIX[(index / width) % 4][(index % width) % 4] as f32 / 16.0;
+            let offset = (threshold - 0.5 + 1.0 / 32.0) * 255.0 / levels;
+            *pixel = Self::dither_store(*pixel as f32 + offset, levels).0;
+        }
+
+        dithered
+    }
+
+    // Bytes per pixel of a frame, for operations which move whole pixels
+    fn bytes_per_pixel(bit_depth: u8, operation: &str) -> Result<usize, OdysseyError> {
+        if !bit_depth.is_multiple_of(8) {
+            return Err(OdysseyError::configuration_error(
+                Box::new(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unable to {} {}-bit frame", operation, bit_depth),
+                )),
+                400,
+            ));
+        }
+        Ok(bit_depth as usize / 8)
+    }
+
+    fn check_frame_len(
+        buffer: &[u8],
+        width: usize,
+        height: usize,
+        bytes_per_pixel: usize,
+    ) -> Result<(), OdysseyError> {
+        let expected_len = width * height * bytes_per_pixel;
+        if buffer.len() != expected_len {
+            return Err(OdysseyError::file_error(
+                Box::new(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Frame is {} bytes but {}x{} pixels needs {}",
+                        buffer.len(),
+                        width,
+                        height,
+                        expected_len
+                    ),
+                )),
+                400,
+            ));
+        }
+        Ok(())
+    }
+
+    fn rotates_or_mirrors(&self) -> bool {
+        self.config.rotation.unwrap_or(0) != 0
+            || self.config.mirror_x.unwrap_or(false)
+            || self.config.mirror_y.unwrap_or(false)
+    }
+
+    // Rotate and mirror a layer from the slicer's orientation into the panel's,
+    // returning it with its new width and height
+    fn transform(
+        &self,
+        buffer: Vec<u8>,
+        width: usize,
+        height: usize,
+        bit_depth: u8,
+    ) -> Result<(Vec<u8>, usize, usize), OdysseyError> {
+        if !self.rotates_or_mirrors() {
+            return Ok((buffer, width, height));
+        }
+
+        let rotation = self.config.rotation.unwrap_or(0);
+        let mirror_x = self.config.mirror_x.unwrap_or(false);
+        let mirror_y = self.config.mirror_y.unwrap_or(false);
+
+        let bytes_per_pixel = Self::bytes_per_pixel(bit_depth, "rotate or mirror")?;
+        Self::check_frame_len(&buffer, width, height, bytes_per_pixel)?;
+
+        let (rotated_width, rotated_height) = match rotation {
+            0 | 180 => (width, height),
+            90 | 270 => (height, width),
+            _ => {
+                return Err(OdysseyError::configuration_error(
+                    Box::new(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Unsupported display rotation {}", rotation),
+                    )),
+                    400,
+                ))
+            }
+        };
+
+        let mut transformed = vec![0; buffer.len()];
+        for y in 0..rotated_height {
+            for x in 0..rotated_width {
+                // Mirroring is in the panel's orientation, so undo it first
+                let unmirrored_x = if mirror_x { rotated_width - 1 - x } else { x };
+                let unmirrored_y = if mirror_y { rotated_height - 1 - y } else { y };
+
+                // Find the pixel in the layer which rotates onto this one
+                let (source_x, source_y) = match rotation {
+                    90 => (unmirrored_y, rotated_width - 1 - unmirrored_x),
+                    180 => (
+                        rotated_width - 1 - unmirrored_x,
+                        rotated_height - 1 - unmirrored_y,
+                    ),
+                    270 => (rotated_height - 1 - unmirrored_y, unmirrored_x),
+                    _ => (unmirrored_x, unmirrored_y),
+                };
+
+                let source = (source_y * width + source_x) * bytes_per_pixel;
+                let destination = (y * rotated_width + x) * bytes_per_pixel;
+                transformed[destination..destination + bytes_per_pixel]
+                    .copy_from_slice(&buffer[source..source + bytes_per_pixel]);
+            }
+        }
+
+        Ok((transformed, rotated_width, rotated_height))
+    }
+
+    // Scale a layer about its centre to correct the panel's measured scaling,
+    // keeping the layer's size. 8-bit layers are resampled bilinearly so
+    // anti-aliased edges survive, while other depths take the nearest pixel
+    fn apply_scale(
+        &self,
+        buffer: Vec<u8>,
+        width: usize,
+        height: usize,
+        bit_depth: u8,
+    ) -> Result<Vec<u8>, OdysseyError> {
+        let scale_x = self.config.scale_x.unwrap_or(1.0);
+        let scale_y = self.config.scale_y.unwrap_or(1.0);
+        if scale_x == 1.0 && scale_y == 1.0 {
+            return Ok(buffer);
+        }
+
+        let bytes_per_pixel = Self::bytes_per_pixel(bit_depth, "scale")?;
+        Self::check_frame_len(&buffer, width, height, bytes_per_pixel)?;
+
+        let center_x = width as f64 / 2.0;
+        let center_y = height as f64 / 2.0;
+
+        let mut scaled = vec![0; buffer.len()];
+        for y in 0..height {
+            // Map the centre of each panel pixel back into the unscaled layer
+            let source_y = (y as f64 + 0.5 - center_y) / scale_y + center_y - 0.5;
+            for x in 0..width {
+                let source_x = (x as f64 + 0.5 - center_x) / scale_x + center_x - 0.5;
+                let destination = (y * width + x) * bytes_per_pixel;
+
+                if bytes_per_pixel == 1 {
+                    scaled[destination] =
+                        Self::sample_bilinear(&buffer, width, height, source_x, source_y);
+                } else if let Some(source) =
+                    Self::pixel_index(width, height, source_x.round(), source_y.round())
+                {
+                    let source = source * bytes_per_pixel;
+                    scaled[destination..destination + bytes_per_pixel]
+                        .copy_from_slice(&buffer[source..source + bytes_per_pixel]);
+                }
+            }
+        }
+
+        Ok(scaled)
+    }
+
+    // Index of the pixel at the given position, if it lies within the layer
+    fn pixel_index(width: usize, height: usize, x: f64, y: f64) -> Option<usize> {
+        (x >= 0.0 && y >= 0.0 && x < width as f64 && y < height as f64)
+            .then(|| y as usize * width + x as usize)
+    }
+
+    // Blend the four 8-bit pixels around a position, treating anything beyond
+    // the edge of the layer as dark
+    fn sample_bilinear(buffer: &[u8], width: usize, height: usize, x: f64, y: f64) -> u8 {
+        let pixel = |x: f64, y: f64| {
+            Self::pixel_index(width, height, x, y).map_or(0.0, |index| buffer[index] as f64)
+        };
+        let (left, top) = (x.floor(), y.floor());
+        let (fraction_x, fraction_y) = (x - left, y - top);
+
+        let upper = pixel(left, top) * (1.0 - fraction_x) + pixel(left + 1.0, top) * fraction_x;
+        let lower =
+            pixel(left, top + 1.0) * (1.0 - fraction_x) + pixel(left + 1.0, top + 1.0) * fraction_x;
+
+        (upper * (1.0 - fraction_y) + lower * fraction_y).round() as u8
     }
 
-    pub fn display_test(&mut self, test: DisplayTest) {
+    // Limit an offset so the layer stays on the panel where possible
+    fn clamp_offset(name: &str, offset: u32, frame_size: usize, screen_size: usize) -> usize {
+        let max_offset = screen_size.saturating_sub(frame_size);
+        if offset as usize > max_offset {
+            tracing::warn!(
+                "{} of {} would push the layer past the edge of the panel, clamping to {}",
+                name,
+                offset,
+                max_offset
+            );
+            max_offset
+        } else {
+            offset as usize
+        }
+    }
+
+    // Place a layer at the configured offset on the panel, leaving the rest of
+    // the panel dark
+    fn apply_offset(
+        &self,
+        buffer: Vec<u8>,
+        width: usize,
+        height: usize,
+        bit_depth: u8,
+    ) -> Result<(Vec<u8>, usize, usize), OdysseyError> {
+        if self.config.offset_x.is_none() && self.config.offset_y.is_none() {
+            return Ok((buffer, width, height));
+        }
+
+        let bytes_per_pixel = Self::bytes_per_pixel(bit_depth, "offset")?;
+        Self::check_frame_len(&buffer, width, height, bytes_per_pixel)?;
+
+        let screen_width = self.config.screen_width as usize;
+        let screen_height = self.config.screen_height as usize;
+        let offset_x = Self::clamp_offset(
+            "offset_x",
+            self.config.offset_x.unwrap_or(0),
+            width,
+            screen_width,
+        );
+        let offset_y = Self::clamp_offset(
+            "offset_y",
+            self.config.offset_y.unwrap_or(0),
+            height,
+            screen_height,
+        );
+
+        let mut composited = vec![0; screen_width * screen_height * bytes_per_pixel];
+        let row_len = width.min(screen_width - offset_x) * bytes_per_pixel;
+        for y in 0..height.min(screen_height - offset_y) {
+            let source = y * width * bytes_per_pixel;
+            let destination = ((y + offset_y) * screen_width + offset_x) * bytes_per_pixel;
+            composited[destination..destination + row_len]
+                .copy_from_slice(&buffer[source..source + row_len]);
+        }
+
+        Ok((composited, screen_width, screen_height))
+    }
+
+    /// Check that a frame, once rotated, covers the panel exactly, or fits
+    /// within it when layers are offset into a region of the panel
+    pub fn check_frame(&self, frame: &Frame) -> Result<(), OdysseyError> {
+        let (width, height) = match self.config.rotation.unwrap_or(0) {
+            90 | 270 => (frame.height, frame.width),
+            _ => (frame.width, frame.height),
+        };
+        let screen_width = self.config.screen_width;
+        let screen_height = self.config.screen_height;
+
+        let fits = if self.config.offset_x.is_some() || self.config.offset_y.is_some() {
+            width <= screen_width && height <= screen_height
+        } else {
+            width == screen_width && height == screen_height
+        };
+
+        if fits {
+            Ok(())
+        } else {
+            Err(OdysseyError::file_error(
+                Box::new(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Layer {} is {}x{} pixels but the display is {}x{}",
+                        frame.file_name, frame.width, frame.height, screen_width, screen_height
+                    ),
+                )),
+                400,
+            ))
+        }
+    }
+
+    pub fn display_frame(&mut self, frame: Frame) -> Result<(), OdysseyError> {
+        self.check_frame(&frame)?;
+
+        let (buffer, width, height) = self.transform(
+            frame.buffer,
+            frame.width as usize,
+            frame.height as usize,
+            frame.bit_depth,
+        )?;
+        let buffer = self.apply_scale(buffer, width, height, frame.bit_depth)?;
+        let (mut buffer, _, _) = self.apply_offset(buffer, width, height, frame.bit_depth)?;
+
+        self.apply_status_overlay(&mut buffer, frame.bit_depth);
+        self.display_bytes(buffer, frame.bit_depth)
+    }
+
+    /// Set the text rendered into the status overlay region, if one is configured
+    pub fn set_status_text(&mut self, status_text: Option<String>) {
+        self.status_text = status_text;
+    }
+
+    fn apply_status_overlay(&self, buffer: &mut Vec<u8>, bit_depth: u8) {
+        if let (Some(overlay), Some(text)) = (&self.config.status_overlay, &self.status_text) {
+            // The overlay is drawn one byte per pixel, so only 8-bit frames are supported
+            if bit_depth != 8 {
+                tracing::debug!("Skipping status overlay for {}-bit frame", bit_depth);
+                return;
+            }
+            status_overlay::render_status(
+                buffer,
+                self.config.screen_width as usize,
+                self.config.screen_height as usize,
+                overlay,
+                text,
+            );
+        }
+    }
+
+    fn display_bytes(&mut self, buffer: Vec<u8>, bit_depth: u8) -> Result<(), OdysseyError> {
+        // Previews are sampled one byte per pixel, so only 8-bit frames are supported
+        self.preview.set(match bit_depth {
+            8 => DisplayPreview::render(&buffer, self.config.screen_width as usize),
+            _ => None,
+        });
+
+        self.write_bytes(buffer, bit_depth)
+    }
+
+    fn write_bytes(&mut self, buffer: Vec<u8>, bit_depth: u8) -> Result<(), OdysseyError> {
+        let encoded = self.re_encode(buffer, bit_depth);
+        let retries = self.config.write_retries.unwrap_or(1);
+
+        let mut attempt = 0;
+        loop {
+            match self.frame_buffer.write_frame(&encoded) {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < retries => {
+                    attempt += 1;
+                    tracing::warn!(
+                        "Framebuffer write failed, retrying ({}/{}): {}",
+                        attempt,
+                        retries,
+                        e
+                    );
+                }
+                Err(e) => return Err(OdysseyError::hardware_error(Box::new(e), 500)),
+            }
+        }
+    }
+
+    /// Turn every pixel off, such as between layers. The preview keeps showing
+    /// the last layer, as that's what was cured
+    pub fn display_blank(&mut self) -> Result<(), OdysseyError> {
+        let blank = self.display_test_blank();
+        self.write_bytes(blank, 8)
+    }
+
+    pub fn display_test(&mut self, test: DisplayTest) -> Result<(), OdysseyError> {
         let test_bytes = match test {
             DisplayTest::White => self.display_test_white(),
             DisplayTest::Blank => self.display_test_blank(),
-            _ => self.display_test_blank(),
+            DisplayTest::Grid => self.display_test_grid(),
+            DisplayTest::Dimensions => self.display_test_dimensions(),
         };
 
-        self.display_bytes(test_bytes, 8);
+        self.display_bytes(test_bytes, 8)
     }
 
     fn display_test_white(&mut self) -> Vec<u8> {
@@ -104,19 +631,188 @@ impl PrintDisplay {
         vec![0x00; (self.config.screen_width * self.config.screen_height) as usize]
     }
 
+    // Evenly spaced lines across the whole screen, including its last row and column
+    fn display_test_grid(&mut self) -> Vec<u8> {
+        let width = self.config.screen_width as usize;
+        let height = self.config.screen_height as usize;
+        let pitch = self.config.grid_pitch.unwrap_or(DEFAULT_GRID_PITCH).max(1) as usize;
+
+        let mut buffer = self.display_test_blank();
+        for y in 0..height {
+            for x in 0..width {
+                if x % pitch == 0 || y % pitch == 0 || x == width - 1 || y == height - 1 {
+                    buffer[y * width + x] = 0xFF;
+                }
+            }
+        }
+        buffer
+    }
+
+    // A border around the screen, a crosshair through its center, and a
+    // centered square outline of DIMENSIONS_SQUARE_SIZE pixels, for measuring
+    // XY scaling against the display's pixel pitch
+    fn display_test_dimensions(&mut self) -> Vec<u8> {
+        let width = self.config.screen_width as usize;
+        let height = self.config.screen_height as usize;
+
+        let mut buffer = self.display_test_blank();
+        let mut fill = |x_range: std::ops::Range<usize>, y_range: std::ops::Range<usize>| {
+            for y in y_range.start.min(height)..y_range.end.min(height) {
+                for x in x_range.start.min(width)..x_range.end.min(width) {
+                    buffer[y * width + x] = 0xFF;
+                }
+            }
+        };
+
+        let line = DIMENSIONS_LINE_WIDTH;
+
+        // Border
+        fill(0..width, 0..line);
+        fill(0..width, height.saturating_sub(line)..height);
+        fill(0..line, 0..height);
+        fill(width.saturating_sub(line)..width, 0..height);
+
+        // Crosshair
+        let (center_x, center_y) = (width / 2, height / 2);
+        fill(
+            0..width,
+            center_y.saturating_sub(line / 2)..center_y + line / 2,
+        );
+        fill(
+            center_x.saturating_sub(line / 2)..center_x + line / 2,
+            0..height,
+        );
+
+        // Square, with its outer edge measuring DIMENSIONS_SQUARE_SIZE pixels,
+        // or half the screen on displays too small to fit it
+        let size = DIMENSIONS_SQUARE_SIZE.min(width.min(height) / 2);
+        let left = center_x.saturating_sub(size / 2);
+        let top = center_y.saturating_sub(size / 2);
+        fill(left..left + size, top..top + line);
+        fill(
+            left..left + size,
+            (top + size).saturating_sub(line)..top + size,
+        );
+        fill(left..left + line, top..top + size);
+        fill(
+            (left + size).saturating_sub(line)..left + size,
+            top..top + size,
+        );
+
+        buffer
+    }
+
+    /// Show a column of squares within each of the given exposure test zones,
+    /// leaving the rest of the screen masked off
+    pub fn display_exposure_test(
+        &mut self,
+        zones: &[ExposureTestZone],
+    ) -> Result<(), OdysseyError> {
+        let width = self.config.screen_width as usize;
+        let height = self.config.screen_height as usize;
+
+        let mut buffer = self.display_test_blank();
+        for zone in zones {
+            let (start, end) = (zone.start_x as usize, (zone.end_x as usize).min(width));
+            if start >= end {
+                continue;
+            }
+            // Squares half the width of the band, separated by gaps of the same size
+            let size = ((end - start) / 2).max(1);
+            let left = start + (end - start - size) / 2;
+            for y in (size / 2..height).filter(|y| (y - size / 2) % (size * 2) < size) {
+                buffer[y * width + left..y * width + left + size].fill(0xFF);
+            }
+        }
+
+        self.display_bytes(buffer, 8)
+    }
+
+    /// Check that the configured frame_buffer exists, is a framebuffer device, and
+    /// can be opened for writing
+    pub fn verify_frame_buffer(config: &DisplayConfig) -> Result<(), OdysseyError> {
+        let metadata = fs::metadata(&config.frame_buffer)?;
+
+        if !metadata.file_type().is_char_device() {
+            return Err(OdysseyError::configuration_error(
+                Box::new(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a framebuffer device", config.frame_buffer),
+                )),
+                400,
+            ));
+        }
+
+        OpenOptions::new().write(true).open(&config.frame_buffer)?;
+
+        Ok(())
+    }
+
     pub fn new(config: &DisplayConfig) -> PrintDisplay {
         PrintDisplay {
-            frame_buffer: WrappedFramebuffer {
-                frame_buffer: Framebuffer::new(config.frame_buffer.clone()).ok(),
-                fb_path: config.frame_buffer.clone(),
-            },
+            frame_buffer: Self::open_device(config),
             config: config.clone(),
+            status_text: None,
+            preview: DisplayPreview::default(),
         }
     }
+
+    // Without the configured device, frames are written to frame_buffer as a
+    // plain file, so they can still be examined
+    fn open_device(config: &DisplayConfig) -> Box<dyn DisplayDevice> {
+        let frame_buffer = match config.backend.unwrap_or_default() {
+            DisplayBackend::Fbdev => Framebuffer::new(config.frame_buffer.clone()).ok(),
+            DisplayBackend::Drm => match DrmDisplay::open(config) {
+                Ok(display) => return Box::new(display),
+                Err(e) => {
+                    tracing::warn!("Unable to open DRM display {}: {}", config.frame_buffer, e);
+                    None
+                }
+            },
+        };
+
+        Box::new(WrappedFramebuffer {
+            frame_buffer,
+            fb_path: config.frame_buffer.clone(),
+        })
+    }
+
+    /// Whether the display device was opened, rather than writing layers to
+    /// frame_buffer as a plain file
+    pub fn frame_buffer_available(&self) -> bool {
+        self.frame_buffer.is_device()
+    }
+
+    /// Take up a reloaded display config. The framebuffer stays open as it
+    /// is, so its path, bit depth and screen size are kept until a restart
+    pub fn reload_config(&mut self, config: &DisplayConfig) {
+        let mut config = config.clone();
+        if config.frame_buffer != self.config.frame_buffer
+            || config.backend != self.config.backend
+            || config.bit_depth != self.config.bit_depth
+            || config.screen_width != self.config.screen_width
+            || config.screen_height != self.config.screen_height
+            || config.strict_frame_buffer != self.config.strict_frame_buffer
+        {
+            tracing::warn!(
+                "Changes to frame_buffer, backend, bit_depth, screen size and strict_frame_buffer take effect after a restart"
+            );
+            config.frame_buffer = self.config.frame_buffer.clone();
+            config.backend = self.config.backend;
+            config.bit_depth = self.config.bit_depth.clone();
+            config.screen_width = self.config.screen_width;
+            config.screen_height = self.config.screen_height;
+            config.strict_frame_buffer = self.config.strict_frame_buffer;
+        }
+
+        self.config = config;
+    }
 }
 
 impl Clone for PrintDisplay {
     fn clone(&self) -> Self {
-        Self::new(&self.config.clone())
+        let mut display = Self::new(&self.config.clone());
+        display.preview = self.preview.clone();
+        display
     }
 }
@@ -0,0 +1,450 @@
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::fd::AsRawFd,
+};
+
+use memmap2::{MmapMut, MmapOptions};
+use nix::{ioctl_readwrite, libc::c_int};
+
+use crate::{configuration::DisplayConfig, display::DisplayDevice};
+
+// Structures and requests of the kernel's DRM mode setting interface, from
+// include/uapi/drm/drm_mode.h, limited to what's needed to light a single
+// panel with a dumb buffer
+
+const DRM_IOCTL_BASE: u8 = b'd';
+const DRM_MODE_CONNECTED: u32 = 1;
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeCardRes {
+    fb_id_ptr: u64,
+    crtc_id_ptr: u64,
+    connector_id_ptr: u64,
+    encoder_id_ptr: u64,
+    count_fbs: u32,
+    count_crtcs: u32,
+    count_connectors: u32,
+    count_encoders: u32,
+    min_width: u32,
+    max_width: u32,
+    min_height: u32,
+    max_height: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct DrmModeModeInfo {
+    clock: u32,
+    hdisplay: u16,
+    hsync_start: u16,
+    hsync_end: u16,
+    htotal: u16,
+    hskew: u16,
+    vdisplay: u16,
+    vsync_start: u16,
+    vsync_end: u16,
+    vtotal: u16,
+    vscan: u16,
+    vrefresh: u32,
+    flags: u32,
+    mode_type: u32,
+    name: [u8; 32],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeCrtc {
+    set_connectors_ptr: u64,
+    count_connectors: u32,
+    crtc_id: u32,
+    fb_id: u32,
+    x: u32,
+    y: u32,
+    gamma_size: u32,
+    mode_valid: u32,
+    mode: DrmModeModeInfo,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeGetEncoder {
+    encoder_id: u32,
+    encoder_type: u32,
+    crtc_id: u32,
+    possible_crtcs: u32,
+    possible_clones: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeGetConnector {
+    encoders_ptr: u64,
+    modes_ptr: u64,
+    props_ptr: u64,
+    prop_values_ptr: u64,
+    count_modes: u32,
+    count_props: u32,
+    count_encoders: u32,
+    encoder_id: u32,
+    connector_id: u32,
+    connector_type: u32,
+    connector_type_id: u32,
+    connection: u32,
+    mm_width: u32,
+    mm_height: u32,
+    subpixel: u32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeFbCmd {
+    fb_id: u32,
+    width: u32,
+    height: u32,
+    pitch: u32,
+    bpp: u32,
+    depth: u32,
+    handle: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeCreateDumb {
+    height: u32,
+    width: u32,
+    bpp: u32,
+    flags: u32,
+    handle: u32,
+    pitch: u32,
+    size: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeMapDumb {
+    handle: u32,
+    pad: u32,
+    offset: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeDestroyDumb {
+    handle: u32,
+}
+
+ioctl_readwrite!(drm_mode_get_resources, DRM_IOCTL_BASE, 0xA0, DrmModeCardRes);
+ioctl_readwrite!(drm_mode_set_crtc, DRM_IOCTL_BASE, 0xA2, DrmModeCrtc);
+ioctl_readwrite!(
+    drm_mode_get_encoder,
+    DRM_IOCTL_BASE,
+    0xA6,
+    DrmModeGetEncoder
+);
+ioctl_readwrite!(
+    drm_mode_get_connector,
+    DRM_IOCTL_BASE,
+    0xA7,
+    DrmModeGetConnector
+);
+ioctl_readwrite!(drm_mode_add_fb, DRM_IOCTL_BASE, 0xAE, DrmModeFbCmd);
+ioctl_readwrite!(drm_mode_rm_fb, DRM_IOCTL_BASE, 0xAF, u32);
+ioctl_readwrite!(
+    drm_mode_create_dumb,
+    DRM_IOCTL_BASE,
+    0xB2,
+    DrmModeCreateDumb
+);
+ioctl_readwrite!(drm_mode_map_dumb, DRM_IOCTL_BASE, 0xB3, DrmModeMapDumb);
+ioctl_readwrite!(
+    drm_mode_destroy_dumb,
+    DRM_IOCTL_BASE,
+    0xB4,
+    DrmModeDestroyDumb
+);
+
+/// Run a DRM request, retrying when it's interrupted as libdrm does
+fn drm_ioctl<T>(
+    card: &File,
+    request: unsafe fn(c_int, *mut T) -> nix::Result<c_int>,
+    data: &mut T,
+) -> Result<(), io::Error> {
+    loop {
+        // The request and its data come in pairs from the definitions above,
+        // and any arrays the data points to outlive the call
+        match unsafe { request(card.as_raw_fd(), data) } {
+            Ok(_) => return Ok(()),
+            Err(nix::errno::Errno::EINTR | nix::errno::Errno::EAGAIN) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn array_ptr<T>(array: &mut [T]) -> u64 {
+    array.as_mut_ptr() as u64
+}
+
+/// Panel driven through DRM/KMS, for kernels without a legacy framebuffer
+/// device. A dumb buffer the size of the panel is scanned out by the first
+/// connected output with a mode matching the configured screen size, and each
+/// frame is copied straight into it
+pub struct DrmDisplay {
+    card: File,
+    path: String,
+    map: MmapMut,
+    handle: u32,
+    fb_id: u32,
+    pitch: usize,
+    row_bytes: usize,
+    height: usize,
+}
+
+impl DrmDisplay {
+    pub fn open(config: &DisplayConfig) -> Result<DrmDisplay, io::Error> {
+        let card = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&config.frame_buffer)?;
+
+        // Each DRM pixel holds one chunk of the configured bit layout, such as
+        // three monochrome pixels in the subpixels of a 5-6-5 RGB pixel
+        let bpp: u32 = config.bit_depth.iter().map(|&bits| bits as u32).sum();
+        let depth = match bpp {
+            8 | 16 | 24 => bpp,
+            32 => 24,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("DRM displays can't show {}-bit pixels", bpp),
+                ))
+            }
+        };
+        let width = config.screen_width / config.bit_depth.len() as u32;
+        let height = config.screen_height;
+
+        let (connector_id, crtc_id, mode) = Self::find_output(&card, width, height)?;
+
+        let mut dumb = DrmModeCreateDumb {
+            width,
+            height,
+            bpp,
+            ..Default::default()
+        };
+        drm_ioctl(&card, drm_mode_create_dumb, &mut dumb)?;
+
+        let mut display = DrmDisplay {
+            path: config.frame_buffer.clone(),
+            // Replaced once the dumb buffer is mapped, so that anything
+            // created before then is still cleaned up if a later step fails
+            map: MmapMut::map_anon(0)?,
+            handle: dumb.handle,
+            fb_id: 0,
+            pitch: dumb.pitch as usize,
+            row_bytes: (width * bpp / 8) as usize,
+            height: height as usize,
+            card,
+        };
+
+        let mut fb_cmd = DrmModeFbCmd {
+            width,
+            height,
+            pitch: dumb.pitch,
+            bpp,
+            depth,
+            handle: dumb.handle,
+            ..Default::default()
+        };
+        drm_ioctl(&display.card, drm_mode_add_fb, &mut fb_cmd)?;
+        display.fb_id = fb_cmd.fb_id;
+
+        let mut map_dumb = DrmModeMapDumb {
+            handle: dumb.handle,
+            ..Default::default()
+        };
+        drm_ioctl(&display.card, drm_mode_map_dumb, &mut map_dumb)?;
+        // The dumb buffer is only ever written through this mapping
+        display.map = unsafe {
+            MmapOptions::new()
+                .offset(map_dumb.offset)
+                .len(dumb.size as usize)
+                .map_mut(&display.card)?
+        };
+        display.map.fill(0);
+
+        let mut connectors = [connector_id];
+        let mut crtc = DrmModeCrtc {
+            set_connectors_ptr: array_ptr(&mut connectors),
+            count_connectors: 1,
+            crtc_id,
+            fb_id: display.fb_id,
+            mode_valid: 1,
+            mode,
+            ..Default::default()
+        };
+        drm_ioctl(&display.card, drm_mode_set_crtc, &mut crtc)?;
+
+        tracing::info!(
+            "Showing layers on DRM connector {} at {}x{}",
+            connector_id,
+            width,
+            height
+        );
+        Ok(display)
+    }
+
+    /// The first connected connector with a mode of the given size, along with
+    /// the CRTC to drive it from
+    fn find_output(
+        card: &File,
+        width: u32,
+        height: u32,
+    ) -> Result<(u32, u32, DrmModeModeInfo), io::Error> {
+        let mut resources = DrmModeCardRes::default();
+        drm_ioctl(card, drm_mode_get_resources, &mut resources)?;
+        let mut crtc_ids = vec![0u32; resources.count_crtcs as usize];
+        let mut connector_ids = vec![0u32; resources.count_connectors as usize];
+        let mut resources = DrmModeCardRes {
+            crtc_id_ptr: array_ptr(&mut crtc_ids),
+            connector_id_ptr: array_ptr(&mut connector_ids),
+            count_crtcs: crtc_ids.len() as u32,
+            count_connectors: connector_ids.len() as u32,
+            ..Default::default()
+        };
+        drm_ioctl(card, drm_mode_get_resources, &mut resources)?;
+
+        let mut available_modes = Vec::new();
+        for connector_id in connector_ids {
+            let mut connector = DrmModeGetConnector {
+                connector_id,
+                ..Default::default()
+            };
+            drm_ioctl(card, drm_mode_get_connector, &mut connector)?;
+            if connector.connection != DRM_MODE_CONNECTED {
+                continue;
+            }
+
+            let mut modes = vec![DrmModeModeInfo::default(); connector.count_modes as usize];
+            let mut encoder_ids = vec![0u32; connector.count_encoders as usize];
+            let mut connector = DrmModeGetConnector {
+                connector_id,
+                modes_ptr: array_ptr(&mut modes),
+                encoders_ptr: array_ptr(&mut encoder_ids),
+                count_modes: modes.len() as u32,
+                count_encoders: encoder_ids.len() as u32,
+                ..Default::default()
+            };
+            drm_ioctl(card, drm_mode_get_connector, &mut connector)?;
+            // Modes may have changed between the calls
+            modes.truncate(connector.count_modes as usize);
+            encoder_ids.truncate(connector.count_encoders as usize);
+
+            let Some(mode) = modes
+                .iter()
+                .find(|mode| mode.hdisplay as u32 == width && mode.vdisplay as u32 == height)
+            else {
+                available_modes.extend(
+                    modes
+                        .iter()
+                        .map(|mode| format!("{}x{}", mode.hdisplay, mode.vdisplay)),
+                );
+                continue;
+            };
+
+            // Keep the CRTC already driving the connector, if there is one
+            if connector.encoder_id != 0 {
+                let mut encoder = DrmModeGetEncoder {
+                    encoder_id: connector.encoder_id,
+                    ..Default::default()
+                };
+                drm_ioctl(card, drm_mode_get_encoder, &mut encoder)?;
+                if encoder.crtc_id != 0 {
+                    return Ok((connector_id, encoder.crtc_id, *mode));
+                }
+            }
+            for encoder_id in encoder_ids {
+                let mut encoder = DrmModeGetEncoder {
+                    encoder_id,
+                    ..Default::default()
+                };
+                drm_ioctl(card, drm_mode_get_encoder, &mut encoder)?;
+                if let Some(crtc_id) = crtc_ids
+                    .iter()
+                    .enumerate()
+                    .find(|(index, _)| encoder.possible_crtcs & (1 << index) != 0)
+                    .map(|(_, crtc_id)| *crtc_id)
+                {
+                    return Ok((connector_id, crtc_id, *mode));
+                }
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "No connected DRM output has a {}x{} mode, found {:?}",
+                width, height, available_modes
+            ),
+        ))
+    }
+}
+
+impl DisplayDevice for DrmDisplay {
+    fn write_frame(&mut self, frame: &[u8]) -> Result<(), io::Error> {
+        if frame.len() != self.row_bytes * self.height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Frame is {} bytes but the display holds {}",
+                    frame.len(),
+                    self.row_bytes * self.height
+                ),
+            ));
+        }
+
+        // Rows of the dumb buffer may be padded out beyond the panel's width
+        for (row, line) in frame.chunks_exact(self.row_bytes).enumerate() {
+            let start = row * self.pitch;
+            self.map[start..start + self.row_bytes].copy_from_slice(line);
+        }
+
+        // As with fbdev, writes into the mapped buffer don't report failure,
+        // so read the frame back to make sure it landed
+        let landed = frame
+            .chunks_exact(self.row_bytes)
+            .enumerate()
+            .all(|(row, line)| {
+                let start = row * self.pitch;
+                self.map[start..start + self.row_bytes] == *line
+            });
+        if !landed {
+            return Err(io::Error::other(format!(
+                "{} contents do not match the written frame",
+                self.path
+            )));
+        }
+        Ok(())
+    }
+
+    fn is_device(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for DrmDisplay {
+    fn drop(&mut self) {
+        if self.fb_id != 0 {
+            if let Err(e) = drm_ioctl(&self.card, drm_mode_rm_fb, &mut self.fb_id) {
+                tracing::warn!("Unable to remove DRM framebuffer: {}", e);
+            }
+        }
+        let mut destroy = DrmModeDestroyDumb {
+            handle: self.handle,
+        };
+        if let Err(e) = drm_ioctl(&self.card, drm_mode_destroy_dumb, &mut destroy) {
+            tracing::warn!("Unable to destroy DRM dumb buffer: {}", e);
+        }
+    }
+}
@@ -8,7 +8,10 @@ use poem::{error::ResponseError, http::StatusCode};
 use tokio::{
     sync::{
         broadcast::error::{RecvError, SendError, TryRecvError},
-        mpsc::error::{SendError as mpscSendError, TryRecvError as mpscTryRecvError},
+        mpsc::error::{
+            SendError as mpscSendError, TryRecvError as mpscTryRecvError,
+            TrySendError as mpscTrySendError,
+        },
     },
     task::JoinError,
 };
@@ -129,6 +132,20 @@ impl<T: Debug + Send + Sync + 'static> From<mpscSendError<T>> for OdysseyError {
         }
     }
 }
+impl<T: Debug + Send + Sync + 'static> From<mpscTrySendError<T>> for OdysseyError {
+    fn from(err: mpscTrySendError<T>) -> OdysseyError {
+        // A full queue is temporary, so report it as such rather than a failure
+        let error_code = match err {
+            mpscTrySendError::Full(_) => 503,
+            mpscTrySendError::Closed(_) => 500,
+        };
+        OdysseyError {
+            error_type: ErrorType::InternalStateError,
+            source: Box::new(err),
+            error_code,
+        }
+    }
+}
 impl From<io::Error> for OdysseyError {
     fn from(err: io::Error) -> OdysseyError {
         let error_code = match err.kind() {
@@ -1,72 +1,581 @@
-use core::panic;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::io;
 
 use async_trait::async_trait;
 use regex::Regex;
-use tokio::time::Duration;
+use tokio::time::{Duration, Instant};
 
-use crate::api_objects::PhysicalState;
-use crate::configuration::GcodeConfig;
+use crate::api_objects::{PhysicalState, ResolvedGcode};
+use crate::configuration::{CommandSync, Configuration, CureZone, GcodeConfig};
 use crate::error::OdysseyError;
 use crate::printer::HardwareControl;
 use crate::serial_handler::InternalCommsHandler;
 
+/// How long to wait for the firmware to echo back a command when verify_echo is set
+const ECHO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often to send the temperature_check command
+const TEMPERATURE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait for a response matching temperature_regex
+const TEMPERATURE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait for a response matching firmware_version_regex
+const FIRMWARE_VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Matches the firmware fields of an M115 response when no
+/// firmware_version_regex is configured
+const DEFAULT_FIRMWARE_VERSION_REGEX: &str = r"FIRMWARE_\w+:.*";
+
+/// Sent after each line of gcode when no line_ending is configured
+const DEFAULT_LINE_ENDING: &str = "\r\n";
+
+/// Deepest macros may be nested within one another, which also stops a macro
+/// which references itself from expanding forever
+const MAX_MACRO_DEPTH: usize = 8;
+
+/// Prefix a line of gcode with its line number and append the XOR checksum of
+/// both, as expected by Marlin style firmware
+pub fn number_line(number: u32, line: &str) -> String {
+    let numbered = format!("N{} {}", number, line);
+    let checksum = numbered.bytes().fold(0, |checksum, byte| checksum ^ byte);
+    format!("{}*{}", numbered, checksum)
+}
+
+/// The line number set by an M110 command, which is the number of the line
+/// it's sent on
+fn m110_line_number(line: &str) -> Option<u32> {
+    let mut words = line.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("M110") {
+        return None;
+    }
+    Some(
+        words
+            .find_map(|word| word.strip_prefix(['N', 'n']))
+            .and_then(|number| number.parse().ok())
+            .unwrap_or(0),
+    )
+}
+
+/// Replace each {{macro}} in the gcode with the macro's gcode, expanding any
+/// macros it references in turn
+pub fn expand_macros(code: &str, macros: &HashMap<String, String>) -> Result<String, String> {
+    expand_macros_at_depth(code, macros, 0)
+}
+
+fn expand_macros_at_depth(
+    code: &str,
+    macros: &HashMap<String, String>,
+    depth: usize,
+) -> Result<String, String> {
+    let re: Regex = Regex::new(r"\{\{(?P<name>\w+)\}\}").unwrap();
+    if !re.is_match(code) {
+        return Ok(code.to_string());
+    }
+    if depth >= MAX_MACRO_DEPTH {
+        return Err(format!(
+            "Macros nested more than {} deep, a macro may reference itself",
+            MAX_MACRO_DEPTH
+        ));
+    }
+
+    let mut expanded = String::new();
+    let mut last_end = 0;
+    for caps in re.captures_iter(code) {
+        let reference = caps.get(0).unwrap();
+        let name = &caps["name"];
+        let body = macros
+            .get(name)
+            .ok_or_else(|| format!("Unknown macro {{{{{}}}}}", name))?;
+
+        expanded.push_str(&code[last_end..reference.start()]);
+        // Block scalars end in a newline, which would leave a blank line
+        // wherever the macro is used
+        expanded.push_str(&expand_macros_at_depth(body.trim_end(), macros, depth + 1)?);
+        last_end = reference.end();
+    }
+    expanded.push_str(&code[last_end..]);
+
+    Ok(expanded)
+}
+
+/// Replace each {substitution} in the gcode with its value, failing with the
+/// name of the first substitution which isn't available
+pub fn substitute(code: &str, substitutions: &HashMap<String, String>) -> Result<String, String> {
+    let re: Regex = Regex::new(r"\{(?P<substitution>\w*)\}").unwrap();
+    let mut parsed_code = code.to_string();
+
+    for caps in re.captures_iter(code) {
+        let sub = &caps["substitution"];
+        match substitutions.get(sub) {
+            Some(value) => parsed_code = parsed_code.replace(&format!("{{{sub}}}"), value),
+            None => return Err(sub.to_string()),
+        }
+    }
+    Ok(parsed_code)
+}
+
+/// Cure zones can't be added, removed or renamed without a restart, as the
+/// curing state is tracked per zone from startup
+pub fn check_cure_zones(state: &PhysicalState, config: &GcodeConfig) -> Result<(), OdysseyError> {
+    let zones: Vec<String> = config
+        .cure_zones()
+        .into_iter()
+        .map(|zone| zone.name)
+        .collect();
+
+    if zones.len() != state.curing.len()
+        || zones.iter().any(|zone| !state.curing.contains_key(zone))
+    {
+        return Err(OdysseyError::configuration_error(
+            Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Changes to cure zones take effect after a restart",
+            )),
+            400,
+        ));
+    }
+    Ok(())
+}
+
+/// Resolve every configured gcode template with representative values for the
+/// substitutions available where it's sent, without sending anything
+pub fn resolve_templates(configuration: &Configuration) -> Vec<ResolvedGcode> {
+    let config = &configuration.gcode;
+    let printer = &configuration.printer;
+    let macros = config.macros.clone().unwrap_or_default();
+
+    // Always available, as in Gcode::add_state_variables and Printer::start_printer
+    let base: HashMap<String, String> = [
+        ("max_z", printer.max_z.to_string()),
+        ("z_lift", printer.default_lift.to_string()),
+        ("z", printer.default_lift.to_string()),
+        ("curing", false.to_string()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect();
+    let with = |extra: &[(&str, String)]| {
+        let mut substitutions = base.clone();
+        substitutions.extend(
+            extra
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone())),
+        );
+        substitutions
+    };
+    let moving = with(&[("speed", (printer.default_up_speed * 60.0).to_string())]);
+    let starting = with(&[("total_layers", "100".to_string())]);
+    let layering = with(&[
+        ("total_layers", "100".to_string()),
+        ("layer", "0".to_string()),
+    ]);
+
+    let mut templates = vec![
+        ("boot", Some(config.boot.clone()), &base),
+        ("shutdown", Some(config.shutdown.clone()), &base),
+        ("emergency_gcode", config.emergency_gcode.clone(), &base),
+        ("home_command", Some(config.home_command.clone()), &base),
+        ("move_command", Some(config.move_command.clone()), &moving),
+        (
+            "manual_move_command",
+            config.manual_move_command.clone(),
+            &moving,
+        ),
+        ("print_start", Some(config.print_start.clone()), &starting),
+        ("layer_start", Some(config.layer_start.clone()), &layering),
+        ("print_end", Some(config.print_end.clone()), &layering),
+        ("status_check", Some(config.status_check.clone()), &base),
+        ("temperature_check", config.temperature_check.clone(), &base),
+        (
+            "firmware_version_command",
+            config.firmware_version_command.clone(),
+            &base,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, template, substitutions)| {
+        Some((name.to_string(), template?, substitutions))
+    })
+    .collect::<Vec<_>>();
+
+    // Cure commands may also be sent outside of a print, by manual control
+    for zone in config.cure_zones() {
+        templates.push((format!("{}.cure_start", zone.name), zone.cure_start, &base));
+        templates.push((format!("{}.cure_end", zone.name), zone.cure_end, &base));
+    }
+
+    templates
+        .into_iter()
+        .map(|(name, template, substitutions)| {
+            let resolved = expand_macros(&template, &macros).and_then(|expanded| {
+                substitute(&expanded, substitutions).map_err(|sub| {
+                    format!("Substitution {{{}}} is unavailable where this is sent", sub)
+                })
+            });
+            ResolvedGcode {
+                name,
+                template,
+                command: resolved.as_ref().ok().cloned(),
+                error: resolved.err(),
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveDirection {
+    Up,
+    Down,
+}
+
+/// Tracks the direction of the last Z move, so the slack in the leadscrew nut
+/// can be taken up whenever a move reverses it. As moves are absolute, the
+/// compensation is carried as an offset between the plate's position and the
+/// position sent to the firmware. The position sent is kept within the Z
+/// limits, so compensation never takes the plate below min_z
+#[derive(Clone, Debug)]
+pub struct BacklashCompensation {
+    pub backlash_microns: u32,
+    pub min_z_microns: u32,
+    pub max_z_microns: u32,
+    pub last_direction: Option<MoveDirection>,
+    pub offset_microns: i64,
+}
+
+impl BacklashCompensation {
+    pub fn new(
+        backlash_microns: u32,
+        min_z_microns: u32,
+        max_z_microns: u32,
+    ) -> BacklashCompensation {
+        BacklashCompensation {
+            backlash_microns,
+            min_z_microns,
+            max_z_microns,
+            last_direction: None,
+            offset_microns: 0,
+        }
+    }
+
+    /// Whether a move from one position to another reverses the last move.
+    /// Moves which go nowhere don't have a direction, so never reverse
+    pub fn reverses(&self, from: u32, to: u32) -> bool {
+        match (self.last_direction, MoveDirection::between(from, to)) {
+            (Some(last), Some(direction)) => last != direction,
+            _ => false,
+        }
+    }
+
+    /// Record a move from one position to another, returning the position in
+    /// microns to send the firmware
+    pub fn compensate(&mut self, from: u32, to: u32) -> i64 {
+        if self.reverses(from, to) {
+            let backlash = i64::from(self.backlash_microns);
+            self.offset_microns += match self.last_direction {
+                Some(MoveDirection::Down) => backlash,
+                _ => -backlash,
+            };
+        }
+        if let Some(direction) = MoveDirection::between(from, to) {
+            self.last_direction = Some(direction);
+        }
+        self.position(to)
+    }
+
+    /// The position in microns to send the firmware for the plate to be at the
+    /// given position, clamped to the Z limits
+    pub fn position(&self, z_microns: u32) -> i64 {
+        (i64::from(z_microns) + self.offset_microns)
+            .clamp(i64::from(self.min_z_microns), i64::from(self.max_z_microns))
+    }
+
+    /// Forget the last direction once the position has been re-established,
+    /// such as by homing
+    pub fn reset(&mut self) {
+        self.last_direction = None;
+        self.offset_microns = 0;
+    }
+}
+
+impl MoveDirection {
+    pub fn between(from: u32, to: u32) -> Option<MoveDirection> {
+        match to.cmp(&from) {
+            Ordering::Greater => Some(MoveDirection::Up),
+            Ordering::Less => Some(MoveDirection::Down),
+            Ordering::Equal => None,
+        }
+    }
+}
+
 pub struct Gcode {
     pub config: GcodeConfig,
     pub state: PhysicalState,
     pub gcode_substitutions: HashMap<String, String>,
+    pub macros: HashMap<String, String>,
     pub serial_comms: InternalCommsHandler,
+    pub temperature_regex: Option<Regex>,
+    pub last_temperature_poll: Option<Instant>,
+    /// Number given to the next line sent when use_line_numbers is set
+    pub next_line_number: u32,
+    /// Serial reconnections the line numbering has already been reset for
+    pub numbered_reconnections: u32,
+    pub backlash: BacklashCompensation,
 }
 
 impl Gcode {
-    pub fn new(config: &GcodeConfig, serial_comms: InternalCommsHandler) -> Gcode {
+    pub fn new(
+        config: &GcodeConfig,
+        backlash: BacklashCompensation,
+        serial_comms: InternalCommsHandler,
+    ) -> Gcode {
+        let numbered_reconnections = serial_comms.reconnections();
         Gcode {
             config: config.clone(),
             state: PhysicalState {
                 z: 0.0,
                 z_microns: 0,
-                curing: false,
+                curing: config
+                    .cure_zones()
+                    .into_iter()
+                    .map(|zone| (zone.name, false))
+                    .collect(),
+                temperature: None,
+                firmware_version: None,
             },
             gcode_substitutions: HashMap::new(),
+            macros: config.macros.clone().unwrap_or_default(),
             serial_comms,
+            temperature_regex: Gcode::compile_temperature_regex(config),
+            last_temperature_poll: None,
+            next_line_number: 1,
+            numbered_reconnections,
+            backlash,
         }
     }
 
-    fn parse_gcode(&mut self, code: String) -> String {
-        let re: Regex = Regex::new(r"\{(?P<substitution>\w*)\}").unwrap();
-        let mut parsed_code = code.clone();
+    fn compile_temperature_regex(config: &GcodeConfig) -> Option<Regex> {
+        config.temperature_regex.as_ref().and_then(|pattern| {
+            Regex::new(pattern)
+                .inspect_err(|e| tracing::error!("Invalid temperature_regex: {}", e))
+                .ok()
+        })
+    }
+
+    /// Look up the requested cure zones, or every zone if none are given
+    fn select_cure_zones(&self, zones: Option<Vec<String>>) -> Result<Vec<CureZone>, OdysseyError> {
+        let configured = self.config.cure_zones();
+        let Some(names) = zones else {
+            return Ok(configured);
+        };
+
+        names
+            .iter()
+            .map(|name| {
+                configured
+                    .iter()
+                    .find(|zone| &zone.name == name)
+                    .cloned()
+                    .ok_or_else(|| {
+                        OdysseyError::configuration_error(
+                            Box::new(io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                format!("Unknown cure zone: {}", name),
+                            )),
+                            400,
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// Send the temperature_check command and parse its response, if both it
+    /// and temperature_regex are configured
+    async fn read_temperature(&mut self) -> Result<Option<f64>, OdysseyError> {
+        let (Some(command), Some(pattern)) = (
+            self.config.temperature_check.clone(),
+            self.temperature_regex.clone(),
+        ) else {
+            return Ok(None);
+        };
+        let parsed_code = self.format_gcode(command)?;
+
+        let capture = self
+            .serial_comms
+            .send_and_capture(parsed_code, &pattern, TEMPERATURE_TIMEOUT)
+            .await?;
+
+        Ok(capture.and_then(|value| value.trim().parse::<f64>().ok()))
+    }
+
+    /// Send the firmware_version_command, if configured, and parse its response
+    async fn read_firmware_version(&mut self) -> Result<Option<String>, OdysseyError> {
+        let Some(command) = self.config.firmware_version_command.clone() else {
+            return Ok(None);
+        };
+        let pattern = Regex::new(
+            self.config
+                .firmware_version_regex
+                .as_deref()
+                .unwrap_or(DEFAULT_FIRMWARE_VERSION_REGEX),
+        )
+        .map_err(|e| {
+            OdysseyError::configuration_error(
+                Box::new(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid firmware_version_regex: {}", e),
+                )),
+                500,
+            )
+        })?;
+        let parsed_code = self.format_gcode(command)?;
+
+        let capture = self
+            .serial_comms
+            .send_and_capture(parsed_code, &pattern, FIRMWARE_VERSION_TIMEOUT)
+            .await?;
+
+        Ok(capture.map(|version| version.trim().to_string()))
+    }
 
+    fn parse_gcode(&mut self, code: String) -> Result<String, OdysseyError> {
         self.add_state_variables();
 
-        for caps in re.captures_iter(&code) {
-            let sub = &caps["substitution"].to_string();
-            if let Some(value) = self.gcode_substitutions.get(sub) {
-                parsed_code = parsed_code.replace(&format!("{{{sub}}}"), value)
-            } else {
-                panic!("Attempted to use gcode substitution {} in context where it was unavailable: {}", sub, code);
+        let code = expand_macros(&code, &self.macros).map_err(|e| {
+            OdysseyError::configuration_error(
+                Box::new(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unable to expand gcode macros in {}: {}", code, e),
+                )),
+                500,
+            )
+        })?;
+
+        substitute(&code, &self.gcode_substitutions).map_err(|sub| {
+            OdysseyError::configuration_error(
+                Box::new(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Attempted to use gcode substitution {} in context where it was unavailable: {}",
+                        sub, code
+                    ),
+                )),
+                500,
+            )
+        })
+    }
+
+    /// Parse gcode into the lines to send over serial, numbering them if
+    /// use_line_numbers is set
+    fn format_gcode(&mut self, code: String) -> Result<String, OdysseyError> {
+        let parsed_code = self.parse_gcode(code)?;
+        let line_ending = self
+            .config
+            .line_ending
+            .clone()
+            .unwrap_or(DEFAULT_LINE_ENDING.to_string());
+
+        if !self.use_line_numbers() {
+            return Ok(parsed_code + &line_ending);
+        }
+
+        // The firmware may have reset along with a lost connection, so the
+        // numbering starts over, as it does at boot, before anything else is
+        // sent. Otherwise it would ask for lines from before the reconnect,
+        // which are no longer kept to be resent
+        let reconnections = self.serial_comms.reconnections();
+        let parsed_code = if reconnections != self.numbered_reconnections {
+            self.numbered_reconnections = reconnections;
+            format!("M110 N0\n{}", parsed_code)
+        } else {
+            parsed_code
+        };
+
+        // Comments are stripped, as the firmware ignores anything after them
+        // including the checksum
+        let mut formatted = String::new();
+        for line in parsed_code
+            .lines()
+            .map(|line| line.split(';').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+        {
+            if let Some(number) = m110_line_number(line) {
+                self.next_line_number = number;
             }
+            formatted += &number_line(self.next_line_number, line);
+            formatted += &line_ending;
+            self.next_line_number = self.next_line_number.wrapping_add(1);
         }
-        parsed_code
+        Ok(formatted)
+    }
+
+    fn use_line_numbers(&self) -> bool {
+        self.config.use_line_numbers.unwrap_or(false)
     }
 
     async fn send_gcode(&mut self, code: String) -> Result<(), OdysseyError> {
-        let parsed_code = self.parse_gcode(code) + "\r\n";
+        let parsed_code = self.format_gcode(code)?;
         tracing::debug!("Executing gcode: {}", parsed_code.trim_end());
 
         self.serial_comms.send(parsed_code).await
     }
 
+    /// Send gcode which must be executed correctly, such as moves or toggling
+    /// the UV array. Verifies the firmware's echo of the command if configured
+    async fn send_critical_gcode(&mut self, code: String) -> Result<(), OdysseyError> {
+        if !self.verify_echo() {
+            return self.send_gcode(code).await;
+        }
+        let parsed_code = self.format_gcode(code)?;
+        tracing::debug!("Executing verified gcode: {}", parsed_code.trim_end());
+
+        self.serial_comms
+            .send_and_verify(parsed_code, ECHO_TIMEOUT)
+            .await
+    }
+
     async fn send_and_await_gcode(
         &mut self,
         code: String,
         expect: &String,
         timeout_seconds: u64,
     ) -> Result<(), OdysseyError> {
-        let parsed_code = self.parse_gcode(code) + "\r\n";
+        let parsed_code = self.format_gcode(code)?;
+        let timeout_duration = Duration::from_secs(timeout_seconds);
+
+        if self.verify_echo() {
+            self.serial_comms
+                .send_and_verify(parsed_code, ECHO_TIMEOUT)
+                .await?;
+            self.serial_comms
+                .await_response(expect, timeout_duration)
+                .await
+        } else {
+            self.serial_comms
+                .send_and_await(parsed_code, expect, timeout_duration)
+                .await
+        }
+    }
 
-        self.serial_comms
-            .send_and_await(parsed_code, expect, Duration::from_secs(timeout_seconds))
-            .await
+    /// Send a lifecycle command, awaiting its acknowledgment if one is configured
+    async fn send_synced_gcode(
+        &mut self,
+        code: String,
+        sync: Option<CommandSync>,
+    ) -> Result<(), OdysseyError> {
+        match sync {
+            Some(sync) => {
+                let timeout_seconds = sync.timeout.unwrap_or(self.config.move_timeout);
+                self.send_and_await_gcode(code, &sync.sync, timeout_seconds)
+                    .await
+            }
+            None => self.send_gcode(code).await,
+        }
+    }
+
+    fn verify_echo(&self) -> bool {
+        self.config.verify_echo.unwrap_or(false)
     }
 
     async fn send_and_check_gcode(
@@ -74,7 +583,7 @@ impl Gcode {
         code: String,
         expect: &String,
     ) -> Result<bool, OdysseyError> {
-        let parsed_code = self.parse_gcode(code) + "\r\n";
+        let parsed_code = self.format_gcode(code)?;
         self.serial_comms.send_and_check(parsed_code, expect).await
     }
 
@@ -84,22 +593,25 @@ impl Gcode {
     fn set_position(&mut self, position: u32) -> PhysicalState {
         self.state.z_microns = position;
         self.state.z = (position as f64) / 1000.0;
-        self.state
+        self.state.clone()
     }
 
     /// Set the internally-stored curing state. Any method which uses a send_gcode
     /// method to enable or disable the LED array (or other curing method) should
     /// call this method to reflect that change
-    fn set_curing(&mut self, curing: bool) -> PhysicalState {
-        self.state.curing = curing;
-        self.state
+    fn set_curing(&mut self, zone: String, curing: bool) -> PhysicalState {
+        self.state.curing.insert(zone, curing);
+        self.state.clone()
     }
 
+    // {z} is where the firmware believes the plate is, which differs from the
+    // reported position by any backlash compensation
     fn add_state_variables(&mut self) {
         self.gcode_substitutions
-            .insert("curing".to_string(), self.state.curing.to_string());
+            .insert("curing".to_string(), self.state.is_curing().to_string());
+        let z = self.backlash.position(self.state.z_microns) as f64 / 1000.0;
         self.gcode_substitutions
-            .insert("z".to_string(), self.state.z.to_string());
+            .insert("z".to_string(), z.to_string());
     }
 }
 
@@ -116,15 +628,34 @@ impl HardwareControl for Gcode {
     }
 
     async fn home(&mut self) -> Result<PhysicalState, OdysseyError> {
-        self.send_gcode(self.config.home_command.clone()).await?;
+        self.send_synced_gcode(
+            self.config.home_command.clone(),
+            self.config.home_sync.clone(),
+        )
+        .await?;
+        self.backlash.reset();
 
-        Ok(self.state)
+        Ok(self.state.clone())
+    }
+
+    // Without home_sync, a home is only known to have completed once the
+    // controller reports the move as it would any other
+    async fn verify_home(&mut self) -> Result<PhysicalState, OdysseyError> {
+        let sync = self.config.home_sync.clone().unwrap_or(CommandSync {
+            sync: self.config.move_sync.clone(),
+            timeout: None,
+        });
+        self.send_synced_gcode(self.config.home_command.clone(), Some(sync))
+            .await?;
+        self.backlash.reset();
+
+        Ok(self.set_position(0))
     }
 
     async fn manual_command(&mut self, command: String) -> Result<PhysicalState, OdysseyError> {
         self.send_gcode(command).await?;
 
-        Ok(self.state)
+        Ok(self.state.clone())
     }
 
     async fn move_z(
@@ -144,67 +675,188 @@ impl HardwareControl for Gcode {
             false => self.config.move_command.clone(),
         };
 
+        // The new position is needed for the {z} substitution, but is only kept
+        // once the move has been acknowledged
+        let previous = (self.state.z_microns, self.backlash.clone());
+        self.backlash.compensate(self.state.z_microns, z);
         self.set_position(z);
         self.add_print_variable("speed".to_string(), speed.to_string());
 
-        self.send_and_await_gcode(
-            command,
-            &self.config.move_sync.clone(),
-            self.config.move_timeout,
-        )
-        .await?;
+        let result = self
+            .send_and_await_gcode(
+                command,
+                &self.config.move_sync.clone(),
+                self.config.move_timeout,
+            )
+            .await;
 
         self.remove_print_variable("speed".to_string());
 
-        Ok(self.state)
+        if let Err(e) = result {
+            let (z_microns, backlash) = previous;
+            self.set_position(z_microns);
+            self.backlash = backlash;
+            return Err(e);
+        }
+
+        Ok(self.state.clone())
     }
 
     async fn start_layer(&mut self, _layer: usize) -> Result<PhysicalState, OdysseyError> {
         self.send_gcode(self.config.layer_start.clone()).await?;
 
-        Ok(self.state)
+        Ok(self.state.clone())
     }
 
-    async fn start_curing(&mut self) -> Result<PhysicalState, OdysseyError> {
-        self.set_curing(true);
+    async fn start_curing(
+        &mut self,
+        zones: Option<Vec<String>>,
+    ) -> Result<PhysicalState, OdysseyError> {
+        let zones = self.select_cure_zones(zones)?;
 
-        self.send_gcode(self.config.cure_start.clone()).await?;
+        for zone in zones {
+            self.set_curing(zone.name, true);
+            self.send_critical_gcode(zone.cure_start).await?;
+        }
 
-        Ok(self.state)
+        Ok(self.state.clone())
     }
 
-    async fn stop_curing(&mut self) -> Result<PhysicalState, OdysseyError> {
-        self.set_curing(false);
-        self.send_gcode(self.config.cure_end.clone()).await?;
-        Ok(self.state)
+    async fn stop_curing(
+        &mut self,
+        zones: Option<Vec<String>>,
+    ) -> Result<PhysicalState, OdysseyError> {
+        let zones = self.select_cure_zones(zones)?;
+
+        // Attempt to turn off every zone, even if an earlier one failed
+        let mut result = Ok(());
+        for zone in zones {
+            self.set_curing(zone.name, false);
+            let sent = self.send_critical_gcode(zone.cure_end).await;
+            result = result.and(sent);
+        }
+
+        result.map(|_| self.state.clone())
     }
 
     async fn start_print(&mut self) -> Result<PhysicalState, OdysseyError> {
-        self.send_gcode(self.config.print_start.clone()).await?;
+        self.send_synced_gcode(
+            self.config.print_start.clone(),
+            self.config.print_start_sync.clone(),
+        )
+        .await?;
 
-        Ok(self.state)
+        Ok(self.state.clone())
     }
 
     async fn end_print(&mut self) -> Result<PhysicalState, OdysseyError> {
-        self.send_gcode(self.config.print_end.clone()).await?;
+        self.send_synced_gcode(
+            self.config.print_end.clone(),
+            self.config.print_end_sync.clone(),
+        )
+        .await?;
 
-        Ok(self.state)
+        Ok(self.state.clone())
     }
 
     async fn boot(&mut self) -> Result<PhysicalState, OdysseyError> {
-        self.send_gcode(self.config.boot.clone()).await?;
+        // The firmware may have kept counting lines from a previous connection
+        if self.use_line_numbers() {
+            self.send_gcode("M110 N0".to_string()).await?;
+        }
+
+        self.send_synced_gcode(self.config.boot.clone(), self.config.boot_sync.clone())
+            .await?;
+
+        // Not knowing the firmware version is no reason to fail the boot
+        match self.read_firmware_version().await {
+            Ok(Some(version)) => {
+                tracing::info!("Controller firmware version: {}", version);
+                self.state.firmware_version = Some(version);
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Unable to read the firmware version: {}", e),
+        }
 
-        Ok(self.state)
+        Ok(self.state.clone())
+    }
+
+    async fn poll_status(&mut self) -> Result<PhysicalState, OdysseyError> {
+        let poll_due = self
+            .last_temperature_poll
+            .is_none_or(|last_poll| last_poll.elapsed() >= TEMPERATURE_POLL_INTERVAL);
+
+        if poll_due {
+            self.last_temperature_poll = Some(Instant::now());
+            self.state.temperature = self.read_temperature().await?;
+        }
+
+        Ok(self.state.clone())
     }
 
     async fn shutdown(&mut self) -> Result<(), OdysseyError> {
-        self.send_gcode(self.config.shutdown.clone()).await?;
+        // The UV array goes off before anything else, whatever the configured
+        // sequence does
+        let mut result = Ok(());
+        for zone in self.config.cure_zones() {
+            self.set_curing(zone.name, false);
+            let sent = self.send_gcode(zone.cure_end).await;
+            result = result.and(sent);
+        }
 
-        Ok(())
+        // Send the sequence a line at a time, awaiting shutdown_sync after each.
+        // A failed line doesn't stop the rest, such as switching off a relay
+        // after a retract which timed out or a substitution which couldn't be
+        // made. Each line is substituted as it's sent
+        let sequence = self.config.shutdown.clone();
+        for line in sequence
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            let sent = self
+                .send_synced_gcode(line.to_string(), self.config.shutdown_sync.clone())
+                .await;
+            if let Err(e) = &sent {
+                tracing::error!("Shutdown gcode {} failed: {}", line, e);
+            }
+            result = result.and(sent);
+        }
+
+        result
+    }
+
+    async fn emergency_stop(&mut self) -> Result<PhysicalState, OdysseyError> {
+        // The emergency gcode goes first, as firmware such as Klipper handles
+        // M112 immediately, while cure_end may wait behind queued moves
+        let mut result = match self.config.emergency_gcode.clone() {
+            Some(code) => self.send_gcode(code).await,
+            None => Ok(()),
+        };
+        for zone in self.config.cure_zones() {
+            self.set_curing(zone.name, false);
+            let sent = self.send_gcode(zone.cure_end).await;
+            result = result.and(sent);
+        }
+
+        result.map(|_| self.state.clone())
     }
 
     fn get_physical_state(&self) -> Result<PhysicalState, OdysseyError> {
-        Ok(self.state)
+        Ok(self.state.clone())
+    }
+
+    fn is_connected(&self) -> Option<bool> {
+        Some(self.serial_comms.is_connected())
+    }
+
+    fn reload_config(&mut self, config: &GcodeConfig) -> Result<(), OdysseyError> {
+        check_cure_zones(&self.state, config)?;
+
+        self.config = config.clone();
+        self.macros = config.macros.clone().unwrap_or_default();
+        self.temperature_regex = Gcode::compile_temperature_regex(config);
+        Ok(())
     }
 
     fn add_print_variable(&mut self, variable: String, value: String) {
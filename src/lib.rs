@@ -1,31 +1,48 @@
 use crate::{
-    api_objects::PrinterState,
+    api_objects::{PrintEvent, PrinterState, UsbEvent},
     configuration::Configuration,
     display::PrintDisplay,
-    gcode::Gcode,
-    printer::{Operation, Printer},
-    serial_handler::SerialHandler,
+    error::OdysseyError,
+    gcode::{BacklashCompensation, Gcode},
+    printer::{HardwareControl, Operation, Printer, PrioritySender},
+    serial_handler::{GcodeLog, SerialHandler},
     shutdown_handler::ShutdownHandler,
+    simulated::SimulatedHardware,
 };
 use git_version::git_version;
 use std::sync::Arc;
 use tokio::{
     runtime::Runtime,
+    signal::unix::{signal, SignalKind},
     sync::{broadcast, mpsc},
+    task::JoinHandle,
 };
+use tokio_util::sync::CancellationToken;
 
 pub mod api;
 pub mod api_objects;
+pub mod checksum;
+pub mod config_schema;
 pub mod configuration;
+pub mod cws;
 pub mod display;
+mod drm_display;
 pub mod error;
 pub mod gcode;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod print_history;
 pub mod printer;
 pub mod printfile;
 pub mod serial_handler;
 pub mod shutdown_handler;
+pub mod simulated;
 pub mod sl1;
+mod status_overlay;
+pub mod thumbnail;
 pub mod updates;
+pub mod usb;
+pub mod uv_usage;
 mod wrapped_framebuffer;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -39,43 +56,229 @@ pub fn start_odyssey(
 ) {
     let shutdown_handler = ShutdownHandler::new();
 
+    let (min_z, max_z) = configuration.printer.z_limits();
     let gcode = Gcode::new(
         &configuration.gcode,
+        BacklashCompensation::new(
+            configuration.printer.backlash_microns.unwrap_or(0),
+            (min_z * 1000.0) as u32,
+            (max_z * 1000.0) as u32,
+        ),
         serial_handler.get_internal_comms().clone().invert(),
     );
+    let gcode_log = serial_handler.get_gcode_log();
+
+    let serial_handle =
+        runtime.spawn(serial_handler.run(shutdown_handler.cancellation_token.clone()));
+
+    run_printer(
+        runtime,
+        configuration,
+        gcode,
+        gcode_log,
+        shutdown_handler,
+        Some(serial_handle),
+    );
+}
+
+/// Start Odyssey against simulated hardware, with no serial connection
+pub fn start_simulated_odyssey(runtime: Runtime, configuration: Arc<Configuration>) {
+    let shutdown_handler = ShutdownHandler::new();
+
+    let simulated_hardware = SimulatedHardware::new(&configuration.gcode);
+
+    run_printer(
+        runtime,
+        configuration,
+        simulated_hardware,
+        GcodeLog::default(),
+        shutdown_handler,
+        None,
+    );
+}
+
+fn run_printer<T: HardwareControl + Send + 'static>(
+    runtime: Runtime,
+    configuration: Arc<Configuration>,
+    hardware_controller: T,
+    gcode_log: GcodeLog,
+    shutdown_handler: ShutdownHandler,
+    serial_handle: Option<JoinHandle<Result<(), OdysseyError>>>,
+) {
+    if let Err(err) = PrintDisplay::verify_frame_buffer(&configuration.display) {
+        if configuration.display.strict_frame_buffer.unwrap_or(false) {
+            panic!(
+                "Configured frame_buffer {} is unusable: {}",
+                configuration.display.frame_buffer, err.source
+            );
+        }
+        if configuration
+            .display
+            .print_without_frame_buffer
+            .unwrap_or(false)
+        {
+            tracing::warn!(
+                "Configured frame_buffer {} is unusable, layers will not be displayed correctly: {}",
+                configuration.display.frame_buffer,
+                err.source
+            );
+        } else {
+            tracing::error!(
+                "Configured frame_buffer {} is unusable, prints will be refused: {}",
+                configuration.display.frame_buffer,
+                err.source
+            );
+        }
+    }
 
     let display: PrintDisplay = PrintDisplay::new(&configuration.display);
+    let display_preview = display.preview.clone();
 
-    let operation_channel = mpsc::channel::<Operation>(100);
+    let operation_channel = mpsc::channel::<Operation>(
+        configuration
+            .api
+            .max_queued_operations
+            .unwrap_or(100)
+            .max(1),
+    );
+    // The priority lanes only carry a handful of safety critical operations,
+    // so need no more than a little room
+    let control_channel = mpsc::channel::<Operation>(16);
+    let emergency_channel = mpsc::channel::<Operation>(4);
+    let priority_sender = PrioritySender {
+        control: control_channel.0,
+        emergency: emergency_channel.0,
+    };
     let status_channel = broadcast::channel::<PrinterState>(100);
+    let event_channel = broadcast::channel::<PrintEvent>(100);
+
+    let usb_channel = broadcast::channel::<UsbEvent>(16);
 
     let sender = operation_channel.0.clone();
     let receiver = status_channel.1.resubscribe();
-
-    let serial_handle =
-        runtime.spawn(serial_handler.run(shutdown_handler.cancellation_token.clone()));
+    let event_receiver = event_channel.1.resubscribe();
 
     let statemachine_handle = runtime.spawn(Printer::start_printer(
         configuration.clone(),
         display,
-        gcode,
+        hardware_controller,
         operation_channel.1,
+        control_channel.1,
+        emergency_channel.1,
         status_channel.0.clone(),
+        event_channel.0.clone(),
+        shutdown_handler.cancellation_token.clone(),
+    ));
+
+    #[cfg(feature = "mqtt")]
+    let mqtt_handle = configuration.mqtt.clone().map(|mqtt_config| {
+        runtime.spawn(mqtt::MqttPublisher::run(
+            mqtt_config,
+            status_channel.1.resubscribe(),
+            operation_channel.0.clone(),
+            priority_sender.clone(),
+            shutdown_handler.cancellation_token.clone(),
+        ))
+    });
+    #[cfg(not(feature = "mqtt"))]
+    if configuration.mqtt.is_some() {
+        tracing::warn!("MQTT is configured, but Odyssey was built without the mqtt feature");
+    }
+
+    if configuration.printer.reload_on_sighup.unwrap_or(true) {
+        runtime.spawn(reload_on_sighup(
+            configuration.clone(),
+            operation_channel.0.clone(),
+            shutdown_handler.cancellation_token.clone(),
+        ));
+    }
+
+    runtime.spawn(usb::run_usb_watcher(
+        configuration.api.usb_glob.clone(),
+        usb_channel.0,
         shutdown_handler.cancellation_token.clone(),
     ));
 
     let api_handle = runtime.spawn(api::start_api(
         configuration.clone(),
         sender,
+        priority_sender,
         receiver,
+        event_receiver,
+        usb_channel.1,
+        display_preview,
+        gcode_log,
         shutdown_handler.cancellation_token.clone(),
     ));
 
     runtime.block_on(async {
         shutdown_handler.until_shutdown().await;
 
-        let _ = serial_handle.await;
+        if let Some(serial_handle) = serial_handle {
+            let _ = serial_handle.await;
+        }
         let _ = statemachine_handle.await;
         let _ = api_handle.await;
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt_handle) = mqtt_handle {
+            let _ = mqtt_handle.await;
+        }
     });
 }
+
+/// Reload the config file on each SIGHUP, handing it to the printer once it's
+/// been validated. Sections the printer doesn't take up are only compared
+/// against the startup config, to warn that they need a restart
+async fn reload_on_sighup(
+    configuration: Arc<Configuration>,
+    operation_sender: mpsc::Sender<Operation>,
+    cancellation_token: CancellationToken,
+) {
+    let Some(config_file) = configuration.config_file.clone() else {
+        return;
+    };
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::error!(
+                "Unable to listen for SIGHUP, config reloading is disabled: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {},
+            _ = cancellation_token.cancelled() => return,
+        }
+
+        tracing::info!("Received SIGHUP, reloading {}", config_file);
+        let reloaded = match Configuration::from_file(config_file.clone()) {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                tracing::error!("Unable to reload config, keeping the current config: {}", e);
+                continue;
+            }
+        };
+
+        if reloaded.api != configuration.api
+            || reloaded.mqtt != configuration.mqtt
+            || reloaded.resin_profiles != configuration.resin_profiles
+        {
+            tracing::warn!(
+                "Changes to the api, mqtt and resin_profiles sections take effect after a restart"
+            );
+        }
+
+        if let Err(e) = operation_sender
+            .send(Operation::ReloadConfiguration {
+                configuration: Box::new(reloaded),
+            })
+            .await
+        {
+            tracing::error!("Unable to send reloaded config to the printer: {}", e);
+        }
+    }
+}
@@ -1,8 +1,7 @@
 use std::{str::FromStr, sync::Arc};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
-use serialport::{ClearBuffer, SerialPort};
 use tokio::runtime::{Builder, Runtime};
 
 use odyssey::{configuration::Configuration, serial_handler::TTYPortHandler};
@@ -16,16 +15,37 @@ struct Args {
     config: String,
     #[arg(default_value_t=String::from("DEBUG"), short, long)]
     loglevel: String,
+    /// Log as plain text, or as a JSON object per line for log aggregators
+    #[arg(value_enum, default_value_t = LogFormat::Text, long)]
+    log_format: LogFormat,
     #[arg(default_value_t = false, short, long)]
     apidocs: bool,
+    /// Run against simulated hardware instead of a serial connection
+    #[arg(default_value_t = false, short, long)]
+    simulate: bool,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
 }
 
 fn main() {
     let args = Args::parse();
 
-    tracing_subscriber::fmt()
-        .with_max_level(LevelFilter::from_str(&args.loglevel).expect("Unable to parse loglevel"))
-        .init();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::from_str(&args.loglevel).expect("Unable to parse loglevel"));
+    match args.log_format {
+        LogFormat::Text => subscriber.init(),
+        // Include the fields of the current span, and every span it's nested
+        // in, such as the request parameters recorded by #[instrument]
+        LogFormat::Json => subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
 
     tracing::info!("Starting Odyssey");
 
@@ -34,21 +54,18 @@ fn main() {
             .expect("Config could not be parsed. See example odyssey.yaml for expected fields:"),
     );
 
-    let mut serial = tokio_serial::new(
-        &configuration.printer.serial,
-        configuration.printer.baudrate,
-    )
-    .open_native()
-    .expect("Unable to open serial port");
+    for problem in configuration.validate() {
+        tracing::warn!("Config problem in {}: {}", problem.field, problem.message);
+    }
 
-    serial
-        .set_exclusive(false)
-        .expect("Unable to set serial port exclusivity(false)");
-    serial
-        .clear(ClearBuffer::All)
-        .expect("Unable to clear serialport buffers");
+    if args.simulate {
+        tracing::info!("Running with simulated hardware");
+        odyssey::start_simulated_odyssey(build_runtime(), configuration);
+        return;
+    }
 
-    let serial_handler = Box::new(TTYPortHandler::new(serial));
+    let serial_handler =
+        Box::new(TTYPortHandler::open(&configuration.printer).expect("Unable to open serial port"));
 
     odyssey::start_odyssey(build_runtime(), configuration, serial_handler);
 }
@@ -0,0 +1,192 @@
+use std::time::Duration;
+
+use poem_openapi::types::ToJSON;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Outgoing, Packet, QoS};
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    api_objects::PrinterState,
+    configuration::MqttConfig,
+    printer::{Operation, PrioritySender},
+};
+
+const DEFAULT_PORT: u16 = 1883;
+const DEFAULT_TOPIC_PREFIX: &str = "odyssey";
+const DEFAULT_CLIENT_ID: &str = "odyssey";
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Publishes printer state to an MQTT broker, and accepts pause, resume and
+/// cancel commands from it
+pub struct MqttPublisher {
+    client: AsyncClient,
+    topic_prefix: String,
+    operation_sender: mpsc::Sender<Operation>,
+    priority_sender: PrioritySender,
+    /// Layer and progress last published, as they're retained and only need
+    /// publishing when they change
+    last_progress: Option<(Option<usize>, Option<u32>)>,
+}
+
+impl MqttPublisher {
+    pub async fn run(
+        configuration: MqttConfig,
+        mut state_receiver: broadcast::Receiver<PrinterState>,
+        operation_sender: mpsc::Sender<Operation>,
+        priority_sender: PrioritySender,
+        cancellation_token: CancellationToken,
+    ) {
+        let mut options = MqttOptions::new(
+            configuration
+                .client_id
+                .unwrap_or(DEFAULT_CLIENT_ID.to_string()),
+            configuration.host,
+            configuration.port.unwrap_or(DEFAULT_PORT),
+        );
+        options.set_keep_alive(KEEP_ALIVE);
+        if let Some(username) = configuration.username {
+            options.set_credentials(username, configuration.password.unwrap_or_default());
+        }
+
+        let (client, mut event_loop) = AsyncClient::new(options, 100);
+        let mut publisher = MqttPublisher {
+            client,
+            topic_prefix: configuration
+                .topic_prefix
+                .unwrap_or(DEFAULT_TOPIC_PREFIX.to_string()),
+            operation_sender,
+            priority_sender,
+            last_progress: None,
+        };
+
+        loop {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => {
+                    publisher.disconnect(&mut event_loop).await;
+                    break;
+                }
+                state = state_receiver.recv() => match state {
+                    Ok(state) => publisher.publish_state(&state),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("MQTT publisher lagged, skipped {} updates", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                event = event_loop.poll() => match event {
+                    // Subscriptions don't survive a reconnect, so renew them on every connection
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        tracing::info!("Connected to MQTT broker");
+                        publisher.subscribe();
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        publisher.handle_command(&String::from_utf8_lossy(&publish.payload));
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        tracing::warn!("MQTT connection error, retrying: {}", err);
+                        tokio::select! {
+                            _ = cancellation_token.cancelled() => break,
+                            _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+                        }
+                    }
+                },
+            }
+        }
+        tracing::info!("MQTT publisher shut down");
+    }
+
+    /// Let the broker know we're going, rather than leaving it to notice the
+    /// keep alive lapse
+    async fn disconnect(&self, event_loop: &mut EventLoop) {
+        if self.client.try_disconnect().is_err() {
+            return;
+        }
+        let _ = tokio::time::timeout(DISCONNECT_TIMEOUT, async {
+            while let Ok(event) = event_loop.poll().await {
+                if let Event::Outgoing(Outgoing::Disconnect) = event {
+                    break;
+                }
+            }
+        })
+        .await;
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.topic_prefix, name)
+    }
+
+    fn subscribe(&self) {
+        if let Err(err) = self
+            .client
+            .try_subscribe(self.topic("command"), QoS::AtLeastOnce)
+        {
+            tracing::error!("Unable to subscribe to MQTT commands: {}", err);
+        }
+    }
+
+    fn publish(&self, name: &str, retain: bool, payload: String) {
+        // Never block on the client, as the event loop shares this task
+        if let Err(err) =
+            self.client
+                .try_publish(self.topic(name), QoS::AtLeastOnce, retain, payload)
+        {
+            tracing::warn!("Unable to publish MQTT {}: {}", name, err);
+        }
+    }
+
+    fn publish_state(&mut self, state: &PrinterState) {
+        self.publish("status", false, state.to_json_string());
+
+        let progress = state.print_data.as_ref().map(|print_data| {
+            let layer = state.layer.unwrap_or(0);
+            ((layer * 100) / print_data.layer_count.max(1)) as u32
+        });
+        if self.last_progress == Some((state.layer, progress)) {
+            return;
+        }
+        self.last_progress = Some((state.layer, progress));
+
+        // An empty retained message clears the topic when no print is running
+        self.publish(
+            "layer",
+            true,
+            state
+                .layer
+                .map(|layer| layer.to_string())
+                .unwrap_or_default(),
+        );
+        self.publish(
+            "progress",
+            true,
+            progress
+                .map(|progress| progress.to_string())
+                .unwrap_or_default(),
+        );
+    }
+
+    fn handle_command(&self, command: &str) {
+        let operation = match command.trim().to_lowercase().as_str() {
+            "pause" => Operation::PausePrint,
+            "resume" => Operation::ResumePrint,
+            "cancel" => Operation::StopPrint { retract: None },
+            _ => {
+                tracing::warn!("Ignoring unknown MQTT command {:?}", command);
+                return;
+            }
+        };
+
+        tracing::info!("Received MQTT command {:?}", operation);
+        let sent = match operation.is_priority() {
+            true => self.priority_sender.try_send(operation),
+            false => self
+                .operation_sender
+                .try_send(operation)
+                .map_err(Into::into),
+        };
+        if let Err(err) = sent {
+            tracing::error!("Unable to send MQTT command to the printer: {}", err);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+};
+
+use crate::{
+    api_objects::{PrintOutcome, PrintRecord, PrintStats},
+    configuration::PrinterConfig,
+};
+
+const DEFAULT_PRINT_HISTORY_FILE: &str = "print_history.jsonl";
+
+/// Append-only log of finished prints, one JSON record per line, so a record
+/// is never lost to an interrupted rewrite of the whole file
+pub struct PrintHistory {
+    path: String,
+}
+
+impl PrintHistory {
+    pub fn new(config: &PrinterConfig) -> PrintHistory {
+        PrintHistory {
+            path: config
+                .print_history_file
+                .clone()
+                .unwrap_or(DEFAULT_PRINT_HISTORY_FILE.to_string()),
+        }
+    }
+
+    pub fn record(&self, record: &PrintRecord) {
+        let result = serde_json::to_string(record)
+            .map_err(io::Error::other)
+            .and_then(|line| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?
+                    .write_all(format!("{}\n", line).as_bytes())
+            });
+
+        if let Err(e) = result {
+            tracing::error!("Unable to save print history to {}: {}", self.path, e);
+        }
+    }
+
+    /// Every recorded print, oldest first. Lines which can't be parsed, such as
+    /// one cut short by a power loss, are skipped
+    pub fn load(&self) -> Result<Vec<PrintRecord>, io::Error> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        Ok(content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| {
+                serde_json::from_str(line)
+                    .inspect_err(|e| tracing::warn!("Skipping print history entry: {}", e))
+                    .ok()
+            })
+            .collect())
+    }
+}
+
+pub fn print_stats(records: &[PrintRecord]) -> PrintStats {
+    let mut stats = records
+        .iter()
+        .fold(PrintStats::default(), |mut stats, record| {
+            stats.total_prints += 1;
+            match record.outcome {
+                PrintOutcome::Completed => stats.completed_prints += 1,
+                PrintOutcome::Failed => stats.failed_prints += 1,
+                PrintOutcome::Cancelled => stats.cancelled_prints += 1,
+            }
+            stats.total_print_seconds += record.duration_seconds();
+            stats.total_material_used += record.used_material;
+            stats
+        });
+
+    if stats.total_prints > 0 {
+        stats.success_rate = Some(stats.completed_prints as f64 / stats.total_prints as f64);
+    }
+
+    // Count each file's prints, along with when it was last printed to break ties
+    let mut print_counts: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (index, record) in records.iter().enumerate() {
+        let (count, last_printed) = print_counts.entry(&record.file_data.path).or_default();
+        *count += 1;
+        *last_printed = index;
+    }
+    if let Some((path, (count, _))) = print_counts
+        .into_iter()
+        .max_by_key(|(_, count_and_last_printed)| *count_and_last_printed)
+    {
+        stats.most_printed_file = Some(path.to_string());
+        stats.most_printed_count = count;
+    }
+
+    stats
+}
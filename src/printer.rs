@@ -1,42 +1,126 @@
+use std::collections::{BTreeMap, VecDeque};
 use std::io;
+use std::mem::{self, Discriminant};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, watch};
 use tokio_util::sync::CancellationToken;
 
-use crate::api_objects::DisplayTest;
 use crate::api_objects::FileMetadata;
 use crate::api_objects::PhysicalState;
+use crate::api_objects::PrintEvent;
+use crate::api_objects::PrintEventType;
 use crate::api_objects::PrintMetadata;
+use crate::api_objects::PrintOutcome;
+use crate::api_objects::PrintRecord;
+use crate::api_objects::PrintSettings;
 use crate::api_objects::PrinterState;
 use crate::api_objects::PrinterStatus;
+use crate::api_objects::UpdatePrintUserMetadata;
+use crate::api_objects::{DisplayTest, ExposureTest};
 use crate::configuration::*;
 use crate::display::*;
-use crate::error::OdysseyError;
+use crate::error::{ErrorType, OdysseyError};
+use crate::print_history::PrintHistory;
+use crate::printfile::open_print_file;
 use crate::printfile::Layer;
 use crate::printfile::PrintFile;
-use crate::sl1::*;
-use tokio::time::{interval, sleep, Duration};
+use crate::sl1::Sl1;
+use crate::uv_usage::UvUsage;
+use tokio::time::{interval, sleep, sleep_until, Duration, Instant};
 
-pub struct Printer<'a, T: HardwareControl> {
-    pub config: &'a PrinterConfig,
+/// Weight given to the most recent layer when updating the layer duration estimate
+const LAYER_DURATION_EMA_WEIGHT: f64 = 0.3;
+
+/// Most layers which may be re-exposed by a single ReexposeLayers operation
+const MAX_REEXPOSE_LAYERS: usize = 5;
+
+/// Repeats of an idempotent operation received within this long of handling
+/// the last are dropped
+const DUPLICATE_OPERATION_WINDOW: Duration = Duration::from_secs(1);
+
+/// Time for a blanked frame to reach the panel, covering a few refreshes at
+/// the usual 60Hz
+const BLANK_SCANOUT_TIME: Duration = Duration::from_millis(50);
+
+/// Thickness of each layer cured by an exposure test, in microns
+const EXPOSURE_TEST_LAYER_HEIGHT: u32 = 50;
+
+pub struct Printer<T: HardwareControl> {
+    pub config: PrinterConfig,
     pub display: PrintDisplay,
     pub hardware_controller: T,
     pub state: PrinterState,
     pub operation_receiver: mpsc::Receiver<Operation>,
+    /// Safety critical operations, handled ahead of anything in the operation
+    /// queue
+    pub control_receiver: mpsc::Receiver<Operation>,
     pub status_sender: broadcast::Sender<PrinterState>,
+    pub event_sender: broadcast::Sender<PrintEvent>,
     pub cancellation_token: CancellationToken,
+    pub layer_duration_ema: Option<f64>,
+    pub pause_layers: Vec<usize>,
+    pub print_started: Option<Instant>,
+    pub cure_started: Option<Instant>,
+    /// When the cure watchdog task forces the UV array off, while curing with
+    /// max_cure_seconds set
+    pub cure_deadline: watch::Sender<Option<Instant>>,
+    pub print_queue: VecDeque<QueuedPrint>,
+    pub queue_resume_at: Option<Instant>,
+    pub resin_profile: Option<ResinProfile>,
+    pub resume_at: Option<Instant>,
+    pub last_idempotent_operation: Option<(Discriminant<Operation>, Instant)>,
+    /// Set by an emergency stop, holding the printer in shutdown until Odyssey
+    /// is restarted, even once the hardware reports ready again
+    pub emergency_stopped: bool,
+    pub uv_usage: UvUsage,
+    pub print_history: PrintHistory,
+    /// Reloaded config file waiting for the print to finish before it's applied
+    pub pending_configuration: Option<Box<Configuration>>,
+    /// When the status was last sent, for spacing out idle heartbeats
+    pub last_status_sent: Instant,
+}
+
+/// A print waiting in the queue, along with the resin profile it was queued with
+#[derive(Clone, Debug)]
+pub struct QueuedPrint {
+    pub file_data: FileMetadata,
+    pub profile: Option<ResinProfile>,
 }
 
-impl<T: HardwareControl> Printer<'_, T> {
+/// Sends safety critical operations, which jump ahead of the operation queue.
+/// An emergency stop interrupts whatever the printer is doing, while the
+/// others are handled as soon as the printer next checks for operations
+#[derive(Clone, Debug)]
+pub struct PrioritySender {
+    pub control: mpsc::Sender<Operation>,
+    pub emergency: mpsc::Sender<Operation>,
+}
+
+impl PrioritySender {
+    pub fn try_send(&self, operation: Operation) -> Result<(), OdysseyError> {
+        match operation {
+            Operation::EmergencyStop => self.emergency.try_send(operation),
+            _ => self.control.try_send(operation),
+        }
+        .map_err(OdysseyError::from)
+    }
+}
+
+impl<T: HardwareControl> Printer<T> {
+    #[allow(clippy::too_many_arguments)]
     pub async fn start_printer(
         config: Arc<Configuration>,
         display: PrintDisplay,
         mut hardware_controller: T,
         operation_receiver: mpsc::Receiver<Operation>,
+        control_receiver: mpsc::Receiver<Operation>,
+        emergency_receiver: mpsc::Receiver<Operation>,
         status_sender: broadcast::Sender<PrinterState>,
+        event_sender: broadcast::Sender<PrintEvent>,
         cancellation_token: CancellationToken,
     ) {
         hardware_controller
@@ -46,8 +130,17 @@ impl<T: HardwareControl> Printer<'_, T> {
             config.printer.default_lift.to_string(),
         );
 
+        let (cure_deadline, deadline_receiver) = watch::channel(None);
+        let (watchdog_sender, watchdog_receiver) = mpsc::channel::<Operation>(1);
+        tokio::spawn(run_cure_watchdog(
+            deadline_receiver,
+            watchdog_sender,
+            cancellation_token.clone(),
+        ));
+
+        let frame_buffer_available = display.frame_buffer_available();
         let mut printer = Printer {
-            config: &config.printer,
+            config: config.printer.clone(),
             display,
             hardware_controller,
             state: PrinterState {
@@ -57,52 +150,170 @@ impl<T: HardwareControl> Printer<'_, T> {
                 physical_state: PhysicalState {
                     z: 0.0,
                     z_microns: 0,
-                    curing: false,
+                    curing: BTreeMap::new(),
+                    temperature: None,
+                    firmware_version: None,
                 },
-                status: PrinterStatus::Shutdown,
+                status: PrinterStatus::Initializing,
+                time_remaining_seconds: None,
+                resume_countdown_seconds: None,
+                print_settings: None,
+                print_queue: Vec::new(),
+                uv_on_seconds: 0.0,
+                print_started_at: None,
+                serial_connected: None,
+                frame_buffer_available,
+                error: None,
             },
             operation_receiver,
+            control_receiver,
             status_sender,
+            event_sender,
             cancellation_token,
+            layer_duration_ema: None,
+            pause_layers: Vec::new(),
+            print_started: None,
+            cure_started: None,
+            cure_deadline,
+            print_queue: VecDeque::new(),
+            queue_resume_at: None,
+            resin_profile: None,
+            resume_at: None,
+            last_idempotent_operation: None,
+            emergency_stopped: false,
+            uv_usage: UvUsage::load(&config.printer),
+            print_history: PrintHistory::new(&config.printer),
+            pending_configuration: None,
+            last_status_sent: Instant::now(),
         };
 
-        printer.start_statemachine().await
+        printer
+            .start_statemachine(emergency_receiver, watchdog_receiver)
+            .await
     }
 
     pub async fn print_event_loop(&mut self) -> Result<(), io::Error> {
-        let mut file: Box<dyn PrintFile + Send> =
-            Box::new(Sl1::from_file(self.get_file_data().unwrap())?);
-
-        let layer_height = file.get_layer_height();
-
-        // Get movement values from file, or configured defaults
-        let lift = file
-            .get_lift()
-            .unwrap_or((self.config.default_lift * 1000.0).trunc() as u32);
-        let up_speed = file.get_up_speed().unwrap_or(self.config.default_up_speed);
-        let down_speed = file
-            .get_down_speed()
-            .unwrap_or(self.config.default_down_speed);
-
-        let wait_before_exposure = file
-            .get_wait_before_exposure()
-            .unwrap_or(self.config.default_wait_before_exposure);
-        let wait_after_exposure = file
-            .get_wait_after_exposure()
-            .unwrap_or(self.config.default_wait_after_exposure);
+        // The print data can be missing if the state changed between the event
+        // loop checking the status and entering here
+        let Some(file_data) = self.get_file_data() else {
+            self.reject_print("no print file data is available".to_string())
+                .await;
+            return Ok(());
+        };
+        // As can the file itself, if it was removed after the print was started
+        let mut file = match open_print_file(file_data) {
+            Ok(file) => file,
+            Err(e) => {
+                self.reject_print(format!("unable to open the print file: {}", e))
+                    .await;
+                return Ok(());
+            }
+        };
+
+        let settings = file.print_settings(self.resin_profile.as_ref(), &self.config);
+        self.state.print_settings = Some(settings);
+        if let Some(print_data) = self.state.print_data.as_mut() {
+            print_data.estimated_print_time =
+                Some(file.recompute_print_time(&settings, &self.config));
+        }
 
         let mut pause_interv = interval(Duration::from_millis(100));
 
+        // Fetch and generate the first frame
+        let mut optional_frame = match Frame::from_layer(file.get_layer_data(0).await).await {
+            Ok(frame) => frame,
+            Err(e) => {
+                self.reject_print(format!("unable to decode first layer: {}", e))
+                    .await;
+                return Ok(());
+            }
+        };
+
+        // Skip blank layers at the bottom of the file, so that the first layer
+        // with content is printed as layer 0
+        let mut layer_offset = 0;
+        if self.config.skip_leading_empty_layers.unwrap_or(false) {
+            while optional_frame
+                .as_ref()
+                .is_some_and(|frame| frame.exposed_pixels() == 0)
+            {
+                layer_offset += 1;
+                optional_frame =
+                    match Frame::from_layer(file.get_layer_data(layer_offset).await).await {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            self.reject_print(format!(
+                                "unable to decode layer {}: {}",
+                                layer_offset, e
+                            ))
+                            .await;
+                            return Ok(());
+                        }
+                    };
+            }
+            if let Some(frame) = optional_frame.as_mut() {
+                frame.exposure_time = file.get_exposure_time(0);
+            }
+            if layer_offset > 0 {
+                tracing::info!("Skipping {} leading empty layers", layer_offset);
+                self.skip_leading_layers(layer_offset);
+            }
+        }
+
         self.hardware_controller.add_print_variable(
             "total_layers".to_string(),
-            file.get_layer_count().to_string(),
+            file.get_layer_count()
+                .saturating_sub(layer_offset)
+                .to_string(),
         );
 
-        // Execute start_print command, then report state
-        self.wrapped_start_print().await;
+        // Refuse to move and wait through a print with nothing on screen
+        if !self.display.frame_buffer_available()
+            && !self
+                .display
+                .config
+                .print_without_frame_buffer
+                .unwrap_or(false)
+        {
+            self.reject_print(format!(
+                "framebuffer {} isn't available",
+                self.display.config.frame_buffer
+            ))
+            .await;
+            return Ok(());
+        }
 
-        // Fetch and generate the first frame
-        let mut optional_frame = Frame::from_layer(file.get_layer_data(0).await).await;
+        // Refuse to print layers sliced for a different display
+        if let Some(Err(e)) = optional_frame
+            .as_ref()
+            .map(|frame| self.display.check_frame(frame))
+        {
+            self.reject_print(format!("first layer doesn't fit the display: {}", e))
+                .await;
+            return Ok(());
+        }
+
+        // Refuse to print a first layer too small to hold the model to the plate
+        if let Some(min_area) = self.config.min_first_layer_area {
+            let first_layer_area = optional_frame
+                .as_ref()
+                .map(Frame::exposed_pixels)
+                .unwrap_or(0);
+            if first_layer_area < min_area {
+                self.reject_print(format!(
+                    "first layer exposes {} pixels but at least {} are required",
+                    first_layer_area, min_area
+                ))
+                .await;
+                return Ok(());
+            }
+        }
+
+        // Execute start_print command, then report state
+        if let Err(e) = self.wrapped_start_print().await {
+            self.abort_print(e).await;
+            return Ok(());
+        }
 
         loop {
             // Run any requested operations that may change the printer state
@@ -119,37 +330,107 @@ impl<T: HardwareControl> Printer<'_, T> {
                         match optional_frame {
                             // More frames exist, continue printing
                             Some(cur_frame) => {
+                                let layer_started = Instant::now();
                                 self.hardware_controller
                                     .add_print_variable("layer".to_string(), layer.to_string());
                                 // Start a task to fetch and generate the next
                                 // frame while we're exposing the current one
                                 let gen_next_frame = tokio::spawn(Frame::from_layer(
-                                    file.get_layer_data(layer + 1).await,
+                                    Self::get_printed_layer(file.as_mut(), layer + 1, layer_offset)
+                                        .await,
                                 ));
 
                                 // Print the current frame by moving into
                                 // position and curing
-                                self.print_frame(
-                                    cur_frame,
-                                    layer,
-                                    layer_height,
-                                    lift,
-                                    up_speed,
-                                    down_speed,
-                                    wait_before_exposure,
-                                    wait_after_exposure,
-                                )
-                                .await;
+                                if let Err(e) = self
+                                    .print_layer(
+                                        cur_frame,
+                                        layer,
+                                        self.state.print_settings.unwrap_or(settings),
+                                    )
+                                    .await
+                                {
+                                    // Only a failed display write or cure is
+                                    // worth retrying, a bad frame never will be
+                                    if !matches!(e.error_type, ErrorType::HardwareError) {
+                                        self.abort_print(e).await;
+                                        break;
+                                    }
+                                    tracing::error!(
+                                        "Unable to print layer {}, pausing print: {}",
+                                        layer,
+                                        e
+                                    );
+                                    gen_next_frame.abort();
+
+                                    // Reload the layer so it can be printed
+                                    // again once the print is resumed
+                                    optional_frame = match Frame::from_layer(
+                                        Self::get_printed_layer(file.as_mut(), layer, layer_offset)
+                                            .await,
+                                    )
+                                    .await
+                                    {
+                                        Ok(frame) => frame,
+                                        Err(e) => {
+                                            self.abort_print(e).await;
+                                            break;
+                                        }
+                                    };
+                                    self.pause_print().await;
+                                    continue;
+                                }
 
                                 // Await generation of the next frame
-                                optional_frame =
-                                    gen_next_frame.await.expect("Layer generation task failed");
+                                optional_frame = match gen_next_frame
+                                    .await
+                                    .expect("Layer generation task failed")
+                                {
+                                    Ok(frame) => frame,
+                                    Err(e) => {
+                                        self.abort_print(e).await;
+                                        break;
+                                    }
+                                };
+
+                                self.update_layer_duration(layer_started.elapsed());
 
                                 // Bump current layer
                                 self.set_layer(layer + 1).await;
+
+                                // Pause if one was scheduled for the new layer
+                                if self.pause_layers.first() == Some(&(layer + 1)) {
+                                    self.pause_layers.remove(0);
+                                    tracing::info!("Pausing at scheduled layer {}", layer + 1);
+                                    self.pause_print().await;
+                                }
+                            }
+                            // No more frames remain, end print. Running out
+                            // before the last layer means the file is damaged,
+                            // which mustn't be reported as a completed print
+                            None => {
+                                let layer_count = self
+                                    .state
+                                    .print_data
+                                    .as_ref()
+                                    .map_or(0, |print_data| print_data.layer_count);
+                                if layer < layer_count {
+                                    self.abort_print(OdysseyError::file_error(
+                                        Box::new(io::Error::new(
+                                            io::ErrorKind::UnexpectedEof,
+                                            format!(
+                                                "layer {} of {} is missing from the print file",
+                                                layer + 1,
+                                                layer_count
+                                            ),
+                                        )),
+                                        500,
+                                    ))
+                                    .await;
+                                    break;
+                                }
+                                self.end_print().await
                             }
-                            // No more frames remain, end print
-                            None => self.end_print().await,
                         }
                     }
                 }
@@ -159,110 +440,382 @@ impl<T: HardwareControl> Printer<'_, T> {
         Ok(())
     }
 
+    // Fetch the file's layer printed as the given layer, after skipping
+    // layer_offset leading layers. Exposure follows the printed layer, so the
+    // first layers printed still get the file's bottom exposure
+    async fn get_printed_layer(
+        file: &mut (dyn PrintFile + Send),
+        layer: usize,
+        layer_offset: usize,
+    ) -> Option<Layer> {
+        let mut layer_data = file.get_layer_data(layer + layer_offset).await?;
+        layer_data.exposure_time = file.get_exposure_time(layer);
+        Some(layer_data)
+    }
+
+    // Drop skipped layers from the print's layer count and estimated time, so
+    // progress is reported against the layers actually printed
+    fn skip_leading_layers(&mut self, skipped: usize) {
+        if let Some(print_data) = self.state.print_data.as_mut() {
+            let layer_count = print_data.layer_count;
+            let printed = layer_count.saturating_sub(skipped);
+            if layer_count > 0 {
+                print_data.print_time *= printed as f64 / layer_count as f64;
+            }
+            print_data.layer_count = printed;
+        }
+    }
+
+    // Print the frame, retrying the whole layer once after a hardware failure
+    // if configured, before falling back to pausing the print
+    async fn print_layer(
+        &mut self,
+        cur_frame: Frame,
+        layer: usize,
+        settings: PrintSettings,
+    ) -> Result<(), OdysseyError> {
+        let retry_frame = self
+            .config
+            .retry_failed_layer
+            .unwrap_or(false)
+            .then(|| cur_frame.clone());
+
+        match (
+            self.print_frame(cur_frame, layer, settings).await,
+            retry_frame,
+        ) {
+            (Err(e), Some(retry_frame)) if matches!(e.error_type, ErrorType::HardwareError) => {
+                tracing::warn!("Layer {} failed, retrying it: {}", layer, e);
+                self.print_frame(retry_frame, layer, settings).await
+            }
+            (result, _) => result,
+        }
+    }
+
     async fn print_frame(
         &mut self,
         cur_frame: Frame,
         layer: usize,
-        layer_height: u32,
-        lift: u32,
-        up_speed: f64,
-        down_speed: f64,
-        wait_before_exposure: f64,
-        wait_after_exposure: f64,
-    ) {
+        settings: PrintSettings,
+    ) -> Result<(), OdysseyError> {
+        // Check the frame before moving, so a bad one is caught before curing
+        self.display.check_frame(&cur_frame)?;
+
         tracing::info!("Begin layer {}", layer);
-        self.wrapped_start_layer(layer).await;
-        let layer_z = ((layer + 1) as u32) * layer_height;
+        self.wrapped_start_layer(layer).await?;
+        let layer_z = ((layer + 1) as u32) * settings.layer_height;
         //let lift_z = layer_z+
 
-        let exposure_time = cur_frame.exposure_time;
+        // A layer beyond the soft limits can't be printed, and curing it at
+        // the wrong height would only ruin the print
+        self.config
+            .check_z(layer_z as f64 / 1000.0)
+            .map_err(|e| OdysseyError::print_error(Box::new(e), 400))?;
+
+        let exposure_time =
+            cur_frame.exposure_time * settings.exposure_multiplier * self.top_fade_factor(layer);
 
-        // Move the plate up first, then down into position
+        // Move the plate up first, unless the file disables the first layer
+        // lift, then down into position
         tracing::info!("Moving to layer position {}", layer_z);
 
-        self.wrapped_move(layer_z + lift, up_speed).await;
-        self.wrapped_move(layer_z, down_speed).await;
+        if layer > 0 || settings.first_layer_lift {
+            self.wrapped_move(layer_z + settings.lift, settings.up_speed)
+                .await?;
+        } else {
+            tracing::info!("Skipping lift before the first layer");
+        }
+        self.wrapped_move(layer_z, settings.down_speed).await?;
+
+        // Let the resin level out after lowering the plate
+        if settings.settle_time > 0.0 {
+            tracing::info!("Settling for {}s after lowering", settings.settle_time);
+            sleep(Duration::from_secs_f64(settings.settle_time)).await;
+        }
 
         // Wait for configured time before curing
-        tracing::info!("Waiting for {}s before cure", wait_before_exposure);
-        sleep(Duration::from_secs_f64(wait_before_exposure)).await;
+        tracing::info!("Waiting for {}s before cure", settings.wait_before_exposure);
+        sleep(Duration::from_secs_f64(settings.wait_before_exposure)).await;
 
         // Display the current frame to the LCD
         tracing::info!("Loading layer to display");
-        self.display.display_frame(cur_frame);
+        self.display.set_status_text(self.status_text(layer));
+        self.display.display_frame(cur_frame)?;
 
         // Activate the UV array for the prescribed length of time
         tracing::info!("Curing layer for {}s", exposure_time);
-        self.wrapped_start_cure().await;
-        sleep(Duration::from_secs_f64(exposure_time)).await;
-        self.wrapped_stop_cure().await;
+        self.expose(exposure_time).await?;
+
+        let mut wait_after_exposure = Duration::from_secs_f64(settings.wait_after_exposure);
+        if self.display.config.blank_between_layers.unwrap_or(false) {
+            tracing::info!("Blanking display until the next layer is in position");
+            self.display.display_blank()?;
+            // The write lands in the buffer straight away, but the panel only
+            // shows it from its next refresh, which must come before the lift
+            wait_after_exposure = wait_after_exposure.max(BLANK_SCANOUT_TIME);
+        }
 
         // Wait for configured time after curing
-        tracing::info!("Waiting for {}s after cure", wait_after_exposure);
-        sleep(Duration::from_secs_f64(wait_after_exposure)).await;
+        tracing::info!(
+            "Waiting for {}s after cure",
+            wait_after_exposure.as_secs_f64()
+        );
+        sleep(wait_after_exposure).await;
+        Ok(())
     }
 
-    async fn wrapped_start_print(&mut self) {
-        if let Ok(physical_state) = self.hardware_controller.start_print().await {
-            self.update_physical_state(physical_state).await;
-        } else {
-            self.shutdown().await;
+    // Exposure factor for the given layer from the configured top fade, which
+    // ramps down to final_exposure_factor on the last layer
+    fn top_fade_factor(&self, layer: usize) -> f64 {
+        match (&self.config.top_fade, &self.state.print_data) {
+            (Some(top_fade), Some(print_data)) => {
+                top_fade.exposure_factor(layer, print_data.layer_count)
+            }
+            _ => 1.0,
         }
     }
 
-    async fn wrapped_start_layer(&mut self, layer: usize) {
-        if let Ok(physical_state) = self.hardware_controller.start_layer(layer).await {
-            self.update_physical_state(physical_state).await;
-        } else {
-            self.shutdown().await;
+    // Summarize print progress for the display's status overlay
+    fn status_text(&self, layer: usize) -> Option<String> {
+        let layer_count = self.state.print_data.as_ref()?.layer_count;
+        let elapsed = self.print_started?.elapsed().as_secs();
+
+        Some(format!(
+            "L {}/{} {}:{:02}:{:02}",
+            layer + 1,
+            layer_count,
+            elapsed / 3600,
+            (elapsed / 60) % 60,
+            elapsed % 60
+        ))
+    }
+
+    // Fold the duration of the last layer into the running per-layer estimate
+    fn update_layer_duration(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        self.layer_duration_ema = Some(match self.layer_duration_ema {
+            Some(ema) => ema + LAYER_DURATION_EMA_WEIGHT * (seconds - ema),
+            None => seconds,
+        });
+    }
+
+    /// Estimate the time remaining in the current print. Uses the measured
+    /// per-layer duration once available, otherwise falls back to scaling the
+    /// recomputed print time, or the file's own print_time, by the fraction of
+    /// layers remaining
+    fn estimate_time_remaining(&self) -> Option<f64> {
+        if !matches!(self.state.status, PrinterStatus::Printing) {
+            return None;
         }
+        let print_data = self.state.print_data.as_ref()?;
+        let remaining_layers = print_data.layer_count.saturating_sub(self.state.layer?) as f64;
+
+        match self.layer_duration_ema {
+            Some(ema) => Some(ema * remaining_layers),
+            None if print_data.layer_count > 0 => {
+                let print_time = print_data
+                    .estimated_print_time
+                    .unwrap_or(print_data.print_time);
+                Some(print_time * remaining_layers / print_data.layer_count as f64)
+            }
+            None => None,
+        }
+    }
+
+    async fn wrapped_start_print(&mut self) -> Result<(), OdysseyError> {
+        let result = self.hardware_controller.start_print().await;
+        self.apply_hardware_result(result).await?;
+        self.mix_resin().await
+    }
+
+    // Slowly raise and lower the plate to stir settled resin before printing
+    async fn mix_resin(&mut self) -> Result<(), OdysseyError> {
+        let Some(mixing) = self.config.resin_mixing.clone() else {
+            return Ok(());
+        };
+        let bottom = self
+            .state
+            .print_settings
+            .map(|settings| settings.layer_height)
+            .unwrap_or(0);
+        let top = (bottom + (mixing.depth * 1000.0).trunc() as u32)
+            .min((self.config.max_z * 1000.0).trunc() as u32);
+
+        tracing::info!(
+            "Mixing resin with {} dips to {}mm at {}mm/s",
+            mixing.count,
+            mixing.depth,
+            mixing.speed
+        );
+        for _ in 0..mixing.count {
+            if !matches!(self.state.status, PrinterStatus::Printing) {
+                break;
+            }
+            self.wrapped_move(top, mixing.speed).await?;
+            self.wrapped_move(bottom, mixing.speed).await?;
+        }
+        Ok(())
+    }
+
+    // Update printer state from the result of a hardware command. Gcode which
+    // can't be built, such as one using an unknown substitution, is never sent
+    // so that error is returned to the caller. So is a failure while the serial
+    // connection is down, which pauses a print until it's reconnected rather
+    // than exiting. Any other failure leaves the hardware in an unknown state,
+    // so shuts down
+    async fn apply_hardware_result(
+        &mut self,
+        result: Result<PhysicalState, OdysseyError>,
+    ) -> Result<(), OdysseyError> {
+        match result {
+            Ok(physical_state) => {
+                self.update_physical_state(physical_state).await;
+                Ok(())
+            }
+            Err(e) if matches!(e.error_type, ErrorType::ConfigurationError) => Err(e),
+            Err(e) if self.hardware_controller.is_connected() == Some(false) => {
+                tracing::warn!("Hardware command failed while disconnected: {}", e);
+                Err(e)
+            }
+            Err(_) => {
+                self.shutdown().await;
+                Ok(())
+            }
+        }
+    }
+
+    async fn wrapped_start_layer(&mut self, layer: usize) -> Result<(), OdysseyError> {
+        let result = self.hardware_controller.start_layer(layer).await;
+        self.apply_hardware_result(result).await
     }
 
     // Execute command and update printer state
     async fn wrapped_command(&mut self, command: String) {
-        if let Ok(physical_state) = self.hardware_controller.manual_command(command).await {
-            self.update_physical_state(physical_state).await;
-        } else {
-            self.shutdown().await;
+        let result = self.hardware_controller.manual_command(command).await;
+        if let Err(e) = self.apply_hardware_result(result).await {
+            tracing::error!("Unable to execute command: {}", e);
         }
     }
 
     // Home and update printer state
     async fn wrapped_home(&mut self) {
-        if let Ok(physical_state) = self.hardware_controller.home().await {
-            self.update_physical_state(physical_state).await;
-        } else {
-            self.shutdown().await;
+        let result = self.hardware_controller.home().await;
+        if let Err(e) = self.apply_hardware_result(result).await {
+            tracing::error!("Unable to home: {}", e);
         }
     }
 
     // Move and update printer state
-    async fn wrapped_move(&mut self, z: u32, speed: f64) {
+    async fn wrapped_move(&mut self, z: u32, speed: f64) -> Result<(), OdysseyError> {
         self._wrapped_move(z, speed, false).await
     }
     async fn wrapped_manual_move(&mut self, z: u32, speed: f64) {
-        self._wrapped_move(z, speed, true).await
+        if let Err(e) = self._wrapped_move(z, speed, true).await {
+            tracing::error!("Unable to move: {}", e);
+        }
     }
-    async fn _wrapped_move(&mut self, z: u32, speed: f64, manual: bool) {
-        if let Ok(physical_state) = self.hardware_controller.move_z(z, speed, manual).await {
-            self.update_physical_state(physical_state).await;
+    // Manual moves beyond the soft Z limits are rejected, while any other move
+    // is clamped to them, as the plate must never be driven past its travel
+    async fn _wrapped_move(
+        &mut self,
+        z: u32,
+        speed: f64,
+        manual: bool,
+    ) -> Result<(), OdysseyError> {
+        let z = if manual {
+            self.config
+                .check_z(z as f64 / 1000.0)
+                .map_err(|e| OdysseyError::configuration_error(Box::new(e), 400))?;
+            z
         } else {
-            self.shutdown().await;
+            let (min_z, max_z) = self.config.z_limits();
+            let clamped = z.clamp(
+                (min_z * 1000.0).trunc() as u32,
+                (max_z * 1000.0).trunc() as u32,
+            );
+            if clamped != z {
+                tracing::warn!(
+                    "Clamping move to {}mm into the Z limits, moving to {}mm",
+                    z as f64 / 1000.0,
+                    clamped as f64 / 1000.0
+                );
+            }
+            clamped
+        };
+
+        let result = self.hardware_controller.move_z(z, speed, manual).await;
+        self.apply_hardware_result(result).await
+    }
+
+    // Start curing, returning any failure so the layer may be retried. Every
+    // zone is switched off again, in case only some of them started
+    async fn start_cure(&mut self) -> Result<(), OdysseyError> {
+        match self.hardware_controller.start_curing(None).await {
+            Ok(physical_state) => {
+                self.update_physical_state(physical_state).await;
+                Ok(())
+            }
+            Err(e) => {
+                self.wrapped_stop_cure().await;
+                Err(e)
+            }
         }
     }
 
-    // Start cure and update printer state
-    async fn wrapped_start_cure(&mut self) {
-        if let Ok(physical_state) = self.hardware_controller.start_curing().await {
-            self.update_physical_state(physical_state).await;
-        } else {
-            self.shutdown().await;
+    // Toggle curing for a single zone, or every zone, and update printer state
+    async fn wrapped_manual_cure(&mut self, cure: bool, zone: Option<String>) {
+        let zones = zone.map(|zone| vec![zone]);
+        let result = match cure {
+            true => self.hardware_controller.start_curing(zones).await,
+            false => self.hardware_controller.stop_curing(zones).await,
+        };
+        if let Err(e) = self.apply_hardware_result(result).await {
+            tracing::error!("Unable to toggle curing: {}", e);
+            // Some zones may have been left on before the failure
+            self.wrapped_stop_cure().await;
+        }
+    }
+
+    // Cure for the given time. An exposure longer than max_cure_seconds is cut
+    // short by the cure watchdog. Failing to stop curing still shuts down, as
+    // the UV array may be left on
+    async fn expose(&mut self, exposure_time: f64) -> Result<(), OdysseyError> {
+        self.start_cure().await?;
+        sleep(Duration::from_secs_f64(exposure_time)).await;
+        self.wrapped_stop_cure().await;
+        Ok(())
+    }
+
+    // Force the UV array off, then shut down rather than continuing with
+    // over-cured resin or a hung exposure
+    async fn trip_cure_watchdog(&mut self) {
+        tracing::error!(
+            "Curing exceeded the configured limit of {}s, shutting down",
+            self.config.max_cure_seconds.unwrap_or_default()
+        );
+        match self.hardware_controller.stop_curing(None).await {
+            Ok(physical_state) => self.update_physical_state(physical_state).await,
+            Err(e) => tracing::error!("Unable to stop curing: {}", e),
+        }
+        self.end_cure_timing();
+        self.shutdown().await;
+    }
+
+    // Poll for reported values, only updating state when they've changed
+    async fn wrapped_poll_status(&mut self) {
+        match self.hardware_controller.poll_status().await {
+            Ok(physical_state) => {
+                if physical_state.temperature != self.state.physical_state.temperature {
+                    self.update_physical_state(physical_state).await;
+                }
+            }
+            Err(e) => tracing::warn!("Unable to poll hardware status: {}", e),
         }
     }
 
     // Stop cure and update printer state
     async fn wrapped_stop_cure(&mut self) {
-        if let Ok(physical_state) = self.hardware_controller.stop_curing().await {
+        if let Ok(physical_state) = self.hardware_controller.stop_curing(None).await {
             self.update_physical_state(physical_state).await;
         } else {
             self.shutdown().await;
@@ -282,22 +835,200 @@ impl<T: HardwareControl> Printer<'_, T> {
         self.update_layer(layer).await;
     }
 
-    pub async fn start_print(&mut self, file_data: FileMetadata) -> Result<(), io::Error> {
+    pub async fn start_print(
+        &mut self,
+        file_data: FileMetadata,
+        profile: Option<ResinProfile>,
+    ) -> Result<(), io::Error> {
         tracing::info!("Starting Print");
 
-        let print_data = Sl1::from_file(file_data)?.get_metadata();
+        let print_data = open_print_file(file_data)?.get_metadata();
         self.enter_printing_state(print_data).await;
+        if matches!(self.state.status, PrinterStatus::Printing) {
+            self.resin_profile = profile;
+        }
         Ok(())
     }
 
     async fn end_print(&mut self) {
+        self.increment_print_count();
+        self._end_print(PrintEventType::PrintCompleted).await;
+
+        // Continue with the next queued print once the cooldown has passed
+        if !self.print_queue.is_empty() {
+            let cooldown = self.config.queue_cooldown_seconds.unwrap_or(0.0);
+            tracing::info!("Starting next queued print in {}s", cooldown);
+            self.queue_resume_at = Some(Instant::now() + Duration::from_secs_f64(cooldown));
+        }
+    }
+
+    fn enqueue_print(&mut self, file_data: FileMetadata, profile: Option<ResinProfile>) {
+        tracing::info!("Queueing print of {}", file_data.name);
+        self.print_queue
+            .push_back(QueuedPrint { file_data, profile });
+
+        // Start right away if nothing is currently printing
+        if matches!(self.state.status, PrinterStatus::Idle) && self.queue_resume_at.is_none() {
+            self.queue_resume_at = Some(Instant::now());
+        }
+    }
+
+    // Remove a single queued print, or clear the queue if no index is given
+    fn remove_queued_print(&mut self, index: Option<usize>) {
+        match index {
+            Some(index) => match self.print_queue.remove(index) {
+                Some(queued) => {
+                    tracing::info!("Removed {} from print queue", queued.file_data.name)
+                }
+                None => tracing::warn!("No queued print at index {}", index),
+            },
+            None => {
+                tracing::info!("Clearing print queue");
+                self.print_queue.clear();
+                self.queue_resume_at = None;
+            }
+        }
+    }
+
+    // Re-read the metadata of the current and queued print files from disk, so
+    // changes made since they were selected are picked up. Layers of a running
+    // print are still read from the file as it was opened
+    fn refresh_files(&mut self) {
+        if let Some(print_data) = self.state.print_data.as_mut() {
+            match open_print_file(print_data.file_data.clone()) {
+                Ok(file) => {
+                    let refreshed = file.get_metadata();
+                    tracing::info!("Refreshed metadata of {}", refreshed.file_data.name);
+                    print_data.file_data = refreshed.file_data;
+                    print_data.user_metadata = refreshed.user_metadata;
+                }
+                Err(e) => tracing::warn!(
+                    "Unable to refresh metadata of {}: {}",
+                    print_data.file_data.name,
+                    e
+                ),
+            }
+        }
+
+        for QueuedPrint { file_data, .. } in self.print_queue.iter_mut() {
+            match FileMetadata::from_path(
+                &file_data.path,
+                &file_data.parent_path,
+                file_data.location_category.clone(),
+            ) {
+                Ok(refreshed) => *file_data = refreshed,
+                Err(e) => tracing::warn!(
+                    "Unable to refresh metadata of queued print {}: {}",
+                    file_data.name,
+                    e
+                ),
+            }
+        }
+    }
+
+    async fn start_queued_print(&mut self) {
+        if !self
+            .queue_resume_at
+            .is_some_and(|resume_at| Instant::now() >= resume_at)
+        {
+            return;
+        }
+        self.queue_resume_at = None;
+
+        if let Some(queued) = self.print_queue.pop_front() {
+            if let Err(e) = self.start_print(queued.file_data, queued.profile).await {
+                tracing::error!("Unable to start queued print: {}", e);
+            }
+            self.send_status().await;
+        }
+    }
+
+    // Abort the print after an unrecoverable error, making sure the UV array is
+    // off before running the usual end of print commands
+    async fn abort_print(&mut self, error: OdysseyError) {
+        tracing::error!("Aborting print: {}", error);
+        self.wrapped_stop_cure().await;
+        self._end_print(PrintEventType::PrintFailed).await;
+    }
+
+    // Add the print which just ended to the print history
+    fn record_print(&self, outcome: PrintOutcome) {
+        let Some(print_data) = self.state.print_data.as_ref() else {
+            return;
+        };
+        let layer_count = print_data.layer_count;
+        let layers_completed = self.state.layer.unwrap_or(0).min(layer_count);
+        let used_material = match layer_count {
+            0 => 0.0,
+            _ => print_data.used_material * layers_completed as f64 / layer_count as f64,
+        };
+
+        self.print_history.record(&PrintRecord {
+            file_data: print_data.file_data.clone(),
+            started_at: self.state.print_started_at,
+            ended_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs()),
+            layers_completed,
+            layer_count,
+            outcome,
+            used_material,
+        });
+    }
+
+    // Count the completed print in the file's metadata. Files on filesystems
+    // without extended attributes simply keep their count, the print still
+    // completes
+    fn increment_print_count(&mut self) {
+        let Some(print_data) = self.state.print_data.as_mut() else {
+            return;
+        };
+
+        let result = print_data.file_data.open_file().and_then(|file| {
+            let print_count = Sl1::get_user_metadata(&file).print_count.saturating_add(1);
+            Sl1::set_user_metadata(
+                &file,
+                UpdatePrintUserMetadata {
+                    print_count: Some(print_count),
+                    favorite: None,
+                    rating: None,
+                    tags: None,
+                },
+            )
+            .map(|_| print_count)
+        });
+
+        match result {
+            Ok(print_count) => print_data.user_metadata.print_count = print_count,
+            Err(e) => tracing::warn!(
+                "Unable to update print count of {}: {}",
+                print_data.file_data.name,
+                e
+            ),
+        }
+    }
+
+    // Refuse a print before any hardware commands have been sent for it
+    async fn reject_print(&mut self, reason: String) {
+        tracing::error!("Refusing to start print, {}", reason);
+        self.send_event(PrintEventType::PrintFailed);
+        self.set_idle().await;
+    }
+
+    async fn _end_print(&mut self, event_type: PrintEventType) {
         if let Ok(physical_state) = self.hardware_controller.end_print().await {
+            self.record_print(match event_type {
+                PrintEventType::PrintCompleted => PrintOutcome::Completed,
+                _ => PrintOutcome::Failed,
+            });
             self.hardware_controller
                 .remove_print_variable("total_layers".to_string());
             self.hardware_controller
                 .remove_print_variable("layer".to_string());
+            self.send_event(event_type);
             self.update_idle_state(physical_state).await;
-            tracing::info!("Print complete.");
+            self.uv_usage.save();
+            tracing::info!("Print ended: {}", event_type.name());
         } else {
             self.shutdown().await;
         }
@@ -305,6 +1036,12 @@ impl<T: HardwareControl> Printer<'_, T> {
 
     async fn pause_print(&mut self) {
         self.update_paused(true).await;
+        // A lift couldn't reach the controller, and would only wait out the
+        // move timeout
+        if self.hardware_controller.is_connected() == Some(false) {
+            tracing::warn!("Serial connection is down, leaving the plate in place for the pause");
+            return;
+        }
         self.wrapped_move(
             ((self.config.max_z * 1000.0).trunc() as u32).min(
                 self.state.physical_state.z_microns
@@ -312,13 +1049,201 @@ impl<T: HardwareControl> Printer<'_, T> {
             ),
             self.config.default_up_speed,
         )
-        .await;
+        .await
+        .unwrap_or_else(|e| tracing::error!("Unable to lift plate for pause: {}", e));
     }
 
     async fn resume_print(&mut self) {
+        if !self.state.paused.unwrap_or(false) {
+            return self.update_paused(false).await;
+        }
+        if self.hardware_controller.is_connected() == Some(false) {
+            tracing::warn!("Unable to resume until the serial connection returns");
+            return;
+        }
+        if self.resume_at.is_some() {
+            tracing::info!("Resume countdown already running");
+            return;
+        }
+
+        match self.config.resume_countdown_seconds {
+            Some(countdown) if countdown > 0.0 => {
+                tracing::info!("Resuming print in {}s", countdown);
+                self.resume_at = Some(Instant::now() + Duration::from_secs_f64(countdown));
+                self.send_status().await;
+            }
+            _ => self.finish_resume().await,
+        }
+    }
+
+    // Resume once the countdown has elapsed, reporting each second remaining
+    async fn check_resume_countdown(&mut self) {
+        let Some(resume_at) = self.resume_at else {
+            return;
+        };
+
+        if !matches!(self.state.status, PrinterStatus::Printing) {
+            self.resume_at = None;
+        } else if Instant::now() >= resume_at {
+            self.resume_at = None;
+            self.send_status().await;
+            self.finish_resume().await;
+        } else {
+            let remaining = resume_at
+                .saturating_duration_since(Instant::now())
+                .as_secs_f64()
+                .ceil();
+            let reported = self.state.resume_countdown_seconds.map(f64::ceil);
+            if reported != Some(remaining) {
+                self.send_status().await;
+            }
+        }
+    }
+
+    async fn finish_resume(&mut self) {
+        if self.state.paused.unwrap_or(false) {
+            // Return to the current layer position before printing continues
+            self.wrapped_move(self._get_layer_z(), self.config.default_down_speed)
+                .await
+                .unwrap_or_else(|e| tracing::error!("Unable to return to layer position: {}", e));
+        }
         self.update_paused(false).await;
     }
 
+    // Adjust the waits around exposure for the remaining layers
+    async fn set_waits(&mut self, settle: Option<f64>, before: Option<f64>, after: Option<f64>) {
+        if let Some(settings) = self.state.print_settings.as_mut() {
+            if let Some(settle) = settle {
+                tracing::info!("Setting settle time to {}s", settle);
+                settings.settle_time = settle;
+            }
+            if let Some(before) = before {
+                tracing::info!("Setting wait before exposure to {}s", before);
+                settings.wait_before_exposure = before;
+            }
+            if let Some(after) = after {
+                tracing::info!("Setting wait after exposure to {}s", after);
+                settings.wait_after_exposure = after;
+            }
+            self.send_status().await;
+        }
+    }
+
+    // Re-cure a range of already printed layers in place, without moving the
+    // plate, to repair a missed or under-cured exposure
+    async fn reexpose_layers(&mut self, start: usize, count: usize) {
+        if let Err(reason) = self.check_reexpose(start, count) {
+            tracing::warn!(
+                "Refusing to re-expose {} layers from layer {}: {}",
+                count,
+                start,
+                reason
+            );
+            return;
+        }
+
+        let Some(file_data) = self.get_file_data() else {
+            return;
+        };
+        let mut file = match open_print_file(file_data) {
+            Ok(file) => file,
+            Err(e) => {
+                tracing::error!("Unable to open print file to re-expose layers: {}", e);
+                return;
+            }
+        };
+
+        for layer in start..start + count {
+            // Stop early if a hardware failure ended the print
+            if !matches!(self.state.status, PrinterStatus::Printing) {
+                break;
+            }
+            let frame = match Frame::from_layer(file.get_layer_data(layer).await).await {
+                Ok(Some(frame)) => frame,
+                Ok(None) => {
+                    tracing::warn!("Layer {} not found, stopping re-exposure", layer);
+                    break;
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "Unable to decode layer {}, stopping re-exposure: {}",
+                        layer,
+                        e
+                    );
+                    break;
+                }
+            };
+
+            let exposure_multiplier = self
+                .state
+                .print_settings
+                .map(|settings| settings.exposure_multiplier)
+                .unwrap_or(1.0);
+            let exposure_time = frame.exposure_time * exposure_multiplier;
+            tracing::info!("Re-exposing layer {} for {}s", layer, exposure_time);
+            if let Err(e) = self.display.display_frame(frame) {
+                tracing::error!(
+                    "Unable to display layer {}, stopping re-exposure: {}",
+                    layer,
+                    e
+                );
+                break;
+            }
+
+            if let Err(e) = self.expose(exposure_time).await {
+                tracing::error!(
+                    "Unable to cure layer {}, stopping re-exposure: {}",
+                    layer,
+                    e
+                );
+                break;
+            }
+        }
+    }
+
+    fn check_reexpose(&self, start: usize, count: usize) -> Result<(), String> {
+        if !matches!(self.state.status, PrinterStatus::Printing) || self.state.paused != Some(true)
+        {
+            return Err("print must be paused".to_string());
+        }
+        if count == 0 || count > MAX_REEXPOSE_LAYERS {
+            return Err(format!(
+                "layer count must be between 1 and {}",
+                MAX_REEXPOSE_LAYERS
+            ));
+        }
+        if start + count > self._get_layer() {
+            return Err(format!(
+                "only layers below {} have been printed",
+                self._get_layer()
+            ));
+        }
+        // Curing with the plate lifted would harden a loose slab of resin in the vat
+        if self.state.physical_state.z_microns != self._get_layer_z() {
+            return Err(format!(
+                "plate must be returned to the layer position at {}um",
+                self._get_layer_z()
+            ));
+        }
+        Ok(())
+    }
+
+    // Schedule a pause once the print reaches the given layer
+    fn schedule_pause(&mut self, layer: usize) {
+        if layer <= self._get_layer() {
+            tracing::warn!(
+                "Unable to schedule pause at layer {}, print is already at layer {}",
+                layer,
+                self._get_layer()
+            );
+            return;
+        }
+        if let Err(index) = self.pause_layers.binary_search(&layer) {
+            tracing::info!("Scheduling pause at layer {}", layer);
+            self.pause_layers.insert(index, layer);
+        }
+    }
+
     fn _get_layer(&self) -> usize {
         self.state.layer.unwrap_or(0)
     }
@@ -344,15 +1269,89 @@ impl<T: HardwareControl> Printer<'_, T> {
         &mut self,
         file_data: FileMetadata,
         layer: usize,
-    ) -> Result<(), io::Error> {
-        let mut file: Box<dyn PrintFile + Send> = Box::new(Sl1::from_file(file_data.clone())?);
+    ) -> Result<(), OdysseyError> {
+        let mut file = open_print_file(file_data.clone())?;
+
+        let optional_frame = Frame::from_layer(file.get_layer_data(layer).await).await?;
+
+        let frame = optional_frame.ok_or(OdysseyError::file_error(
+            Box::new(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no layer {}", file_data.name, layer),
+            )),
+            404,
+        ))?;
+        tracing::info!("Loading layer {} from {} to display", layer, file_data.name);
+        self.display.display_frame(frame)?;
+        Ok(())
+    }
 
-        let optional_frame = Frame::from_layer(file.get_layer_data(layer).await).await;
+    // Cure the exposure test pattern onto the plate from home, then raise it so
+    // the result can be read off. Errors end the test with everything blanked
+    async fn run_exposure_test(&mut self, test: ExposureTest) {
+        tracing::info!(
+            "Starting exposure test of {} zones over {} layers: {:?}",
+            test.zones.len(),
+            test.layers,
+            test.zones
+                .iter()
+                .map(|zone| zone.exposure_seconds)
+                .collect::<Vec<_>>()
+        );
+        self.wrapped_home().await;
+
+        for layer in 0..test.layers {
+            if !matches!(self.state.status, PrinterStatus::Idle) {
+                break;
+            }
+            let layer_z = (layer as u32 + 1) * EXPOSURE_TEST_LAYER_HEIGHT;
+            if let Err(e) = self.exposure_test_layer(&test, layer_z).await {
+                tracing::error!("Stopping exposure test: {}", e);
+                break;
+            }
+        }
 
-        if let Some(frame) = optional_frame {
-            tracing::info!("Loading layer {} from {} to display", layer, file_data.name);
-            self.display.display_frame(frame);
+        if let Err(e) = self.display.display_blank() {
+            tracing::warn!("Unable to blank display: {}", e);
         }
+        if matches!(self.state.status, PrinterStatus::Idle) {
+            let max_z = (self.config.max_z * 1000.0).trunc() as u32;
+            if let Err(e) = self.wrapped_move(max_z, self.config.default_up_speed).await {
+                tracing::error!("Unable to raise plate after exposure test: {}", e);
+            }
+            tracing::info!("Exposure test complete");
+        }
+    }
+
+    // Lift and return to the layer, then cure with every zone lit, masking off
+    // one more zone from the left after each step
+    async fn exposure_test_layer(
+        &mut self,
+        test: &ExposureTest,
+        layer_z: u32,
+    ) -> Result<(), OdysseyError> {
+        let lift = (self.config.default_lift * 1000.0).trunc() as u32;
+        self.wrapped_move(layer_z + lift, self.config.default_up_speed)
+            .await?;
+        self.wrapped_move(layer_z, self.config.default_down_speed)
+            .await?;
+        sleep(Duration::from_secs_f64(
+            self.config.default_wait_before_exposure,
+        ))
+        .await;
+
+        for (step, exposure) in test.exposure_steps().enumerate() {
+            if !matches!(self.state.status, PrinterStatus::Idle) {
+                return Ok(());
+            }
+            self.display.display_exposure_test(&test.zones[step..])?;
+            self.expose(exposure).await?;
+        }
+        self.display.display_blank()?;
+        sleep(Duration::from_secs_f64(
+            self.config.default_wait_after_exposure,
+        ))
+        .await;
         Ok(())
     }
 
@@ -365,9 +1364,25 @@ impl<T: HardwareControl> Printer<'_, T> {
                     print_data: Some(print_data),
                     paused: Some(false),
                     layer: Some(0),
-                    physical_state: self.state.physical_state,
+                    physical_state: self.state.physical_state.clone(),
                     status: PrinterStatus::Printing,
+                    time_remaining_seconds: None,
+                    resume_countdown_seconds: None,
+                    print_settings: None,
+                    print_queue: Vec::new(),
+                    uv_on_seconds: self.uv_usage.on_seconds(),
+                    print_started_at: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .ok()
+                        .map(|now| now.as_secs()),
+                    serial_connected: self.state.serial_connected,
+                    frame_buffer_available: self.state.frame_buffer_available,
+                    error: None,
                 };
+                self.layer_duration_ema = None;
+                self.send_event(PrintEventType::PrintStarted);
+                self.pause_layers.clear();
+                self.print_started = Some(Instant::now());
             }
             PrinterStatus::Printing => {
                 tracing::debug!("Already in printing state!");
@@ -375,10 +1390,39 @@ impl<T: HardwareControl> Printer<'_, T> {
             PrinterStatus::Shutdown => {
                 tracing::debug!("Cannot start print, Odyssey shutdown");
             }
+            PrinterStatus::Initializing => {
+                tracing::debug!("Cannot start print, Odyssey still initializing");
+            }
+        }
+    }
+
+    // Stop timing the current cure, adding it to the UV array's usage
+    fn end_cure_timing(&mut self) {
+        if let Some(cure_started) = self.cure_started.take() {
+            self.uv_usage.add(cure_started.elapsed().as_secs_f64());
+        }
+        self.cure_deadline.send_replace(None);
+    }
+
+    fn start_cure_timing(&mut self) {
+        if self.cure_started.is_some() {
+            return;
         }
+        let cure_started = Instant::now();
+        self.cure_started = Some(cure_started);
+        self.cure_deadline.send_replace(
+            self.config
+                .max_cure_seconds
+                .map(|max_cure| cure_started + Duration::from_secs_f64(max_cure)),
+        );
     }
 
     async fn update_physical_state(&mut self, new_physical_state: PhysicalState) {
+        // Track how long the UV array has been on for the cure watchdog
+        match new_physical_state.is_curing() {
+            true => self.start_cure_timing(),
+            false => self.end_cure_timing(),
+        }
         match self.state.status {
             PrinterStatus::Printing => {
                 self.state.physical_state = new_physical_state;
@@ -386,13 +1430,19 @@ impl<T: HardwareControl> Printer<'_, T> {
             PrinterStatus::Idle => {
                 self.state.physical_state = new_physical_state;
             }
-            PrinterStatus::Shutdown => (),
+            PrinterStatus::Shutdown | PrinterStatus::Initializing => (),
         }
         self.send_status().await;
     }
 
     async fn update_paused(&mut self, new_pause: bool) {
         if matches!(self.state.status, PrinterStatus::Printing) {
+            if self.state.paused != Some(new_pause) {
+                self.send_event(match new_pause {
+                    true => PrintEventType::PrintPaused,
+                    false => PrintEventType::PrintResumed,
+                });
+            }
             self.state.paused = Some(new_pause);
         }
         self.send_status().await;
@@ -401,30 +1451,98 @@ impl<T: HardwareControl> Printer<'_, T> {
     async fn update_layer(&mut self, new_layer: usize) {
         if matches!(self.state.status, PrinterStatus::Printing) {
             self.state.layer = Some(new_layer);
+            self.send_event(PrintEventType::LayerChanged);
         }
         self.send_status().await;
     }
 
+    fn is_idempotent_operation(operation: &Operation) -> bool {
+        matches!(
+            operation,
+            Operation::PausePrint
+                | Operation::ResumePrint
+                | Operation::StopPrint { .. }
+                | Operation::Shutdown
+        )
+    }
+
+    // Whether the operation repeats an idempotent operation handled moments
+    // ago, such as from a double clicked pause button
+    fn is_duplicate_operation(&self, operation: &Operation) -> bool {
+        Self::is_idempotent_operation(operation)
+            && self
+                .last_idempotent_operation
+                .is_some_and(|(last_operation, handled)| {
+                    last_operation == mem::discriminant(operation)
+                        && handled.elapsed() < DUPLICATE_OPERATION_WINDOW
+                })
+    }
+
+    // Note when an idempotent operation finished, as repeats queued while it
+    // was being handled are only dropped once they're received
+    fn record_operation(&mut self, operation: &Operation) {
+        if Self::is_idempotent_operation(operation) {
+            self.last_idempotent_operation = Some((mem::discriminant(operation), Instant::now()));
+        }
+    }
+
     async fn printing_operation_handler(&mut self) {
+        self.check_connection().await;
+        self.wrapped_poll_status().await;
+        self.check_resume_countdown().await;
+
         /*if !self.verify_hardware().await {
             return;
         }*/
 
-        let mut op_result = self.operation_receiver.try_recv();
-
-        while let Ok(operation) = op_result {
-            match operation {
-                Operation::PausePrint => self.pause_print().await,
+        while let Some(operation) = self.next_operation() {
+            match operation.clone() {
+                _ if self.is_duplicate_operation(&operation) => {
+                    tracing::info!("Dropping duplicate operation: {:?}", operation)
+                }
+                Operation::PausePrint => {
+                    if self.resume_at.take().is_some() {
+                        tracing::info!("Resume countdown cancelled");
+                        self.send_status().await;
+                    } else {
+                        self.pause_print().await
+                    }
+                }
                 Operation::ResumePrint => self.resume_print().await,
-                Operation::StopPrint => self.set_idle().await,
+                Operation::PauseAtLayer { layer } => self.schedule_pause(layer),
+                Operation::SetWaits {
+                    settle,
+                    before,
+                    after,
+                } => self.set_waits(settle, before, after).await,
+                Operation::ReexposeLayers { start, count } => {
+                    self.reexpose_layers(start, count).await
+                }
+                Operation::StopPrint { retract } => self.stop_print(retract).await,
+                Operation::EnqueuePrint { file_data, profile } => {
+                    self.enqueue_print(file_data, profile);
+                    self.send_status().await;
+                }
+                Operation::RemoveQueuedPrint { index } => {
+                    self.remove_queued_print(index);
+                    self.send_status().await;
+                }
+                Operation::RefreshFile => {
+                    self.refresh_files();
+                    self.send_status().await;
+                }
                 Operation::QueryState => self.send_status().await,
                 Operation::Shutdown => self.shutdown().await,
                 Operation::ManualMove { z } => {
                     self.paused_move(z, self.config.default_up_speed).await
                 }
-                _ => (),
+                Operation::ReloadConfiguration { configuration } => {
+                    tracing::info!("Applying the reloaded config once the print finishes");
+                    self.pending_configuration = Some(configuration);
+                }
+                _ => tracing::warn!("Ignoring operation while printing: {:?}", operation),
             };
-            op_result = self.operation_receiver.try_recv();
+            self.record_operation(&operation);
         }
     }
 
@@ -433,7 +1551,11 @@ impl<T: HardwareControl> Printer<'_, T> {
 
         match self.hardware_controller.boot().await {
             Ok(physical_state) => {
-                self.update_idle_state(physical_state).await;
+                if self.config.home_on_boot.unwrap_or(false) {
+                    self.home_on_boot().await;
+                } else {
+                    self.update_idle_state(physical_state).await;
+                }
             }
             Err(e) => {
                 tracing::error!("Error booting printer:{}", e);
@@ -442,6 +1564,22 @@ impl<T: HardwareControl> Printer<'_, T> {
         }
     }
 
+    // A home which isn't confirmed points to a stepper or driver fault, so the
+    // printer is held in shutdown with the API still up to report it, rather
+    // than going idle and failing part way into a print
+    async fn home_on_boot(&mut self) {
+        tracing::info!("Homing on boot");
+        match self.hardware_controller.verify_home().await {
+            Ok(physical_state) => self.update_idle_state(physical_state).await,
+            Err(e) => {
+                tracing::error!("Unable to home on boot: {}", e);
+                self.set_shutdown_state();
+                self.state.error = Some(format!("Homing failed at boot: {}", e));
+                self.send_status().await;
+            }
+        }
+    }
+
     pub async fn _verify_hardware(&mut self) -> bool {
         if let Ok(false) = self.hardware_controller.is_ready().await {
             tracing::error!("Hardware controller no longer ready! Shutting down Odyssey");
@@ -453,24 +1591,36 @@ impl<T: HardwareControl> Printer<'_, T> {
 
     pub async fn shutdown(&mut self) {
         tracing::info!("Shutting down.");
-        // If hardware still running, execute shutdown commands
-        if let Ok(true) = self.hardware_controller.is_ready().await {
-            if (self.hardware_controller.shutdown().await).is_ok() {
-                tracing::info!("Shut down gcode executed successfully")
-            } else {
-                tracing::info!("Unable to execute shutdown gcode")
-            }
+        if matches!(self.state.status, PrinterStatus::Printing) {
+            self.record_print(PrintOutcome::Failed);
+            self.send_event(PrintEventType::PrintFailed);
+        }
+        // Run the shutdown sequence even if the hardware isn't reporting ready,
+        // as after the error which led here, since it switches the UV array
+        // off. Only a connection known to be down is left alone
+        if self.hardware_controller.is_connected() == Some(false) {
+            tracing::warn!("Serial connection is down, unable to execute shutdown gcode");
+        } else if let Err(e) = self.hardware_controller.shutdown().await {
+            tracing::error!("Unable to execute shutdown gcode: {}", e);
+        } else {
+            tracing::info!("Shut down gcode executed successfully");
         }
 
+        self.uv_usage.save();
         self.cancellation_token.cancel();
+        self.set_shutdown_state();
+    }
 
+    fn set_shutdown_state(&mut self) {
         self.state.status = PrinterStatus::Shutdown;
         self.state.paused = None;
         self.state.print_data = None;
         self.state.physical_state = PhysicalState {
             z: f64::MAX,
             z_microns: u32::MAX,
-            curing: false,
+            curing: BTreeMap::new(),
+            temperature: None,
+            firmware_version: None,
         }
     }
 
@@ -485,45 +1635,194 @@ impl<T: HardwareControl> Printer<'_, T> {
     */
 
     async fn send_status(&mut self) {
+        self.state.time_remaining_seconds = self.estimate_time_remaining();
+        self.state.resume_countdown_seconds = self.resume_at.map(|resume_at| {
+            resume_at
+                .saturating_duration_since(Instant::now())
+                .as_secs_f64()
+        });
+        self.state.print_queue = self
+            .print_queue
+            .iter()
+            .map(|queued| queued.file_data.clone())
+            .collect();
+        self.state.uv_on_seconds = self.uv_usage.on_seconds();
         self.status_sender
             .send(self.state.clone())
             .expect("Failed to send state update");
+        self.last_status_sent = Instant::now();
     }
 
-    pub async fn start_statemachine(&mut self) {
+    fn send_event(&self, event_type: PrintEventType) {
+        let event = PrintEvent {
+            event_type,
+            file_name: self.get_file_data().map(|file_data| file_data.name),
+            layer: self.state.layer,
+        };
+        if self.event_sender.send(event).is_err() {
+            tracing::debug!("No listeners for print event {}", event_type.name());
+        }
+    }
+
+    pub async fn start_statemachine(
+        &mut self,
+        mut emergency_receiver: mpsc::Receiver<Operation>,
+        mut watchdog_receiver: mpsc::Receiver<Operation>,
+    ) {
         self.hardware_controller.initialize().await;
+        self.send_status().await;
 
-        let mut interv = interval(Duration::from_millis(1000));
+        let mut interv = interval(self.config.idle_tick());
 
         loop {
             if self.cancellation_token.is_cancelled() {
                 log::info!("Shutting down statemachine");
                 break;
             }
-            match self.state.status {
-                PrinterStatus::Idle => self.idle_event_loop().await,
-                PrinterStatus::Printing => self
-                    .print_event_loop()
-                    .await
-                    .expect("Unexpected error during print"),
-                PrinterStatus::Shutdown => self.shutdown_event_loop().await,
+
+            // Emergency operations interrupt the current event loop, even in
+            // the middle of a layer, rather than waiting behind queued operations.
+            // The cure watchdog does the same, so a hung move or serial await
+            // can't leave the UV array on
+            let emergency_operation = tokio::select! {
+                biased;
+                Some(operation) = emergency_receiver.recv() => Some(operation),
+                Some(operation) = watchdog_receiver.recv() => Some(operation),
+                _ = self.run_event_loop() => None,
+            };
+            if let Some(operation) = emergency_operation {
+                self.emergency_operation_handler(operation).await;
             }
 
+            // Take up a changed tick from a reloaded config
+            if interv.period() != self.config.idle_tick() {
+                interv = interval(self.config.idle_tick());
+            }
             interv.tick().await;
         }
     }
 
+    async fn run_event_loop(&mut self) {
+        self.check_connection().await;
+
+        match self.state.status {
+            PrinterStatus::Idle => self.idle_event_loop().await,
+            PrinterStatus::Printing => self
+                .print_event_loop()
+                .await
+                .expect("Unexpected error during print"),
+            PrinterStatus::Shutdown | PrinterStatus::Initializing => {
+                self.shutdown_event_loop().await
+            }
+        }
+    }
+
+    // Report changes to the hardware connection. An idle printer is held in
+    // shutdown while it's lost, so it boots again once the connection returns,
+    // and a print is paused to be resumed from the current layer. The
+    // controller is trusted to have kept the plate's position, so resuming
+    // doesn't home with a part on the plate
+    async fn check_connection(&mut self) {
+        let connected = self.hardware_controller.is_connected();
+        if connected == self.state.serial_connected {
+            return;
+        }
+        self.state.serial_connected = connected;
+
+        match connected {
+            Some(false) => {
+                tracing::warn!("Lost connection to the printer's controller");
+                match self.state.status {
+                    PrinterStatus::Idle => self.set_shutdown_state(),
+                    PrinterStatus::Printing if self.state.paused == Some(false) => {
+                        tracing::warn!("Pausing print until the connection returns");
+                        self.pause_print().await;
+                    }
+                    _ => {}
+                }
+            }
+            Some(true) => tracing::info!("Connected to the printer's controller"),
+            None => {}
+        }
+        self.send_status().await;
+    }
+
+    async fn emergency_operation_handler(&mut self, operation: Operation) {
+        match operation {
+            Operation::EmergencyStop => self.emergency_stop().await,
+            Operation::CureTimeout => self.trip_cure_watchdog().await,
+            _ => tracing::warn!("Ignoring non-emergency operation: {:?}", operation),
+        }
+    }
+
+    // Take the next operation to handle, always preferring the control lane so
+    // stopping or pausing never waits behind queued moves
+    fn next_operation(&mut self) -> Option<Operation> {
+        self.control_receiver
+            .try_recv()
+            .or_else(|_| self.operation_receiver.try_recv())
+            .ok()
+    }
+
+    // Halt all motion and curing without waiting on the hardware, then hold
+    // the printer in shutdown. Queued operations and prints are dropped, so
+    // nothing moves the plate again until Odyssey is restarted
+    async fn emergency_stop(&mut self) {
+        tracing::error!("Emergency stop triggered");
+        if matches!(self.state.status, PrinterStatus::Printing) {
+            self.record_print(PrintOutcome::Failed);
+            self.send_event(PrintEventType::PrintFailed);
+        }
+
+        if let Err(e) = self.hardware_controller.emergency_stop().await {
+            tracing::error!("Unable to send emergency stop commands: {}", e);
+        }
+        self.end_cure_timing();
+
+        while let Some(operation) = self.next_operation() {
+            tracing::info!("Dropping operation after emergency stop: {:?}", operation);
+        }
+        self.print_queue.clear();
+        self.queue_resume_at = None;
+        self.resume_at = None;
+
+        self.uv_usage.save();
+        self.emergency_stopped = true;
+        self.set_shutdown_state();
+        self.send_status().await;
+    }
+
     async fn shutdown_event_loop(&mut self) {
         let mut shutdown_interv = interval(Duration::from_millis(10000));
 
         self.shutdown_operation_handler().await;
 
-        if let PrinterStatus::Shutdown = self.state.status {
+        // Nothing sent to the controller would be answered until the connection
+        // returns, so there's no checking whether it's ready to boot. Nor is a
+        // failed boot home retried, since it would drive a faulty axis again
+        if self.emergency_stopped
+            || self.state.error.is_some()
+            || self.state.serial_connected == Some(false)
+        {
+            shutdown_interv.tick().await;
+            return;
+        }
+
+        if matches!(
+            self.state.status,
+            PrinterStatus::Shutdown | PrinterStatus::Initializing
+        ) {
             match self.hardware_controller.is_ready().await {
                 Ok(true) => {
                     self.boot().await;
                 }
                 _ => {
+                    // Only now is it known the printer is really shut down
+                    if matches!(self.state.status, PrinterStatus::Initializing) {
+                        tracing::info!("Hardware not ready, waiting for it in shutdown");
+                        self.state.status = PrinterStatus::Shutdown;
+                        self.send_status().await;
+                    }
                     shutdown_interv.tick().await;
                 }
             }
@@ -532,25 +1831,91 @@ impl<T: HardwareControl> Printer<'_, T> {
 
     // While in shutdown state, process operations to drop them from queue
     async fn shutdown_operation_handler(&mut self) {
-        let mut op_result = self.operation_receiver.try_recv();
+        while let Some(operation) = self.next_operation() {
+            match operation {
+                Operation::QueryState => self.send_status().await,
+                Operation::ReloadConfiguration { configuration } => {
+                    self.reload_configuration(*configuration)
+                }
+                _ => tracing::warn!("Ignoring operation while shutdown: {:?}", operation),
+            }
+        }
+    }
+
+    // Take up a reloaded config file. Settings tied to the serial connection
+    // or files opened at startup keep their current values until a restart
+    fn reload_configuration(&mut self, configuration: Configuration) {
+        let mut printer_config = configuration.printer;
+        if printer_config.serial != self.config.serial
+            || printer_config.baudrate != self.config.baudrate
+            || printer_config.gcode_log_size != self.config.gcode_log_size
+            || printer_config.uv_usage_file != self.config.uv_usage_file
+            || printer_config.print_history_file != self.config.print_history_file
+            || printer_config.backlash_microns != self.config.backlash_microns
+        {
+            tracing::warn!(
+                "Changes to serial, baudrate, gcode_log_size, uv_usage_file, print_history_file and backlash_microns take effect after a restart"
+            );
+            printer_config.serial = self.config.serial.clone();
+            printer_config.baudrate = self.config.baudrate;
+            printer_config.gcode_log_size = self.config.gcode_log_size;
+            printer_config.uv_usage_file = self.config.uv_usage_file.clone();
+            printer_config.print_history_file = self.config.print_history_file.clone();
+            printer_config.backlash_microns = self.config.backlash_microns;
+        }
 
-        while let Ok(operation) = op_result {
-            if let Operation::QueryState = operation {
-                self.send_status().await
+        if let Err(e) = self.hardware_controller.reload_config(&configuration.gcode) {
+            tracing::warn!("Keeping the current gcode config: {}", e);
+        }
+
+        self.hardware_controller
+            .add_print_variable("max_z".to_string(), printer_config.max_z.to_string());
+        self.hardware_controller.add_print_variable(
+            "z_lift".to_string(),
+            printer_config.default_lift.to_string(),
+        );
+        self.uv_usage
+            .set_lifespan_hours(printer_config.uv_lifespan_hours);
+        self.display.reload_config(&configuration.display);
+        self.config = printer_config;
+
+        tracing::info!("Reloaded configuration");
+    }
+
+    // Cancel the print, always switching the UV array off first. The plate is
+    // then raised out of the resin if asked, or if retract_on_cancel is set
+    async fn stop_print(&mut self, retract: Option<bool>) {
+        self.wrapped_stop_cure().await;
+        if !matches!(self.state.status, PrinterStatus::Printing) {
+            return;
+        }
+        self.record_print(PrintOutcome::Cancelled);
+        self.send_event(PrintEventType::PrintCancelled);
+
+        if retract.or(self.config.retract_on_cancel).unwrap_or(false) {
+            tracing::info!("Raising plate out of the resin");
+            let max_z = (self.config.max_z * 1000.0).trunc() as u32;
+            if let Err(e) = self.wrapped_move(max_z, self.config.default_up_speed).await {
+                tracing::error!("Unable to raise plate after cancelling: {}", e);
+            }
+            if !matches!(self.state.status, PrinterStatus::Printing) {
+                return;
             }
-            op_result = self.operation_receiver.try_recv();
         }
+        self.set_idle().await;
     }
 
     async fn set_idle(&mut self) {
         self.state.status = PrinterStatus::Idle;
         self.state.layer = None;
         self.state.paused = None;
+        self.state.print_settings = None;
         self.send_status().await;
     }
 
     async fn update_idle_state(&mut self, physical_state: PhysicalState) {
         self.state.status = PrinterStatus::Idle;
+        self.state.print_settings = None;
         self.state.physical_state = physical_state;
         self.send_status().await;
     }
@@ -560,13 +1925,26 @@ impl<T: HardwareControl> Printer<'_, T> {
             return;
         }*/
 
-        let mut op_result = self.operation_receiver.try_recv();
-
-        while let Ok(operation) = op_result {
-            match operation {
+        while let Some(operation) = self.next_operation() {
+            match operation.clone() {
+                _ if self.is_duplicate_operation(&operation) => {
+                    tracing::info!("Dropping duplicate operation: {:?}", operation)
+                }
                 Operation::QueryState => self.send_status().await,
-                Operation::StartPrint { file_data } => {
-                    self.start_print(file_data).await.unwrap_or(())
+                Operation::StartPrint { file_data, profile } => {
+                    self.start_print(file_data, profile).await.unwrap_or(())
+                }
+                Operation::EnqueuePrint { file_data, profile } => {
+                    self.enqueue_print(file_data, profile);
+                    self.send_status().await;
+                }
+                Operation::RemoveQueuedPrint { index } => {
+                    self.remove_queued_print(index);
+                    self.send_status().await;
+                }
+                Operation::RefreshFile => {
+                    self.refresh_files();
+                    self.send_status().await;
                 }
                 Operation::ManualCommand { command } => self.wrapped_command(command).await,
                 Operation::ManualHome => self.wrapped_home().await,
@@ -574,41 +1952,57 @@ impl<T: HardwareControl> Printer<'_, T> {
                     self.wrapped_manual_move(z, self.config.default_up_speed)
                         .await
                 }
-                Operation::ManualCure { cure } => {
-                    if cure {
-                        self.wrapped_start_cure().await;
-                    } else {
-                        self.wrapped_stop_cure().await;
-                    }
-                }
+                Operation::ManualCure { cure, zone } => self.wrapped_manual_cure(cure, zone).await,
                 Operation::ManualDisplayTest { test } => {
-                    self.display.display_test(test);
+                    if let Err(e) = self.display.display_test(test) {
+                        tracing::warn!("Unable to display test pattern: {}", e);
+                    }
                 }
                 Operation::ManualDisplayLayer { file_data, layer } => {
-                    self.display_file_layer(file_data, layer)
-                        .await
-                        .unwrap_or(());
+                    if let Err(e) = self.display_file_layer(file_data, layer).await {
+                        tracing::warn!("Unable to display layer {}: {}", layer, e);
+                    }
                 }
+                Operation::ExposureTest { test } => self.run_exposure_test(test).await,
                 Operation::Shutdown => self.shutdown().await,
-                _ => (),
+                Operation::ReloadConfiguration { configuration } => {
+                    self.reload_configuration(*configuration)
+                }
+                _ => tracing::warn!("Ignoring operation while idle: {:?}", operation),
             };
-            op_result = self.operation_receiver.try_recv();
+            self.record_operation(&operation);
         }
     }
 
     async fn idle_event_loop(&mut self) {
+        if let Some(configuration) = self.pending_configuration.take() {
+            self.reload_configuration(*configuration);
+        }
+        self.wrapped_poll_status().await;
         self.idle_operation_handler().await;
+        self.start_queued_print().await;
+        self.send_heartbeat().await;
+    }
+
+    // Resend the status if nothing else has sent it recently, so idle clients
+    // can tell the printer is still running
+    async fn send_heartbeat(&mut self) {
+        let Some(heartbeat) = self.config.status_heartbeat_seconds else {
+            return;
+        };
+        if matches!(self.state.status, PrinterStatus::Idle)
+            && self.last_status_sent.elapsed() >= Duration::from_secs_f64(heartbeat)
+        {
+            self.send_status().await;
+        }
     }
 }
 
 impl Frame {
-    async fn from_layer(layer: Option<Layer>) -> Option<Frame> {
-        if layer.is_some() {
-            let layer = layer.unwrap();
-            let frame = Frame::from_vec(layer.file_name, layer.exposure_time, layer.data);
-            return Some(frame);
-        }
-        None
+    async fn from_layer(layer: Option<Layer>) -> Result<Option<Frame>, OdysseyError> {
+        layer
+            .map(|layer| Frame::from_vec(layer.file_name, layer.exposure_time, layer.data))
+            .transpose()
     }
 }
 
@@ -616,15 +2010,40 @@ impl Frame {
 pub enum Operation {
     StartPrint {
         file_data: FileMetadata,
+        profile: Option<ResinProfile>,
     },
-    StopPrint,
+    StopPrint {
+        /// Overrides retract_on_cancel for this cancellation
+        retract: Option<bool>,
+    },
+    EnqueuePrint {
+        file_data: FileMetadata,
+        profile: Option<ResinProfile>,
+    },
+    RemoveQueuedPrint {
+        index: Option<usize>,
+    },
+    RefreshFile,
     PausePrint,
     ResumePrint,
+    PauseAtLayer {
+        layer: usize,
+    },
+    SetWaits {
+        settle: Option<f64>,
+        before: Option<f64>,
+        after: Option<f64>,
+    },
+    ReexposeLayers {
+        start: usize,
+        count: usize,
+    },
     ManualMove {
         z: u32,
     },
     ManualCure {
         cure: bool,
+        zone: Option<String>,
     },
     ManualHome,
     ManualCommand {
@@ -637,8 +2056,79 @@ pub enum Operation {
     ManualDisplayTest {
         test: DisplayTest,
     },
+    ExposureTest {
+        test: ExposureTest,
+    },
     QueryState,
     Shutdown,
+    /// Take up a reloaded config file, once any print in progress finishes
+    ReloadConfiguration {
+        configuration: Box<Configuration>,
+    },
+    /// Only handled when sent through a PrioritySender
+    EmergencyStop,
+    /// Sent by the cure watchdog task when curing outlasts max_cure_seconds
+    CureTimeout,
+}
+
+impl Operation {
+    /// Whether the operation is safety critical, and should be sent through a
+    /// PrioritySender to jump ahead of any queued operations
+    pub fn is_priority(&self) -> bool {
+        matches!(
+            self,
+            Operation::StopPrint { .. }
+                | Operation::PausePrint
+                | Operation::Shutdown
+                | Operation::EmergencyStop
+        )
+    }
+}
+
+enum WatchdogEvent {
+    DeadlineChanged,
+    Expired,
+    Stopped,
+}
+
+// Runs apart from the printer's task, so the UV array is forced off even while
+// that task is stuck in a hung move, serial await or sleep
+async fn run_cure_watchdog(
+    mut deadline: watch::Receiver<Option<Instant>>,
+    timeout_sender: mpsc::Sender<Operation>,
+    cancellation_token: CancellationToken,
+) {
+    loop {
+        let current = *deadline.borrow_and_update();
+        let expired = async {
+            match current {
+                Some(at) => sleep_until(at).await,
+                None => std::future::pending().await,
+            }
+        };
+        let event = tokio::select! {
+            _ = cancellation_token.cancelled() => WatchdogEvent::Stopped,
+            changed = deadline.changed() => match changed {
+                Ok(()) => WatchdogEvent::DeadlineChanged,
+                Err(_) => WatchdogEvent::Stopped,
+            },
+            _ = expired => WatchdogEvent::Expired,
+        };
+
+        match event {
+            WatchdogEvent::DeadlineChanged => {}
+            WatchdogEvent::Stopped => break,
+            WatchdogEvent::Expired => {
+                if timeout_sender.send(Operation::CureTimeout).await.is_err() {
+                    break;
+                }
+                // Only fire once for each cure, waiting for the printer to end it
+                if deadline.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
 }
 
 #[async_trait]
@@ -656,11 +2146,32 @@ pub trait HardwareControl {
         manual: bool,
     ) -> Result<PhysicalState, OdysseyError>;
     async fn start_layer(&mut self, layer: usize) -> Result<PhysicalState, OdysseyError>;
-    async fn start_curing(&mut self) -> Result<PhysicalState, OdysseyError>;
-    async fn stop_curing(&mut self) -> Result<PhysicalState, OdysseyError>;
+    /// Start curing with the given zones, or every zone if none are given
+    async fn start_curing(
+        &mut self,
+        zones: Option<Vec<String>>,
+    ) -> Result<PhysicalState, OdysseyError>;
+    /// Stop curing with the given zones, or every zone if none are given
+    async fn stop_curing(
+        &mut self,
+        zones: Option<Vec<String>>,
+    ) -> Result<PhysicalState, OdysseyError>;
     async fn boot(&mut self) -> Result<PhysicalState, OdysseyError>;
+    /// Home, failing unless the hardware confirms the home completed
+    async fn verify_home(&mut self) -> Result<PhysicalState, OdysseyError>;
+    /// Refresh any periodically reported values, such as temperature
+    async fn poll_status(&mut self) -> Result<PhysicalState, OdysseyError>;
     async fn shutdown(&mut self) -> Result<(), OdysseyError>;
+    /// Immediately halt motion and stop curing in every zone, without awaiting
+    /// any acknowledgment from the hardware
+    async fn emergency_stop(&mut self) -> Result<PhysicalState, OdysseyError>;
     fn get_physical_state(&self) -> Result<PhysicalState, OdysseyError>;
+    /// Whether the connection to the hardware is up, or None if there's no
+    /// connection to lose
+    fn is_connected(&self) -> Option<bool>;
+    /// Take up reloaded gcode settings, refusing any change which needs a
+    /// restart
+    fn reload_config(&mut self, config: &GcodeConfig) -> Result<(), OdysseyError>;
     fn add_print_variable(&mut self, variable: String, value: String);
     fn remove_print_variable(&mut self, variable: String);
     fn clear_variables(&mut self);
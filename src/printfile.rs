@@ -1,20 +1,91 @@
 use std::{
+    ffi::OsStr,
     fs::File,
     io::{self, Error},
+    path::Path,
 };
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use xattr::FileExt;
 
-use crate::api_objects::{
-    FileData, FileMetadata, PrintMetadata, PrintUserMetadata, ThumbnailSize,
-    UpdatePrintUserMetadata,
+use crate::{
+    api_objects::{
+        FileData, FileMetadata, PrintMetadata, PrintSettings, PrintUserMetadata,
+        UpdatePrintUserMetadata,
+    },
+    configuration::{PrinterConfig, ResinProfile},
+    cws::Cws,
+    sl1::Sl1,
 };
 
 static XATTR_PRINT_COUNT: &str = "user.odyssey.print_count";
 static XATTR_PRINT_RATING: &str = "user.odyssey.print_rating";
 static XATTR_PRINT_FAVORITE: &str = "user.odyssey.favorite";
+static XATTR_PRINT_TAGS: &str = "user.odyssey.tags";
+
+/// Limits on a file's tags, keeping their JSON encoding within the 4KiB
+/// xattr value size of ext4 and most other filesystems
+pub const MAX_TAGS: usize = 32;
+pub const MAX_TAG_LENGTH: usize = 64;
+
+/// Trim and deduplicate tags, dropping empty ones, and check they fit within
+/// the limits
+pub fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>, Error> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if tag.chars().count() > MAX_TAG_LENGTH {
+            return Err(Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Tags may be at most {} characters long", MAX_TAG_LENGTH),
+            ));
+        }
+        if !tag.is_empty() && !normalized.iter().any(|existing| existing == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+
+    if normalized.len() > MAX_TAGS {
+        return Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Files may have at most {} tags", MAX_TAGS),
+        ));
+    }
+    Ok(normalized)
+}
+
+/// File extensions of the supported print file formats
+pub static SUPPORTED_EXTENSIONS: [&str; 2] = ["sl1", "cws"];
+
+/// Whether the given path has the extension of a supported print file format
+pub fn is_print_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|extension| {
+            SUPPORTED_EXTENSIONS
+                .iter()
+                .any(|supported| supported.eq_ignore_ascii_case(extension))
+        })
+}
+
+/// Open the given file with the PrintFile implementation matching its extension
+pub fn open_print_file(file_data: FileMetadata) -> Result<Box<dyn PrintFile + Send>, io::Error> {
+    let extension = file_data
+        .get_full_path()
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_lowercase);
+
+    match extension.as_deref() {
+        Some("sl1") => Ok(Box::new(Sl1::from_file(file_data)?)),
+        Some("cws") => Ok(Box::new(Cws::from_file(file_data)?)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unsupported print file type: {}", file_data.name),
+        )),
+    }
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Layer {
@@ -30,9 +101,12 @@ pub trait PrintFile {
         Self: Sized;
     async fn get_layer_data(&mut self, index: usize) -> Option<Layer>;
     fn get_layer_count(&self) -> usize;
+    /// Exposure time in seconds of the layer at the given index
+    fn get_exposure_time(&self, index: usize) -> f64;
     fn get_layer_height(&self) -> u32;
     fn get_metadata(&self) -> PrintMetadata;
-    fn get_thumbnail(&mut self, size: ThumbnailSize) -> Result<FileData, Error>;
+    /// Get a thumbnail scaled to fit within the given dimensions
+    fn get_thumbnail(&mut self, width: u32, height: u32) -> Result<FileData, Error>;
     // Optional fields not present in every file type
     fn get_lift(&self) -> Option<u32> {
         None
@@ -43,12 +117,95 @@ pub trait PrintFile {
     fn get_down_speed(&self) -> Option<f64> {
         None
     }
+    fn get_settle_time(&self) -> Option<f64> {
+        None
+    }
     fn get_wait_after_exposure(&self) -> Option<f64> {
         None
     }
     fn get_wait_before_exposure(&self) -> Option<f64> {
         None
     }
+    /// Whether the plate should lift before the first layer, for files whose
+    /// slicer disables it to improve plate adhesion
+    fn get_first_layer_lift(&self) -> Option<bool> {
+        None
+    }
+    /// Movement and timing values for printing this file, taken from the
+    /// resin profile, then the file, then configured defaults
+    fn print_settings(
+        &self,
+        profile: Option<&ResinProfile>,
+        config: &PrinterConfig,
+    ) -> PrintSettings {
+        let profile = profile.cloned().unwrap_or_default();
+        PrintSettings {
+            layer_height: self.get_layer_height(),
+            lift: profile
+                .lift
+                .map(|lift| (lift * 1000.0).trunc() as u32)
+                .or(self.get_lift())
+                .unwrap_or((config.default_lift * 1000.0).trunc() as u32),
+            up_speed: profile
+                .up_speed
+                .or(self.get_up_speed())
+                .unwrap_or(config.default_up_speed),
+            down_speed: profile
+                .down_speed
+                .or(self.get_down_speed())
+                .unwrap_or(config.default_down_speed),
+            settle_time: profile
+                .settle_time
+                .or(self.get_settle_time())
+                .or(config.default_settle_time)
+                .unwrap_or(0.0),
+            wait_before_exposure: profile
+                .wait_before_exposure
+                .or(self.get_wait_before_exposure())
+                .unwrap_or(config.default_wait_before_exposure)
+                * config.wait_before_exposure_multiplier.unwrap_or(1.0),
+            wait_after_exposure: profile
+                .wait_after_exposure
+                .or(self.get_wait_after_exposure())
+                .unwrap_or(config.default_wait_after_exposure)
+                * config.wait_after_exposure_multiplier.unwrap_or(1.0),
+            exposure_multiplier: profile.exposure_multiplier.unwrap_or(1.0)
+                * config.exposure_multiplier.unwrap_or(1.0),
+            first_layer_lift: self.get_first_layer_lift().unwrap_or(true),
+        }
+    }
+    /// Print time in seconds when printed with the given settings, summing
+    /// each layer's travel, waits and exposure. Unlike the slicer's
+    /// print_time, this follows Odyssey's own movement and wait settings
+    fn recompute_print_time(&self, settings: &PrintSettings, config: &PrinterConfig) -> f64 {
+        let layer_count = self.get_layer_count();
+        let layer_height = settings.layer_height as f64 / 1000.0;
+        let lift = settings.lift as f64 / 1000.0;
+
+        (0..layer_count)
+            .map(|layer| {
+                // Lift from the last layer then lower onto the next, or move
+                // straight down when the first layer lift is disabled
+                let travel = if layer > 0 || settings.first_layer_lift {
+                    (lift + layer_height) / settings.up_speed + lift / settings.down_speed
+                } else {
+                    layer_height / settings.down_speed
+                };
+                let exposure = self.get_exposure_time(layer)
+                    * settings.exposure_multiplier
+                    * config
+                        .top_fade
+                        .as_ref()
+                        .map_or(1.0, |top_fade| top_fade.exposure_factor(layer, layer_count));
+
+                travel
+                    + settings.settle_time
+                    + settings.wait_before_exposure
+                    + exposure
+                    + settings.wait_after_exposure
+            })
+            .sum()
+    }
     fn _get_xattr(file: &File, xattr_name: &str) -> Option<Vec<u8>>
     where
         Self: Sized,
@@ -71,6 +228,7 @@ pub trait PrintFile {
             print_count: Self::get_print_count(file),
             favorite: Self::get_favorite(file),
             rating: Self::get_rating(file),
+            tags: Self::get_tags(file),
         }
     }
     fn get_print_count(file: &File) -> u32
@@ -100,16 +258,34 @@ pub trait PrintFile {
             .filter(|val| *val != 0)
             .is_some()
     }
+    fn get_tags(file: &File) -> Vec<String>
+    where
+        Self: Sized,
+    {
+        Self::_get_xattr(file, XATTR_PRINT_TAGS)
+            .and_then(|v| serde_json::from_slice(&v).ok())
+            .unwrap_or_default()
+    }
     fn _set_xattr(file: &File, xattr_name: &str, value: &[u8]) -> Result<(), Error>
     where
         Self: Sized,
     {
+        if !xattr::SUPPORTED_PLATFORM {
+            return Err(Error::new(
+                io::ErrorKind::Unsupported,
+                "extended attributes aren't supported on this platform",
+            ));
+        }
         file.set_xattr(xattr_name, value)
     }
     fn set_user_metadata(file: &File, user_metadata: UpdatePrintUserMetadata) -> Result<(), Error>
     where
         Self: Sized,
     {
+        // Checked up front, so invalid tags don't leave the other fields half
+        // updated
+        let tags = user_metadata.tags.map(normalize_tags).transpose()?;
+
         let mut result = Ok(());
         if let Some(print_count) = user_metadata.print_count {
             result = result.and(Self::set_print_count(file, print_count));
@@ -120,6 +296,9 @@ pub trait PrintFile {
         if let Some(rating) = user_metadata.rating {
             result = result.and(Self::set_rating(file, rating));
         }
+        if let Some(tags) = tags {
+            result = result.and(Self::set_tags(file, tags));
+        }
         result
     }
     fn set_print_count(file: &File, val: u32) -> Result<(), Error>
@@ -141,4 +320,11 @@ pub trait PrintFile {
         let val: u8 = if val { 1 } else { 0 };
         Self::_set_xattr(file, XATTR_PRINT_FAVORITE, &val.to_be_bytes())
     }
+    fn set_tags(file: &File, val: Vec<String>) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        let val = serde_json::to_vec(&normalize_tags(val)?).map_err(Error::other)?;
+        Self::_set_xattr(file, XATTR_PRINT_TAGS, &val)
+    }
 }
@@ -1,19 +1,102 @@
 use async_trait::async_trait;
-use serialport::TTYPort;
+use regex::Regex;
+use serialport::{ClearBuffer, SerialPort, TTYPort};
+use std::collections::VecDeque;
 use std::io::{self, BufRead, BufReader, Write};
+use std::mem::take;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::broadcast::error::TryRecvError;
 use tokio::sync::broadcast::{self, Receiver, Sender};
-use tokio::time::{interval, timeout, Duration};
+use tokio::sync::mpsc;
+use tokio::task::spawn_blocking;
+use tokio::time::{interval, sleep, timeout, Duration};
 use tokio_util::sync::CancellationToken;
 
+use crate::api_objects::{GcodeDirection, GcodeLogEntry};
+use crate::configuration::PrinterConfig;
 use crate::error::OdysseyError;
 
+/// Wait before the first attempt to reopen a lost serial port, doubling after
+/// each failed attempt up to RECONNECT_MAX_BACKOFF
+const RECONNECT_MIN_BACKOFF: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Numbered lines kept after being sent, in case the firmware asks for them to
+/// be resent
+const RESEND_HISTORY: usize = 64;
+
+/// How long a blocking read waits for data before checking for cancellation.
+/// Lines are still passed on as soon as they're received
+const SERIAL_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Bounded history of the lines sent to and received from the serial
+/// connection, shared with the API for diagnosing misbehaving prints. The
+/// oldest lines are dropped once it's full, so it never grows during a print
+#[derive(Clone, Debug, Default)]
+pub struct GcodeLog {
+    entries: Arc<RwLock<VecDeque<GcodeLogEntry>>>,
+    capacity: usize,
+}
+
+impl GcodeLog {
+    pub fn new(capacity: usize) -> GcodeLog {
+        GcodeLog {
+            entries: Arc::new(RwLock::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Every logged line, oldest first
+    pub fn entries(&self) -> Vec<GcodeLogEntry> {
+        self.entries
+            .read()
+            .map(|entries| entries.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Record each non-empty line of a message
+    pub fn record(&self, direction: GcodeDirection, message: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_millis() as f64 / 1000.0)
+            .unwrap_or_default();
+
+        if let Ok(mut entries) = self.entries.write() {
+            for line in message
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+            {
+                if entries.len() >= self.capacity {
+                    entries.pop_front();
+                }
+                entries.push_back(GcodeLogEntry {
+                    timestamp,
+                    direction: direction.clone(),
+                    line: line.to_string(),
+                });
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct InternalCommsHandler {
     outgoing_sender: Sender<String>,
     outgoing_receiver: Receiver<String>,
     incoming_sender: Sender<String>,
     incoming_receiver: Receiver<String>,
+    /// Whether the serial port behind the handler is connected, shared by
+    /// every clone
+    connected: Arc<AtomicBool>,
+    /// Number of times the connection has been re-established, shared by
+    /// every clone
+    reconnections: Arc<AtomicU32>,
 }
 
 impl Clone for InternalCommsHandler {
@@ -23,6 +106,8 @@ impl Clone for InternalCommsHandler {
             outgoing_receiver: self.outgoing_receiver.resubscribe(),
             incoming_sender: self.incoming_sender.clone(),
             incoming_receiver: self.incoming_receiver.resubscribe(),
+            connected: self.connected.clone(),
+            reconnections: self.reconnections.clone(),
         }
     }
 }
@@ -42,6 +127,8 @@ impl InternalCommsHandler {
             outgoing_receiver,
             incoming_sender,
             incoming_receiver,
+            connected: Arc::new(AtomicBool::new(true)),
+            reconnections: Arc::new(AtomicU32::new(0)),
         }
     }
     pub fn invert(&self) -> Self {
@@ -50,9 +137,28 @@ impl InternalCommsHandler {
             outgoing_receiver: self.incoming_receiver.resubscribe(),
             incoming_sender: self.outgoing_sender.clone(),
             incoming_receiver: self.outgoing_receiver.resubscribe(),
+            connected: self.connected.clone(),
+            reconnections: self.reconnections.clone(),
         }
     }
 
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        let was_connected = self.connected.swap(connected, Ordering::Relaxed);
+        if connected && !was_connected {
+            self.reconnections.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Number of times the connection has been re-established, for noticing a
+    /// reconnect which happened between sends
+    pub fn reconnections(&self) -> u32 {
+        self.reconnections.load(Ordering::Relaxed)
+    }
+
     async fn flush_input(&mut self) -> Result<(), OdysseyError> {
         while !self.incoming_receiver.is_empty() {
             let _ = self.incoming_receiver.recv().await?;
@@ -112,6 +218,51 @@ impl InternalCommsHandler {
         }
     }
 
+    /// Wait for each line of a sent message to be echoed back, ignoring any
+    /// unrelated output received in between
+    pub async fn await_echo(
+        &mut self,
+        message: &str,
+        timeout_duration: Duration,
+    ) -> Result<(), OdysseyError> {
+        for line in message
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            match timeout(timeout_duration, self._await_response(&line.to_string())).await {
+                Ok(res) => res?,
+                Err(elapsed) => {
+                    tracing::warn!("No echo received over serialport for: {}", line);
+                    return Err(OdysseyError::hardware_error(Box::new(elapsed), 0));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for a received line matching the given pattern, returning the
+    /// first capture group if there is one, or otherwise the whole match
+    pub async fn await_capture(
+        &mut self,
+        pattern: &Regex,
+        timeout_duration: Duration,
+    ) -> Result<Option<String>, OdysseyError> {
+        let capture = async {
+            loop {
+                let message = self.receive().await?;
+                if let Some(caps) = pattern.captures(&message) {
+                    let capture = caps.get(1).or(caps.get(0));
+                    return Ok(capture.map(|capture| capture.as_str().to_string()));
+                }
+            }
+        };
+        match timeout(timeout_duration, capture).await {
+            Ok(res) => res,
+            Err(_) => Ok(None),
+        }
+    }
+
     pub async fn send_and_check(
         &mut self,
         message: String,
@@ -132,6 +283,27 @@ impl InternalCommsHandler {
         self.send(message).await?;
         self.await_response(expected, timeout_duration).await
     }
+
+    pub async fn send_and_verify(
+        &mut self,
+        message: String,
+        timeout_duration: Duration,
+    ) -> Result<(), OdysseyError> {
+        self.flush_input().await?;
+        self.send(message.clone()).await?;
+        self.await_echo(&message, timeout_duration).await
+    }
+
+    pub async fn send_and_capture(
+        &mut self,
+        message: String,
+        pattern: &Regex,
+        timeout_duration: Duration,
+    ) -> Result<Option<String>, OdysseyError> {
+        self.flush_input().await?;
+        self.send(message).await?;
+        self.await_capture(pattern, timeout_duration).await
+    }
 }
 
 #[async_trait]
@@ -141,22 +313,52 @@ pub trait SerialHandler {
         cancellation_token: CancellationToken,
     ) -> Result<(), OdysseyError>;
     fn get_internal_comms(&self) -> InternalCommsHandler;
+    /// History of the lines passing over the connection. Handlers which keep
+    /// none return an empty log
+    fn get_gcode_log(&self) -> GcodeLog {
+        GcodeLog::default()
+    }
+}
+
+/// Open the printer's serial port, clearing anything left in its buffers
+pub fn open_serial_port(serial: &str, baudrate: u32) -> Result<TTYPort, io::Error> {
+    let mut serial_port = tokio_serial::new(serial, baudrate)
+        .timeout(SERIAL_READ_TIMEOUT)
+        .open_native()?;
+    serial_port.set_exclusive(false)?;
+    serial_port.clear(ClearBuffer::All)?;
+    Ok(serial_port)
 }
 
 pub struct TTYPortHandler {
     serial_port: TTYPort,
+    serial: String,
+    baudrate: u32,
     internal_comms: InternalCommsHandler,
+    gcode_log: GcodeLog,
+    /// Recently sent numbered lines, oldest first
+    sent_lines: VecDeque<(u32, String)>,
 }
 
 impl TTYPortHandler {
-    pub fn new(serial_port: TTYPort) -> TTYPortHandler {
-        TTYPortHandler {
-            serial_port,
+    /// Open the configured serial port, which is reopened with the same
+    /// settings if the connection is lost
+    pub fn open(config: &PrinterConfig) -> Result<TTYPortHandler, io::Error> {
+        Ok(TTYPortHandler {
+            serial_port: open_serial_port(&config.serial, config.baudrate)?,
+            serial: config.serial.clone(),
+            baudrate: config.baudrate,
             internal_comms: InternalCommsHandler::new(),
-        }
+            gcode_log: GcodeLog::new(config.gcode_log_size.unwrap_or(500)),
+            sent_lines: VecDeque::with_capacity(RESEND_HISTORY),
+        })
     }
 
-    async fn _send_serial(&mut self, message: &String) -> Result<usize, OdysseyError> {
+    fn reader(&self) -> Result<BufReader<TTYPort>, io::Error> {
+        Ok(BufReader::new(self.serial_port.try_clone_native()?))
+    }
+
+    fn _send_serial(&mut self, message: &String) -> Result<usize, io::Error> {
         loop {
             match self.serial_port.write(message.as_bytes()) {
                 Ok(n) => {
@@ -165,12 +367,101 @@ impl TTYPortHandler {
                     self.serial_port.flush()?;
                     return Ok(n);
                 }
-                Err(e) => {
-                    if e.kind() == io::ErrorKind::Interrupted {
-                        continue;
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Keep any numbered lines of a sent message, starting over when the
+    /// numbering is reset
+    fn record_numbered_lines(&mut self, message: &str) {
+        for line in message.split_inclusive('\n') {
+            let Some(number) = line_number(line) else {
+                continue;
+            };
+            if self
+                .sent_lines
+                .back()
+                .is_some_and(|(last, _)| *last >= number)
+            {
+                self.sent_lines.clear();
+            }
+            if self.sent_lines.len() >= RESEND_HISTORY {
+                self.sent_lines.pop_front();
+            }
+            self.sent_lines.push_back((number, line.to_string()));
+        }
+    }
+
+    /// Send every line from the requested line number again, after the
+    /// firmware received a corrupted line
+    fn resend_from(&mut self, number: u32) -> Result<(), io::Error> {
+        let lines: Vec<String> = self
+            .sent_lines
+            .iter()
+            .filter(|(sent, _)| *sent >= number)
+            .map(|(_, line)| line.clone())
+            .collect();
+
+        if lines.is_empty() {
+            tracing::warn!("Unable to resend line {}, it's no longer kept", number);
+        }
+        for line in lines {
+            tracing::debug!("Resending to serial message={}", line.trim_end());
+            self._send_serial(&line)?;
+            self.gcode_log.record(GcodeDirection::Sent, &line);
+        }
+        Ok(())
+    }
+
+    /// Reopen the serial port after the connection is lost, such as when the
+    /// USB cable is unplugged, backing off between attempts. Anything sent
+    /// meanwhile is dropped, as it was meant for a printer which may no longer
+    /// be in the state it expected. Returns None if cancelled first
+    async fn reconnect(
+        &mut self,
+        error: io::Error,
+        cancellation_token: &CancellationToken,
+    ) -> Option<BufReader<TTYPort>> {
+        tracing::error!("Lost serial connection to {}: {}", self.serial, error);
+        self.internal_comms.set_connected(false);
+        self.sent_lines.clear();
+
+        let mut backoff = RECONNECT_MIN_BACKOFF;
+        loop {
+            tokio::select! {
+                _ = sleep(backoff) => {},
+                _ = cancellation_token.cancelled() => return None,
+            }
+
+            loop {
+                match self.internal_comms.try_receive().await {
+                    Ok(Some(message)) => tracing::warn!(
+                        "Dropping message sent while disconnected: {}",
+                        message.trim_end()
+                    ),
+                    Ok(None) => break,
+                    // Falling behind only skips messages being dropped anyway
+                    Err(_) => continue,
+                }
+            }
+
+            match open_serial_port(&self.serial, self.baudrate) {
+                Ok(serial_port) => {
+                    self.serial_port = serial_port;
+                    match self.reader() {
+                        Ok(reader) => {
+                            tracing::info!("Reconnected to serial port {}", self.serial);
+                            self.internal_comms.set_connected(true);
+                            return Some(reader);
+                        }
+                        Err(e) => tracing::debug!("Unable to read from {}: {}", self.serial, e),
                     }
                 }
+                Err(e) => tracing::debug!("Unable to reopen {}: {}", self.serial, e),
             }
+            backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF);
         }
     }
 }
@@ -181,49 +472,123 @@ impl SerialHandler for TTYPortHandler {
         self.internal_comms.clone()
     }
 
+    fn get_gcode_log(&self) -> GcodeLog {
+        self.gcode_log.clone()
+    }
+
     async fn run(
         mut self: Box<Self>,
         cancellation_token: CancellationToken,
     ) -> Result<(), OdysseyError> {
-        let mut buf_reader = BufReader::new(
-            self.serial_port
-                .try_clone_native()
-                .map_err(|err| OdysseyError::hardware_error(Box::new(err), 0))?,
-        );
-
-        let mut interval = interval(Duration::from_millis(100));
+        let reader = self
+            .reader()
+            .map_err(|err| OdysseyError::hardware_error(Box::new(err), 0))?;
+        let mut reader_token = cancellation_token.child_token();
+        let mut incoming = spawn_reader(reader, reader_token.clone());
 
         loop {
-            interval.tick().await;
-
-            let mut read_string = String::new();
-            match buf_reader.read_line(&mut read_string) {
-                Err(e) => match e.kind() {
-                    io::ErrorKind::TimedOut => {
-                        continue;
+            let result = tokio::select! {
+                _ = cancellation_token.cancelled() => {
+                    tracing::info!("Shutting down serial processing loop");
+                    return Ok(());
+                }
+                read = incoming.recv() => match read {
+                    Some(Ok(read_string)) => {
+                        tracing::debug!("Read from serial: {}", read_string.trim_end());
+                        self.gcode_log
+                            .record(GcodeDirection::Received, &read_string);
+                        let resend = resend_request(&read_string);
+                        self.internal_comms.send(read_string).await?;
+                        match resend {
+                            Some(number) => self.resend_from(number),
+                            None => Ok(()),
+                        }
                     }
-                    // Broken Pipe here
-                    _ => Err(e)?,
+                    Some(Err(e)) => Err(e),
+                    None => Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "serial reader stopped",
+                    )),
                 },
-                Ok(n) => {
-                    if n > 0 {
-                        tracing::debug!("Read {} bytes from serial: {}", n, read_string.trim_end());
-                        self.internal_comms.send(read_string).await?;
+                message = self.internal_comms.receive() => {
+                    let message = message?;
+                    tracing::debug!("Writing to serial message={}", message);
+                    let result = self._send_serial(&message).map(|_| ());
+                    if result.is_ok() {
+                        self.gcode_log.record(GcodeDirection::Sent, &message);
+                        self.record_numbered_lines(&message);
                     }
+                    result
                 }
             };
 
-            if let Some(message) = self.internal_comms.try_receive().await? {
-                tracing::debug!("Writing to serial message={}", message);
-                self._send_serial(&message).await?;
+            if let Err(e) = result {
+                reader_token.cancel();
+                match self.reconnect(e, &cancellation_token).await {
+                    Some(reader) => {
+                        reader_token = cancellation_token.child_token();
+                        incoming = spawn_reader(reader, reader_token.clone());
+                    }
+                    None => return Ok(()),
+                }
             }
+        }
+    }
+}
+
+/// The number of a line sent with a line number, such as N12 G28*18
+fn line_number(line: &str) -> Option<u32> {
+    let (number, _) = line.strip_prefix('N')?.split_once(' ')?;
+    number.parse().ok()
+}
 
-            if cancellation_token.is_cancelled() {
-                tracing::info!("Shutting down serial processing loop");
-                return Ok(());
+/// The line number the firmware asks to be resent from, after receiving a
+/// line with a bad checksum or out of order
+fn resend_request(line: &str) -> Option<u32> {
+    let line = line.trim();
+    let number = line
+        .strip_prefix("Resend:")
+        .or_else(|| line.strip_prefix("rs "))?;
+    number.trim().trim_start_matches('N').parse().ok()
+}
+
+/// Read lines from the serial port on a blocking thread, passing each one on
+/// as soon as it arrives. The thread stops after the first unrecoverable
+/// error, which is passed on for the connection to be reopened, or once the
+/// token is cancelled
+fn spawn_reader(
+    mut reader: BufReader<TTYPort>,
+    cancellation_token: CancellationToken,
+) -> mpsc::UnboundedReceiver<io::Result<String>> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    spawn_blocking(move || {
+        // Partial lines are kept across timeouts until the rest arrives
+        let mut read_string = String::new();
+        while !cancellation_token.is_cancelled() {
+            match reader.read_line(&mut read_string) {
+                Ok(_) => {
+                    if !read_string.is_empty() && sender.send(Ok(take(&mut read_string))).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => match e.kind() {
+                    io::ErrorKind::TimedOut | io::ErrorKind::Interrupted => {}
+                    io::ErrorKind::InvalidData => {
+                        tracing::warn!("Discarding invalid data read from serial: {}", e);
+                        read_string.clear();
+                    }
+                    // Broken Pipe here
+                    _ => {
+                        let _ = sender.send(Err(e));
+                        return;
+                    }
+                },
             }
         }
-    }
+    });
+
+    receiver
 }
 
 pub async fn run_listener(
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use tokio::time::{sleep, Duration};
+
+use crate::api_objects::PhysicalState;
+use crate::configuration::GcodeConfig;
+use crate::error::OdysseyError;
+use crate::gcode::check_cure_zones;
+use crate::printer::HardwareControl;
+
+/// Speed in mm/s used to simulate a homing move
+const SIMULATED_HOME_SPEED: f64 = 5.0;
+
+/// HardwareControl implementation with no hardware behind it. Tracks a virtual
+/// PhysicalState and waits for roughly as long as the real machine would, so the
+/// statemachine and API can be exercised without a printer attached
+pub struct SimulatedHardware {
+    pub state: PhysicalState,
+    pub print_variables: HashMap<String, String>,
+}
+
+impl SimulatedHardware {
+    pub fn new(config: &GcodeConfig) -> SimulatedHardware {
+        SimulatedHardware {
+            state: PhysicalState {
+                z: 0.0,
+                z_microns: 0,
+                curing: config
+                    .cure_zones()
+                    .into_iter()
+                    .map(|zone| (zone.name, false))
+                    .collect(),
+                temperature: None,
+                firmware_version: None,
+            },
+            print_variables: HashMap::new(),
+        }
+    }
+
+    /// Wait as long as a move from the current position to z would take at the given speed
+    async fn simulate_move(&mut self, z: u32, speed: f64) {
+        let distance = (z.abs_diff(self.state.z_microns) as f64) / 1000.0;
+
+        if speed > 0.0 {
+            sleep(Duration::from_secs_f64(distance / speed)).await;
+        }
+
+        self.state.z_microns = z;
+        self.state.z = (z as f64) / 1000.0;
+    }
+
+    /// Set the curing state of the given zones, or of every zone if none are given
+    fn set_curing(&mut self, zones: Option<Vec<String>>, curing: bool) {
+        match zones {
+            Some(zones) => {
+                for zone in zones {
+                    self.state.curing.insert(zone, curing);
+                }
+            }
+            None => self
+                .state
+                .curing
+                .values_mut()
+                .for_each(|zone_curing| *zone_curing = curing),
+        }
+    }
+}
+
+#[async_trait]
+impl HardwareControl for SimulatedHardware {
+    async fn is_ready(&mut self) -> Result<bool, OdysseyError> {
+        Ok(true)
+    }
+
+    async fn initialize(&mut self) {
+        tracing::info!("Initializing simulated hardware");
+    }
+
+    async fn home(&mut self) -> Result<PhysicalState, OdysseyError> {
+        tracing::info!("Simulating home");
+        self.simulate_move(0, SIMULATED_HOME_SPEED).await;
+        Ok(self.state.clone())
+    }
+
+    async fn verify_home(&mut self) -> Result<PhysicalState, OdysseyError> {
+        self.home().await
+    }
+
+    async fn manual_command(&mut self, command: String) -> Result<PhysicalState, OdysseyError> {
+        tracing::info!("Simulating command: {}", command);
+        Ok(self.state.clone())
+    }
+
+    async fn start_print(&mut self) -> Result<PhysicalState, OdysseyError> {
+        tracing::info!("Simulating print start");
+        Ok(self.state.clone())
+    }
+
+    async fn end_print(&mut self) -> Result<PhysicalState, OdysseyError> {
+        tracing::info!("Simulating print end");
+        Ok(self.state.clone())
+    }
+
+    async fn move_z(
+        &mut self,
+        z: u32,
+        speed: f64,
+        _manual: bool,
+    ) -> Result<PhysicalState, OdysseyError> {
+        tracing::info!("Simulating move to {} at {}mm/s", z, speed);
+        self.simulate_move(z, speed).await;
+        Ok(self.state.clone())
+    }
+
+    async fn start_layer(&mut self, layer: usize) -> Result<PhysicalState, OdysseyError> {
+        tracing::info!("Simulating layer start {}", layer);
+        Ok(self.state.clone())
+    }
+
+    async fn start_curing(
+        &mut self,
+        zones: Option<Vec<String>>,
+    ) -> Result<PhysicalState, OdysseyError> {
+        tracing::debug!("Simulating cure start for zones {:?}", zones);
+        self.set_curing(zones, true);
+        Ok(self.state.clone())
+    }
+
+    async fn stop_curing(
+        &mut self,
+        zones: Option<Vec<String>>,
+    ) -> Result<PhysicalState, OdysseyError> {
+        tracing::debug!("Simulating cure end for zones {:?}", zones);
+        self.set_curing(zones, false);
+        Ok(self.state.clone())
+    }
+
+    async fn boot(&mut self) -> Result<PhysicalState, OdysseyError> {
+        tracing::info!("Simulating boot");
+        Ok(self.state.clone())
+    }
+
+    async fn poll_status(&mut self) -> Result<PhysicalState, OdysseyError> {
+        Ok(self.state.clone())
+    }
+
+    async fn shutdown(&mut self) -> Result<(), OdysseyError> {
+        tracing::info!("Simulating shutdown");
+        self.set_curing(None, false);
+        Ok(())
+    }
+
+    async fn emergency_stop(&mut self) -> Result<PhysicalState, OdysseyError> {
+        tracing::info!("Simulating emergency stop");
+        self.set_curing(None, false);
+        Ok(self.state.clone())
+    }
+
+    fn get_physical_state(&self) -> Result<PhysicalState, OdysseyError> {
+        Ok(self.state.clone())
+    }
+
+    fn is_connected(&self) -> Option<bool> {
+        None
+    }
+
+    fn reload_config(&mut self, config: &GcodeConfig) -> Result<(), OdysseyError> {
+        check_cure_zones(&self.state, config)
+    }
+
+    fn add_print_variable(&mut self, variable: String, value: String) {
+        self.print_variables.insert(variable, value);
+    }
+
+    fn remove_print_variable(&mut self, variable: String) {
+        self.print_variables.remove(&variable);
+    }
+
+    fn clear_variables(&mut self) {
+        self.print_variables.clear();
+    }
+}
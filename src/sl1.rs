@@ -6,17 +6,18 @@ use std::{
 use async_trait::async_trait;
 use config::{Config, ConfigError, File as ConfigFile, FileFormat};
 use itertools::Itertools;
+use regex::Regex;
 use serde::Deserialize;
 use zip::ZipArchive;
 
 use crate::{
-    api_objects::{FileData, FileMetadata, PrintMetadata, ThumbnailSize},
+    api_objects::{FileData, FileMetadata, PrintMetadata},
     printfile::{Layer, PrintFile},
+    thumbnail::{self, EmbeddedThumbnail},
 };
 
 const CONFIG_FILE: &str = "config.ini";
-const THUMBNAIL_SMALL: &str = "thumbnail/thumbnail400x400.png";
-const THUMBNAIL_LARGE: &str = "thumbnail/thumbnail800x480.png";
+const THUMBNAIL_PATTERN: &str = r"^thumbnail/thumbnail(?P<width>\d+)x(?P<height>\d+)\.png$";
 
 /// PrintConfig object encompassing the fields stored in `config.ini` inside a `.sl1` file
 #[derive(Debug, Deserialize)]
@@ -70,6 +71,7 @@ pub struct Sl1 {
     config: PrintConfig,
     archive: ZipArchive<File>,
     frame_list: Vec<String>,
+    thumbnails: Vec<EmbeddedThumbnail>,
     metadata: PrintMetadata,
 }
 
@@ -102,6 +104,19 @@ impl PrintFile for Sl1 {
             .sorted()
             .collect();
 
+        let thumbnail_re = Regex::new(THUMBNAIL_PATTERN).unwrap();
+        let thumbnails: Vec<EmbeddedThumbnail> = archive
+            .file_names()
+            .filter_map(|name| {
+                let caps = thumbnail_re.captures(name)?;
+                Some(EmbeddedThumbnail {
+                    path: name.to_string(),
+                    width: caps["width"].parse().ok()?,
+                    height: caps["height"].parse().ok()?,
+                })
+            })
+            .collect();
+
         let metadata = PrintMetadata {
             file_data,
             used_material: config.used_material,
@@ -110,10 +125,12 @@ impl PrintFile for Sl1 {
             layer_height_microns: ((config.layer_height * 1000.0).trunc() as u32),
             layer_count: frame_list.len(),
             user_metadata,
+            estimated_print_time: None,
         };
 
         Ok(Sl1 {
             frame_list,
+            thumbnails,
             archive,
             config,
             metadata,
@@ -145,6 +162,10 @@ impl PrintFile for Sl1 {
         self.frame_list.len()
     }
 
+    fn get_exposure_time(&self, index: usize) -> f64 {
+        self.config.exposure_time(index)
+    }
+
     fn get_layer_height(&self) -> u32 {
         (self.config.layer_height * 1000.0).trunc() as u32
     }
@@ -153,19 +174,20 @@ impl PrintFile for Sl1 {
         self.metadata.clone()
     }
 
-    fn get_thumbnail(&mut self, size: ThumbnailSize) -> Result<FileData, Error> {
-        let mut thumbnail_file = match size {
-            ThumbnailSize::Small => self.archive.by_name(THUMBNAIL_SMALL)?,
-            ThumbnailSize::Large => self.archive.by_name(THUMBNAIL_LARGE)?,
+    fn get_thumbnail(&mut self, width: u32, height: u32) -> Result<FileData, Error> {
+        let (width, height) = thumbnail::clamp_dimensions(width, height);
+
+        let Some(embedded) = thumbnail::closest_thumbnail(&self.thumbnails, width, height) else {
+            tracing::debug!("No thumbnail embedded in file, generating placeholder");
+            return thumbnail::placeholder_thumbnail(width, height);
         };
 
         let mut ret: Vec<u8> = Vec::new();
 
-        thumbnail_file.read_to_end(&mut ret)?;
+        self.archive
+            .by_name(embedded.path.as_str())?
+            .read_to_end(&mut ret)?;
 
-        Ok(FileData {
-            name: "thumbnail.png".to_string(),
-            data: ret,
-        })
+        thumbnail::fit_thumbnail(ret, width, height)
     }
 }
@@ -0,0 +1,78 @@
+use crate::configuration::StatusOverlayConfig;
+
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+
+/// 3x5 bitmap glyphs for the characters the status overlay needs, one row per
+/// entry with the leftmost pixel in the highest of the three low bits
+fn glyph(character: char) -> Option<[u8; GLYPH_HEIGHT]> {
+    match character {
+        '0' => Some([0b111, 0b101, 0b101, 0b101, 0b111]),
+        '1' => Some([0b010, 0b110, 0b010, 0b010, 0b111]),
+        '2' => Some([0b111, 0b001, 0b111, 0b100, 0b111]),
+        '3' => Some([0b111, 0b001, 0b111, 0b001, 0b111]),
+        '4' => Some([0b101, 0b101, 0b111, 0b001, 0b001]),
+        '5' => Some([0b111, 0b100, 0b111, 0b001, 0b111]),
+        '6' => Some([0b111, 0b100, 0b111, 0b101, 0b111]),
+        '7' => Some([0b111, 0b001, 0b001, 0b001, 0b001]),
+        '8' => Some([0b111, 0b101, 0b111, 0b101, 0b111]),
+        '9' => Some([0b111, 0b101, 0b111, 0b001, 0b111]),
+        '/' => Some([0b001, 0b001, 0b010, 0b100, 0b100]),
+        ':' => Some([0b000, 0b010, 0b000, 0b010, 0b000]),
+        'L' => Some([0b100, 0b100, 0b100, 0b100, 0b111]),
+        ' ' => Some([0b000; GLYPH_HEIGHT]),
+        _ => None,
+    }
+}
+
+/// Render the given text into the configured region of an 8-bit grayscale
+/// buffer of the given panel size. The region is cleared first, and the buffer
+/// is extended if it doesn't reach the end of the region. Any of the region
+/// beyond the panel is left out, so the frame never outgrows it
+pub fn render_status(
+    buffer: &mut Vec<u8>,
+    width: usize,
+    screen_height: usize,
+    config: &StatusOverlayConfig,
+    text: &str,
+) {
+    let row_offset = config.row_offset as usize;
+    if row_offset >= screen_height {
+        return;
+    }
+    let height = (config.height as usize).min(screen_height - row_offset);
+
+    let region_end = (row_offset + height) * width;
+    if buffer.len() < region_end {
+        buffer.resize(region_end, 0x00);
+    }
+    buffer[row_offset * width..region_end].fill(0x00);
+
+    // Leave a blank glyph row above and below the text
+    let scale = (height / (GLYPH_HEIGHT + 2)).max(1);
+    let top = row_offset + scale;
+
+    for (index, character) in text.chars().enumerate() {
+        let Some(rows) = glyph(character) else {
+            continue;
+        };
+        let left = scale + index * (GLYPH_WIDTH + 1) * scale;
+
+        for (glyph_y, row) in rows.iter().enumerate() {
+            for glyph_x in 0..GLYPH_WIDTH {
+                if row & (1 << (GLYPH_WIDTH - 1 - glyph_x)) == 0 {
+                    continue;
+                }
+                for y in 0..scale {
+                    let pixel_y = top + glyph_y * scale + y;
+                    let pixel_x = left + glyph_x * scale;
+                    if pixel_y >= row_offset + height || pixel_x + scale > width {
+                        continue;
+                    }
+                    let start = pixel_y * width + pixel_x;
+                    buffer[start..start + scale].fill(0xFF);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::io::{self, Cursor, Error};
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView, ImageFormat, Luma};
+
+use crate::api_objects::FileData;
+
+const THUMBNAIL_NAME: &str = "thumbnail.png";
+/// Upper bound on requested thumbnail dimensions, to keep resizing cheap
+const MAX_THUMBNAIL_DIMENSION: u32 = 2048;
+const PLACEHOLDER_SHADE: u8 = 0x80;
+
+/// A thumbnail embedded in a print file, identified by its path and dimensions
+#[derive(Clone, Debug)]
+pub struct EmbeddedThumbnail {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Clamp requested dimensions to a sane range
+pub fn clamp_dimensions(width: u32, height: u32) -> (u32, u32) {
+    (
+        width.clamp(1, MAX_THUMBNAIL_DIMENSION),
+        height.clamp(1, MAX_THUMBNAIL_DIMENSION),
+    )
+}
+
+/// Pick the embedded thumbnail best suited to the requested size: the smallest
+/// one covering it, or the largest available if none do
+pub fn closest_thumbnail(
+    thumbnails: &[EmbeddedThumbnail],
+    width: u32,
+    height: u32,
+) -> Option<&EmbeddedThumbnail> {
+    let area = |thumbnail: &&EmbeddedThumbnail| thumbnail.width * thumbnail.height;
+
+    thumbnails
+        .iter()
+        .filter(|thumbnail| thumbnail.width >= width && thumbnail.height >= height)
+        .min_by_key(area)
+        .or_else(|| thumbnails.iter().max_by_key(area))
+}
+
+/// Scale the given PNG thumbnail to fit within the requested size, keeping its
+/// aspect ratio. Thumbnails already at the requested size are returned as-is
+pub fn fit_thumbnail(data: Vec<u8>, width: u32, height: u32) -> Result<FileData, Error> {
+    let image = image::load_from_memory_with_format(&data, ImageFormat::Png)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    if image.dimensions() == (width, height) {
+        return Ok(FileData {
+            name: THUMBNAIL_NAME.to_string(),
+            data,
+            width: Some(width),
+            height: Some(height),
+        });
+    }
+
+    encode_thumbnail(image.resize(width, height, FilterType::Triangle))
+}
+
+/// Scale the given PNG down to fit within the requested size, keeping its
+/// aspect ratio. Images which already fit are returned as-is, never enlarged
+pub fn downscale_png(data: Vec<u8>, max_width: u32, max_height: u32) -> Result<Vec<u8>, Error> {
+    let image = image::load_from_memory_with_format(&data, ImageFormat::Png)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    if image.width() <= max_width && image.height() <= max_height {
+        return Ok(data);
+    }
+
+    Ok(encode_thumbnail(image.resize(max_width, max_height, FilterType::Triangle))?.data)
+}
+
+/// Generate a plain placeholder thumbnail, for files with none embedded
+pub fn placeholder_thumbnail(width: u32, height: u32) -> Result<FileData, Error> {
+    encode_thumbnail(DynamicImage::ImageLuma8(image::ImageBuffer::from_pixel(
+        width,
+        height,
+        Luma([PLACEHOLDER_SHADE]),
+    )))
+}
+
+fn encode_thumbnail(image: DynamicImage) -> Result<FileData, Error> {
+    let mut data = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    Ok(FileData {
+        name: THUMBNAIL_NAME.to_string(),
+        data,
+        width: Some(image.width()),
+        height: Some(image.height()),
+    })
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use glob::Pattern;
+use tokio::{
+    sync::broadcast,
+    time::{interval, Duration},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::api_objects::{StorageInfo, UsbEvent, UsbEventType};
+
+/// How often the mount table is checked for USB drives coming and going
+const USB_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+const MOUNTS_FILE: &str = "/proc/mounts";
+
+/// Pattern matching the mount points of the USB drives searched by the
+/// usb_glob, which is the glob's directory up to and including its first
+/// wildcard, such as /media/usb* for /media/usb*/*.sl1. Without any wildcard
+/// it's the directory the files are found in
+pub fn mount_pattern(usb_glob: &str) -> Option<Pattern> {
+    let path = Path::new(usb_glob).parent()?;
+    let is_wildcard = |component: &Component| {
+        component
+            .as_os_str()
+            .to_str()
+            .is_some_and(|component| component.contains(['*', '?', '[']))
+    };
+
+    let mount_root: PathBuf = match path.components().position(|c| is_wildcard(&c)) {
+        Some(index) => path.components().take(index + 1).collect(),
+        None => path.to_path_buf(),
+    };
+
+    Pattern::new(mount_root.to_str()?).ok()
+}
+
+/// Mount points listed in a mount table in the format of /proc/mounts, which
+/// escapes spaces and other whitespace as octal
+pub fn parse_mounts(mounts: &str) -> Vec<String> {
+    mounts
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(unescape_mount_point)
+        .collect()
+}
+
+fn unescape_mount_point(mount_point: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = mount_point;
+    while let Some(index) = rest.find('\\') {
+        unescaped.push_str(&rest[..index]);
+        let escaped = rest.get(index + 1..index + 4);
+        match escaped.and_then(|code| u8::from_str_radix(code, 8).ok()) {
+            Some(byte) => {
+                unescaped.push(byte as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Bytes available to unprivileged users on the mounted filesystem
+fn free_bytes(mount_path: &str) -> Option<u64> {
+    StorageInfo::from_path(Path::new(mount_path))
+        .map(|storage| storage.free_bytes)
+        .inspect_err(|e| tracing::debug!("Unable to read free space of {}: {}", mount_path, e))
+        .ok()
+}
+
+fn usb_mounts(pattern: &Pattern) -> BTreeSet<String> {
+    match fs::read_to_string(MOUNTS_FILE) {
+        Ok(mounts) => parse_mounts(&mounts)
+            .into_iter()
+            .filter(|mount_point| pattern.matches(mount_point))
+            .collect(),
+        Err(e) => {
+            tracing::warn!("Unable to read {}: {}", MOUNTS_FILE, e);
+            BTreeSet::new()
+        }
+    }
+}
+
+/// Watch the mount table for USB drives matching the usb_glob being mounted or
+/// unmounted, sending an event for each. Drives already mounted at startup are
+/// listed as usual, without an event
+pub async fn run_usb_watcher(
+    usb_glob: String,
+    sender: broadcast::Sender<UsbEvent>,
+    cancellation_token: CancellationToken,
+) {
+    let Some(pattern) = mount_pattern(&usb_glob) else {
+        tracing::info!(
+            "No USB mount points to watch for in usb_glob {:?}",
+            usb_glob
+        );
+        return;
+    };
+    tracing::info!("Watching for USB drives mounted at {}", pattern);
+
+    let mut mounted = usb_mounts(&pattern);
+    let mut interval = interval(USB_POLL_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = cancellation_token.cancelled() => {
+                tracing::info!("Shutting down USB watcher");
+                return;
+            }
+            _ = interval.tick() => {}
+        }
+
+        let current = usb_mounts(&pattern);
+
+        let inserted = current
+            .difference(&mounted)
+            .map(|mount_path| (UsbEventType::Inserted, mount_path));
+        let removed = mounted
+            .difference(&current)
+            .map(|mount_path| (UsbEventType::Removed, mount_path));
+
+        for (event_type, mount_path) in inserted.chain(removed) {
+            tracing::info!("USB drive {:?} at {}", event_type, mount_path);
+            let free_bytes = match event_type {
+                UsbEventType::Inserted => free_bytes(mount_path),
+                UsbEventType::Removed => None,
+            };
+
+            // Nobody listening is no reason to stop watching
+            let _ = sender.send(UsbEvent {
+                event_type,
+                mount_path: mount_path.clone(),
+                free_bytes,
+            });
+        }
+
+        mounted = current;
+    }
+}
@@ -0,0 +1,118 @@
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::PrinterConfig;
+
+const DEFAULT_UV_USAGE_FILE: &str = "uv_usage.yaml";
+
+/// UV-on time is written out once this many seconds have built up unsaved, so
+/// the file isn't rewritten after every layer
+const SAVE_INTERVAL_SECONDS: f64 = 60.0;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UvUsageFile {
+    on_seconds: f64,
+}
+
+/// Cumulative time the UV array has been on across every print, persisted so
+/// the array's remaining life can be tracked between restarts
+pub struct UvUsage {
+    path: String,
+    on_seconds: f64,
+    unsaved_seconds: f64,
+    lifespan_seconds: Option<f64>,
+}
+
+impl UvUsage {
+    /// Load the recorded UV-on time, starting from zero if none was recorded
+    pub fn load(config: &PrinterConfig) -> UvUsage {
+        let path = config
+            .uv_usage_file
+            .clone()
+            .unwrap_or(DEFAULT_UV_USAGE_FILE.to_string());
+
+        let on_seconds = match fs::read_to_string(&path) {
+            Ok(content) => match serde_yaml::from_str::<UvUsageFile>(&content) {
+                Ok(usage) => usage.on_seconds,
+                Err(e) => {
+                    tracing::error!("Unable to parse UV usage file {}: {}", path, e);
+                    0.0
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0.0,
+            Err(e) => {
+                tracing::error!("Unable to read UV usage file {}: {}", path, e);
+                0.0
+            }
+        };
+
+        let usage = UvUsage {
+            path,
+            on_seconds,
+            unsaved_seconds: 0.0,
+            lifespan_seconds: config.uv_lifespan_hours.map(|hours| hours * 3600.0),
+        };
+        tracing::info!("UV array has been on for {:.1}h", on_seconds / 3600.0);
+        if usage.past_lifespan() {
+            usage.warn_lifespan();
+        }
+        usage
+    }
+
+    pub fn set_lifespan_hours(&mut self, lifespan_hours: Option<f64>) {
+        self.lifespan_seconds = lifespan_hours.map(|hours| hours * 3600.0);
+    }
+
+    pub fn on_seconds(&self) -> f64 {
+        self.on_seconds
+    }
+
+    /// Record time the UV array spent on, warning if it crosses the lifespan
+    pub fn add(&mut self, seconds: f64) {
+        let was_past_lifespan = self.past_lifespan();
+        self.on_seconds += seconds;
+        self.unsaved_seconds += seconds;
+
+        if !was_past_lifespan && self.past_lifespan() {
+            self.warn_lifespan();
+        }
+        if self.unsaved_seconds >= SAVE_INTERVAL_SECONDS {
+            self.save();
+        }
+    }
+
+    /// Write out any unsaved UV-on time
+    pub fn save(&mut self) {
+        if self.unsaved_seconds == 0.0 {
+            return;
+        }
+
+        // Write then rename, so an interrupted write can't lose the total
+        let temp_path = format!("{}.tmp", self.path);
+        let result = serde_yaml::to_string(&UvUsageFile {
+            on_seconds: self.on_seconds,
+        })
+        .map_err(io::Error::other)
+        .and_then(|content| fs::write(&temp_path, content))
+        .and_then(|_| fs::rename(&temp_path, &self.path));
+
+        match result {
+            Ok(()) => self.unsaved_seconds = 0.0,
+            Err(e) => tracing::error!("Unable to save UV usage to {}: {}", self.path, e),
+        }
+    }
+
+    fn past_lifespan(&self) -> bool {
+        self.lifespan_seconds
+            .is_some_and(|lifespan| self.on_seconds >= lifespan)
+    }
+
+    fn warn_lifespan(&self) {
+        tracing::warn!(
+            "UV array has been on for {:.1}h, past its configured lifespan of {:.1}h. Plan to replace it",
+            self.on_seconds / 3600.0,
+            self.lifespan_seconds.unwrap_or_default() / 3600.0
+        );
+    }
+}
@@ -1,7 +1,12 @@
-use std::{fs::OpenOptions, io::Write};
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+};
 
 use framebuffer::Framebuffer;
 
+use crate::display::DisplayDevice;
+
 // Wrap the real framebuffer in this, with a corresponding write call
 // If none, write to file at given path instead, so we can pretend it's real
 // and examine the output manually if we like
@@ -10,24 +15,44 @@ pub struct WrappedFramebuffer {
     pub fb_path: String,
 }
 
-impl WrappedFramebuffer {
+impl DisplayDevice for WrappedFramebuffer {
     ///Writes a frame to the Framebuffer, or to the fb_path if not a real buffer
-    pub fn write_frame(&mut self, frame: &[u8]) {
+    fn write_frame(&mut self, frame: &[u8]) -> Result<(), io::Error> {
         match self.frame_buffer.as_mut() {
-            Some(fb) => fb.write_frame(frame),
+            Some(fb) => {
+                let fb_len = fb.read_frame().len();
+                if frame.len() != fb_len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Frame is {} bytes but the framebuffer holds {}",
+                            frame.len(),
+                            fb_len
+                        ),
+                    ));
+                }
+                fb.write_frame(frame);
+
+                // Writes into the mapped buffer don't report failure, so read
+                // the frame back to make sure it landed
+                if fb.read_frame() != frame {
+                    return Err(io::Error::other(
+                        "Framebuffer contents do not match the written frame",
+                    ));
+                }
+                Ok(())
+            }
             None => {
                 tracing::info!("Writing layer to path: {}", self.fb_path);
-                match OpenOptions::new()
+                OpenOptions::new()
                     .append(true)
-                    .open(self.fb_path.clone())
-                    .as_mut()
-                {
-                    Ok(output_file) => {
-                        let _ = output_file.write_all(frame);
-                    }
-                    Err(e) => tracing::error!("Error while writing layer: {}", e),
-                }
+                    .open(self.fb_path.clone())?
+                    .write_all(frame)
             }
         }
     }
+
+    fn is_device(&self) -> bool {
+        self.frame_buffer.is_some()
+    }
 }
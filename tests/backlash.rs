@@ -0,0 +1,125 @@
+use odyssey::{
+    gcode::{BacklashCompensation, Gcode, MoveDirection},
+    printer::HardwareControl,
+    serial_handler::InternalCommsHandler,
+};
+
+use crate::common::default_test_configuration;
+
+mod common;
+
+#[test]
+fn test_move_direction() {
+    assert_eq!(MoveDirection::between(1000, 2000), Some(MoveDirection::Up));
+    assert_eq!(
+        MoveDirection::between(2000, 1000),
+        Some(MoveDirection::Down)
+    );
+    assert_eq!(MoveDirection::between(1000, 1000), None);
+}
+
+#[test]
+fn test_reversal_detection() {
+    let mut backlash = BacklashCompensation::new(50, 0, 150_000);
+
+    // Without a previous move, there's nothing to reverse
+    assert!(!backlash.reverses(0, 1000));
+    assert_eq!(backlash.compensate(0, 1000), 1000);
+
+    assert!(!backlash.reverses(1000, 2000));
+    assert!(backlash.reverses(2000, 1000));
+    // A move which goes nowhere keeps the last direction
+    assert!(!backlash.reverses(2000, 2000));
+    assert_eq!(backlash.compensate(1000, 1000), 1000);
+    assert_eq!(backlash.last_direction, Some(MoveDirection::Up));
+}
+
+#[test]
+fn test_compensation_on_reversal() {
+    let mut backlash = BacklashCompensation::new(50, 0, 150_000);
+
+    // Lift and descent of successive layers, the descent reversing the lift
+    assert_eq!(backlash.compensate(0, 50), 50);
+    assert_eq!(backlash.compensate(50, 1050), 1050);
+    assert_eq!(backlash.compensate(1050, 100), 50);
+    // Carrying on down needs no further compensation
+    assert_eq!(backlash.compensate(100, 80), 30);
+    assert_eq!(backlash.compensate(80, 1080), 1080);
+    assert_eq!(backlash.compensate(1080, 130), 80);
+
+    backlash.reset();
+    assert_eq!(backlash.last_direction, None);
+    assert_eq!(backlash.compensate(0, 1000), 1000);
+}
+
+#[test]
+fn test_compensation_is_clamped_to_z_limits() {
+    let mut backlash = BacklashCompensation::new(200, 0, 150_000);
+
+    // Descending to the second layer would otherwise be sent as -0.1mm
+    assert_eq!(backlash.compensate(0, 1100), 1100);
+    assert_eq!(backlash.compensate(1100, 100), 0);
+    assert_eq!(backlash.position(100), 0);
+    // Further up, the full compensation applies again
+    assert_eq!(backlash.compensate(100, 1150), 1150);
+    assert_eq!(backlash.compensate(1150, 300), 100);
+
+    // And the top of travel is respected the same way
+    let mut backlash = BacklashCompensation::new(200, 0, 10_000);
+    assert_eq!(backlash.compensate(5000, 4000), 4000);
+    assert_eq!(backlash.compensate(4000, 9900), 10_000);
+}
+
+#[tokio::test]
+async fn test_compensation_is_not_reported() {
+    let config = default_test_configuration().gcode;
+    let move_sync = config.move_sync.clone();
+    let mut serial = InternalCommsHandler::new();
+    let mut gcode = Gcode::new(
+        &config,
+        BacklashCompensation::new(50, 0, 150_000),
+        serial.invert(),
+    );
+
+    let mut sent = Vec::new();
+    for z in [1000, 200] {
+        let (state, line) = tokio::join!(gcode.move_z(z, 1.0, false), async {
+            let line = serial.receive().await.unwrap();
+            serial.send(move_sync.clone()).await.unwrap();
+            line
+        });
+        let state = state.expect("Unable to move");
+        assert_eq!(state.z_microns, z);
+        sent.push(line);
+    }
+
+    assert_eq!(
+        sent,
+        ["MOVE_PLATE Z=1 F=60\r\n", "MOVE_PLATE Z=0.15 F=60\r\n"]
+    );
+}
+
+#[tokio::test]
+async fn test_failed_move_keeps_compensation() {
+    let mut config = default_test_configuration().gcode;
+    config.move_timeout = 1;
+    let move_sync = config.move_sync.clone();
+    let mut serial = InternalCommsHandler::new();
+    let mut gcode = Gcode::new(
+        &config,
+        BacklashCompensation::new(50, 0, 150_000),
+        serial.invert(),
+    );
+
+    let (state, _) = tokio::join!(gcode.move_z(1000, 1.0, false), async {
+        serial.receive().await.unwrap();
+        serial.send(move_sync.clone()).await.unwrap();
+    });
+    state.expect("Unable to move");
+
+    // The reversing move is never acknowledged
+    assert!(gcode.move_z(200, 1.0, false).await.is_err());
+    assert_eq!(gcode.state.z_microns, 1000);
+    assert_eq!(gcode.backlash.last_direction, Some(MoveDirection::Up));
+    assert_eq!(gcode.backlash.offset_microns, 0);
+}
@@ -8,12 +8,14 @@ use odyssey::{
 use tokio::time::interval;
 use tokio_util::sync::CancellationToken;
 
+#[allow(dead_code)]
 pub struct MockSerialHandler {
     internal_comms: InternalCommsHandler,
     pub response_map: HashMap<String, String>,
     pub default_response: String,
 }
 
+#[allow(dead_code)]
 impl MockSerialHandler {
     pub fn new(default_response: String) -> MockSerialHandler {
         MockSerialHandler {
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use odyssey::configuration::{ApiConfig, Configuration, DisplayConfig, GcodeConfig, PrinterConfig};
 
 pub mod mock_serial_handler;
@@ -15,17 +17,41 @@ pub fn default_test_configuration() -> Configuration {
         printer: PrinterConfig {
             serial: String::from("/dev/null"),
             baudrate: 250000,
+            reload_on_sighup: None,
+            gcode_log_size: None,
             max_z: 300.0,
+            min_z: None,
             default_lift: 10.0,
             default_up_speed: 3.4,
             default_down_speed: 3.4,
+            default_settle_time: None,
             default_wait_before_exposure: 2.2,
             default_wait_after_exposure: 1.5,
             pause_lift: 100.0,
+            retract_on_cancel: None,
+            home_on_boot: None,
+            backlash_microns: None,
+            min_first_layer_area: None,
+            skip_leading_empty_layers: None,
+            retry_failed_layer: None,
+            uv_usage_file: None,
+            print_history_file: None,
+            uv_lifespan_hours: None,
+            max_cure_seconds: None,
+            queue_cooldown_seconds: None,
+            resume_countdown_seconds: None,
+            idle_tick_seconds: None,
+            status_heartbeat_seconds: None,
+            resin_mixing: None,
+            exposure_multiplier: None,
+            wait_before_exposure_multiplier: None,
+            wait_after_exposure_multiplier: None,
+            top_fade: None,
         },
         gcode: GcodeConfig {
             boot: String::from("G90"),
             shutdown: String::from("M84\nUVLED_OFF"),
+            emergency_gcode: None,
             home_command: String::from("HOME_AXIS"),
             move_command: String::from("MOVE_PLATE Z={z} F={speed}"),
             print_start: String::from("START_GCODE TOTAL_LAYERS={total_layers}"),
@@ -38,19 +64,59 @@ pub fn default_test_configuration() -> Configuration {
             status_check: String::from("STATUS_GCODE"),
             status_desired: String::from("READY STATUS RESPONSE"),
             manual_move_command: None,
+            verify_echo: None,
+            temperature_check: None,
+            temperature_regex: None,
+            firmware_version_command: None,
+            firmware_version_regex: None,
+            boot_sync: None,
+            home_sync: None,
+            print_start_sync: None,
+            print_end_sync: None,
+            shutdown_sync: None,
+            cure_zones: None,
+            macros: None,
+            line_ending: None,
+            use_line_numbers: None,
         },
         api: ApiConfig {
             upload_path: upload_path(),
             usb_glob: upload_path(),
             port: 12357,
             enable_docs: Some(true),
+            enable_moonraker: None,
+            max_concurrent_extractions: None,
+            max_queued_extractions: None,
+            max_queued_operations: None,
+            max_manual_moves_per_second: None,
+            manual_cure_debounce_seconds: None,
+            api_key: None,
+            upload_directories: None,
         },
         display: DisplayConfig {
             frame_buffer: "/dev/null".to_owned(),
+            backend: None,
             bit_depth: vec![5, 6, 5],
             screen_width: 1920,
             screen_height: 1080,
+            status_overlay: None,
+            strict_frame_buffer: None,
+            print_without_frame_buffer: None,
+            blank_between_layers: None,
+            write_retries: None,
+            grid_pitch: None,
+            dithering: None,
+            rotation: None,
+            mirror_x: None,
+            mirror_y: None,
+            offset_x: None,
+            offset_y: None,
+            scale_x: None,
+            scale_y: None,
         },
+        resin_profiles: HashMap::new(),
+        directory_profiles: HashMap::new(),
+        mqtt: None,
     }
 }
 
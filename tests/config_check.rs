@@ -0,0 +1,51 @@
+use odyssey::configuration::ProblemSeverity;
+
+use crate::common::default_test_configuration;
+
+mod common;
+
+fn problem_fields(config: &odyssey::configuration::Configuration) -> Vec<String> {
+    config
+        .validate()
+        .into_iter()
+        .map(|problem| problem.field)
+        .collect()
+}
+
+#[test]
+fn test_validate_lists_every_problem() {
+    let mut config = default_test_configuration();
+    config.printer.max_z = 5000.0;
+    config.gcode.home_command = " ".to_string();
+    config.api.upload_path = "/nonexistent/uploads".to_string();
+    config.printer.backlash_microns = Some(500);
+
+    let fields = problem_fields(&config);
+    for field in [
+        "printer.max_z",
+        "gcode.home_command",
+        "api.upload_path",
+        "printer.backlash_microns",
+    ] {
+        assert!(fields.contains(&field.to_string()), "{field} in {fields:?}");
+    }
+    assert!(config
+        .validate()
+        .iter()
+        .all(|problem| problem.severity == ProblemSeverity::Warning));
+}
+
+#[test]
+fn test_validate_reports_invalid_configuration() {
+    let mut config = default_test_configuration();
+    config.printer.min_z = Some(config.printer.max_z + 1.0);
+    config.display.bit_depth = vec![5, 6];
+
+    let errors = config
+        .validate()
+        .into_iter()
+        .filter(|problem| problem.severity == ProblemSeverity::Error)
+        .map(|problem| problem.field)
+        .collect::<Vec<_>>();
+    assert_eq!(errors, vec!["printer", "display"]);
+}
@@ -0,0 +1,47 @@
+use odyssey::config_schema::{config_schema, FIELD_BOUNDS};
+
+#[test]
+fn test_bounds_name_numeric_settings() {
+    let schema = config_schema();
+
+    for bounds in FIELD_BOUNDS {
+        let field = schema
+            .iter()
+            .find(|field| field.field == bounds.field)
+            .unwrap_or_else(|| panic!("{} isn't a config setting", bounds.field));
+        assert!(
+            ["integer", "number"].contains(&field.value_type.as_str()),
+            "{} isn't numeric",
+            bounds.field
+        );
+        if let (Some(min), Some(max)) = (bounds.min, bounds.max) {
+            assert!(min < max, "{} has an empty range", bounds.field);
+        }
+    }
+}
+
+#[test]
+fn test_schema_describes_settings() {
+    let schema = config_schema();
+    let field = |name: &str| {
+        schema
+            .iter()
+            .find(|field| field.field == name)
+            .unwrap_or_else(|| panic!("{} is missing", name))
+    };
+
+    let max_z = field("printer.max_z");
+    assert_eq!(max_z.value_type, "number");
+    assert_eq!(max_z.unit.as_deref(), Some("mm"));
+    assert!(max_z.required);
+
+    let dithering = field("display.dithering");
+    assert_eq!(dithering.value_type, "string");
+    assert_eq!(dithering.options, ["none", "floyd_steinberg", "ordered"]);
+    assert!(!dithering.required);
+    assert!(dithering.description.is_some());
+
+    // Settings of an optional section aren't required unless it's present
+    assert!(!field("mqtt.host").required);
+    assert!(schema.iter().all(|field| field.field != "config_file"));
+}
@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+use odyssey::configuration::{Configuration, ResinProfile};
+
+use crate::common::default_test_configuration;
+
+mod common;
+
+fn configuration(mappings: &[(&str, &str)]) -> Configuration {
+    let mut configuration = default_test_configuration();
+    configuration.resin_profiles = HashMap::from([
+        ("grey".to_string(), ResinProfile::default()),
+        ("clear".to_string(), ResinProfile::default()),
+    ]);
+    configuration.directory_profiles = mappings
+        .iter()
+        .map(|(directory, profile)| (directory.to_string(), profile.to_string()))
+        .collect();
+    configuration
+}
+
+#[test]
+fn test_nearest_directory_profile() {
+    let configuration = configuration(&[("resins", "grey"), ("resins/clear/", "clear")]);
+
+    assert_eq!(
+        configuration.directory_profile("resins/clear/parts/c1.sl1"),
+        Some(&"clear".to_string())
+    );
+    assert_eq!(
+        configuration.directory_profile("resins/c1.sl1"),
+        Some(&"grey".to_string())
+    );
+    assert_eq!(configuration.directory_profile("c1.sl1"), None);
+}
+
+#[test]
+fn test_directory_mapped_to_unknown_profile() {
+    let configuration = configuration(&[("resins", "missing")]);
+
+    assert!(configuration
+        .validate()
+        .iter()
+        .any(|problem| problem.is_error() && problem.field == "directory_profiles"));
+}
@@ -0,0 +1,326 @@
+use std::fs;
+
+use odyssey::{
+    configuration::{DisplayConfig, DitherMode, StatusOverlayConfig},
+    display::{Frame, PrintDisplay},
+};
+use tempfile::NamedTempFile;
+
+const WIDTH: u32 = 16;
+const HEIGHT: u32 = 16;
+
+#[test]
+fn test_re_encode_565() {
+    // Each run of three pixels packs into one 16-bit chunk
+    let pixels = [0xFF, 0x80, 0x10]
+        .into_iter()
+        .cycle()
+        .take((WIDTH * HEIGHT) as usize)
+        .collect();
+    let output = display_pixels(display_config(vec![5, 6, 5], None), screen_frame(pixels));
+
+    let chunk = u16::from_le_bytes([output[0], output[1]]);
+    assert_eq!(chunk >> 11, 0xFF >> 3);
+    assert_eq!((chunk >> 5) & 0x3F, 0x80 >> 2);
+    assert_eq!(chunk & 0x1F, 0x10 >> 3);
+}
+
+#[test]
+fn test_re_encode_without_dithering_truncates() {
+    let output = display_pixels(
+        display_config(vec![1; 8], Some(DitherMode::None)),
+        screen_frame(mid_grey()),
+    );
+
+    // Every pixel at or above half brightness is fully lit
+    assert!(output.iter().all(|&byte| byte == 0xFF));
+}
+
+#[test]
+fn test_re_encode_floyd_steinberg() {
+    let output = display_pixels(
+        display_config(vec![1; 8], Some(DitherMode::FloydSteinberg)),
+        screen_frame(mid_grey()),
+    );
+
+    assert_half_lit(&output);
+}
+
+#[test]
+fn test_re_encode_ordered() {
+    let output = display_pixels(
+        display_config(vec![1; 8], Some(DitherMode::Ordered)),
+        screen_frame(mid_grey()),
+    );
+
+    assert_half_lit(&output);
+    // The Bayer pattern lights alternating pixels for a 50% grey
+    assert!(output.iter().all(|&byte| byte == 0xAA || byte == 0x55));
+}
+
+#[test]
+fn test_re_encode_dithering_keeps_solid_pixels() {
+    let pixels = (0..WIDTH * HEIGHT)
+        .map(|index| if index % 2 == 0 { 0xFF } else { 0x00 })
+        .collect();
+
+    let output = display_pixels(
+        display_config(vec![1; 8], Some(DitherMode::FloydSteinberg)),
+        screen_frame(pixels),
+    );
+
+    assert!(output.iter().all(|&byte| byte == 0xAA));
+}
+
+// Layers sliced for a 3x2 panel, either as is or rotated by 90 or 270 degrees
+fn wide_layer() -> Frame {
+    // 1 2 3
+    // 4 5 6
+    layer_frame(vec![1, 2, 3, 4, 5, 6], 3, 2)
+}
+
+fn tall_layer() -> Frame {
+    // 1 2
+    // 3 4
+    // 5 6
+    layer_frame(vec![1, 2, 3, 4, 5, 6], 2, 3)
+}
+
+#[test]
+fn test_rotate_0() {
+    let output = display_pixels(rotated_config(0, 3, 2), wide_layer());
+
+    assert_eq!(output, vec![1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn test_rotate_90() {
+    // Turned clockwise:  5 3 1
+    //                    6 4 2
+    let output = display_pixels(rotated_config(90, 3, 2), tall_layer());
+
+    assert_eq!(output, vec![5, 3, 1, 6, 4, 2]);
+}
+
+#[test]
+fn test_rotate_180() {
+    let output = display_pixels(rotated_config(180, 3, 2), wide_layer());
+
+    assert_eq!(output, vec![6, 5, 4, 3, 2, 1]);
+}
+
+#[test]
+fn test_rotate_270() {
+    // Turned anticlockwise:  2 4 6
+    //                        1 3 5
+    let output = display_pixels(rotated_config(270, 3, 2), tall_layer());
+
+    assert_eq!(output, vec![2, 4, 6, 1, 3, 5]);
+}
+
+#[test]
+fn test_mirror_x() {
+    let mut config = rotated_config(0, 3, 2);
+    config.mirror_x = Some(true);
+
+    let output = display_pixels(config, wide_layer());
+
+    assert_eq!(output, vec![3, 2, 1, 6, 5, 4]);
+}
+
+#[test]
+fn test_mirror_y_after_rotate_90() {
+    let mut config = rotated_config(90, 3, 2);
+    config.mirror_y = Some(true);
+
+    let output = display_pixels(config, tall_layer());
+
+    assert_eq!(output, vec![6, 4, 2, 5, 3, 1]);
+}
+
+#[test]
+fn test_rotate_rejects_mismatched_frame() {
+    let mut display = PrintDisplay::new(&rotated_config(90, 4, 2));
+
+    let result = display.display_frame(tall_layer());
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_status_overlay_must_fit_panel() {
+    let overlay_config = |row_offset, height| DisplayConfig {
+        status_overlay: Some(StatusOverlayConfig { row_offset, height }),
+        ..offset_config(None, None)
+    };
+
+    assert!(overlay_config(1, 2).validate().is_ok());
+    assert!(overlay_config(2, 2).validate().is_err());
+    assert!(overlay_config(3, 1).validate().is_err());
+}
+
+#[test]
+fn test_status_overlay_is_clamped_to_panel() {
+    let frame_buffer = NamedTempFile::new().expect("Unable to create frame buffer file");
+    let config = DisplayConfig {
+        frame_buffer: frame_buffer.path().to_string_lossy().to_string(),
+        status_overlay: Some(StatusOverlayConfig {
+            row_offset: 2,
+            height: 10,
+        }),
+        ..offset_config(None, None)
+    };
+    let mut display = PrintDisplay::new(&config);
+    display.set_status_text(Some("1".to_string()));
+
+    display
+        .display_frame(layer_frame((1..=12).collect(), 4, 3))
+        .expect("Unable to display frame");
+
+    // Only the last row is left for the overlay, which isn't tall enough for
+    // any text
+    let output = fs::read(frame_buffer.path()).expect("Unable to read frame buffer file");
+    assert_eq!(output, [1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_rotation_must_be_right_angle() {
+    assert!(rotated_config(45, 3, 2).validate().is_err());
+    assert!(rotated_config(270, 3, 2).validate().is_ok());
+}
+
+#[test]
+fn test_offset() {
+    let config = offset_config(Some(1), Some(1));
+
+    let output = display_pixels(config, layer_frame(vec![1, 2, 3, 4], 2, 2));
+
+    #[rustfmt::skip]
+    assert_eq!(output, vec![
+        0, 0, 0, 0,
+        0, 1, 2, 0,
+        0, 3, 4, 0,
+    ]);
+}
+
+#[test]
+fn test_offset_is_clamped_to_panel() {
+    let config = offset_config(Some(5), None);
+
+    let output = display_pixels(config, layer_frame(vec![1, 2, 3, 4], 2, 2));
+
+    #[rustfmt::skip]
+    assert_eq!(output, vec![
+        0, 0, 1, 2,
+        0, 0, 3, 4,
+        0, 0, 0, 0,
+    ]);
+}
+
+#[test]
+fn test_scale_shrinks_about_centre() {
+    let mut config = rotated_config(0, 4, 1);
+    config.scale_x = Some(0.5);
+
+    let output = display_pixels(config, layer_frame(vec![0xFF; 4], 4, 1));
+
+    assert_eq!(output, vec![0, 0xFF, 0xFF, 0]);
+}
+
+#[test]
+fn test_scale_grows_about_centre() {
+    let mut config = rotated_config(0, 1, 4);
+    config.scale_y = Some(2.0);
+
+    let output = display_pixels(config, layer_frame(vec![0, 0, 0xFF, 0xFF], 1, 4));
+
+    // The lit lower half grows upwards, blending into the dark upper half
+    assert_eq!(output, vec![0, 0x40, 0xBF, 0xFF]);
+}
+
+fn offset_config(offset_x: Option<u32>, offset_y: Option<u32>) -> DisplayConfig {
+    DisplayConfig {
+        screen_width: 4,
+        screen_height: 3,
+        offset_x,
+        offset_y,
+        ..display_config(vec![8], None)
+    }
+}
+
+fn rotated_config(rotation: u16, width: u32, height: u32) -> DisplayConfig {
+    DisplayConfig {
+        screen_width: width,
+        screen_height: height,
+        rotation: Some(rotation),
+        ..display_config(vec![8], None)
+    }
+}
+
+fn mid_grey() -> Vec<u8> {
+    vec![0x80; (WIDTH * HEIGHT) as usize]
+}
+
+fn assert_half_lit(output: &[u8]) {
+    let lit: u32 = output.iter().map(|byte| byte.count_ones()).sum();
+    let total = WIDTH * HEIGHT;
+    assert!(
+        lit.abs_diff(total / 2) <= total / 32,
+        "{} of {} pixels lit",
+        lit,
+        total
+    );
+}
+
+fn display_config(bit_depth: Vec<u8>, dithering: Option<DitherMode>) -> DisplayConfig {
+    DisplayConfig {
+        frame_buffer: String::new(),
+        backend: None,
+        bit_depth,
+        screen_width: WIDTH,
+        screen_height: HEIGHT,
+        status_overlay: None,
+        strict_frame_buffer: None,
+        print_without_frame_buffer: None,
+        blank_between_layers: None,
+        write_retries: None,
+        grid_pitch: None,
+        dithering,
+        rotation: None,
+        mirror_x: None,
+        mirror_y: None,
+        offset_x: None,
+        offset_y: None,
+        scale_x: None,
+        scale_y: None,
+    }
+}
+
+fn layer_frame(pixels: Vec<u8>, width: u32, height: u32) -> Frame {
+    Frame {
+        file_name: "layer.png".to_string(),
+        buffer: pixels,
+        exposure_time: 1.0,
+        bit_depth: 8,
+        width,
+        height,
+    }
+}
+
+fn screen_frame(pixels: Vec<u8>) -> Frame {
+    layer_frame(pixels, WIDTH, HEIGHT)
+}
+
+// Display a frame and return the bytes written to the frame buffer
+fn display_pixels(mut config: DisplayConfig, frame: Frame) -> Vec<u8> {
+    let frame_buffer = NamedTempFile::new().expect("Unable to create frame buffer file");
+    config.frame_buffer = frame_buffer.path().to_string_lossy().to_string();
+
+    let mut display = PrintDisplay::new(&config);
+
+    display
+        .display_frame(frame)
+        .expect("Unable to display frame");
+
+    fs::read(frame_buffer.path()).expect("Unable to read frame buffer file")
+}
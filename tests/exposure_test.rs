@@ -0,0 +1,27 @@
+use odyssey::api_objects::ExposureTest;
+
+#[test]
+fn test_exposure_steps_add_up_to_each_zone() {
+    let test = ExposureTest::new(1000, 2.0, 0.5, 4, 1);
+
+    let steps: Vec<f64> = test.exposure_steps().collect();
+    assert_eq!(steps, [2.0, 0.5, 0.5, 0.5]);
+
+    // A zone is lit until it's masked off after its own step
+    for zone in &test.zones {
+        let cured: f64 = steps[..=zone.zone].iter().sum();
+        assert!((cured - zone.exposure_seconds).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_zones_split_screen_evenly() {
+    let test = ExposureTest::new(1000, 2.0, 0.5, 3, 1);
+
+    let bands: Vec<(u32, u32)> = test
+        .zones
+        .iter()
+        .map(|zone| (zone.start_x, zone.end_x))
+        .collect();
+    assert_eq!(bands, [(0, 333), (333, 666), (666, 999)]);
+}
@@ -0,0 +1,43 @@
+use std::io::Cursor;
+
+use image::{GrayImage, ImageFormat, Luma};
+use odyssey::display::Frame;
+
+#[test]
+fn garbage_layer_data_is_rejected() {
+    let garbage = b"definitely not a png".to_vec();
+
+    let result = Frame::from_vec("garbage.png".to_string(), 1.0, garbage);
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn truncated_layer_data_is_rejected() {
+    let mut png = layer_png();
+    png.truncate(png.len() / 2);
+
+    let result = Frame::from_vec("truncated.png".to_string(), 1.0, png);
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn valid_layer_data_is_decoded() {
+    let frame = Frame::from_vec("layer.png".to_string(), 2.5, layer_png())
+        .expect("Valid PNG should decode");
+
+    assert_eq!(frame.bit_depth, 8);
+    assert_eq!(frame.exposure_time, 2.5);
+    assert_eq!(frame.buffer.len(), 16 * 8);
+    assert_eq!(frame.exposed_pixels(), 4 * 8);
+}
+
+fn layer_png() -> Vec<u8> {
+    let image = GrayImage::from_fn(16, 8, |x, _| Luma([if x < 4 { 255 } else { 0 }]));
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .expect("Unable to encode test PNG");
+    png
+}
@@ -0,0 +1,141 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use odyssey::{
+    gcode::{number_line, BacklashCompensation, Gcode},
+    printer::HardwareControl,
+    serial_handler::{InternalCommsHandler, SerialHandler, TTYPortHandler},
+};
+use serialport::{SerialPort, TTYPort};
+use tokio::task::spawn_blocking;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::default_test_configuration;
+
+mod common;
+
+fn gcode(line_ending: Option<&str>, use_line_numbers: bool) -> (Gcode, InternalCommsHandler) {
+    let mut config = default_test_configuration().gcode;
+    config.line_ending = line_ending.map(str::to_string);
+    config.use_line_numbers = Some(use_line_numbers);
+
+    let serial = InternalCommsHandler::new();
+    (
+        Gcode::new(
+            &config,
+            BacklashCompensation::new(0, 0, 150_000),
+            serial.invert(),
+        ),
+        serial,
+    )
+}
+
+#[test]
+fn test_number_line() {
+    assert_eq!(number_line(1, "G28"), "N1 G28*18");
+    assert_eq!(number_line(2, "G1 Z5"), "N2 G1 Z5*101");
+}
+
+#[tokio::test]
+async fn test_default_line_ending() {
+    let (mut gcode, mut serial) = gcode(None, false);
+
+    gcode
+        .manual_command("G28\nM84".to_string())
+        .await
+        .expect("Unable to send gcode");
+
+    assert_eq!(serial.receive().await.unwrap(), "G28\nM84\r\n");
+}
+
+#[tokio::test]
+async fn test_numbered_lines() {
+    let (mut gcode, mut serial) = gcode(Some("\n"), true);
+
+    gcode
+        .manual_command("G28\nG1 Z5 ; lift".to_string())
+        .await
+        .expect("Unable to send gcode");
+    gcode
+        .manual_command("M110 N0\nM84".to_string())
+        .await
+        .expect("Unable to send gcode");
+
+    assert_eq!(serial.receive().await.unwrap(), "N1 G28*18\nN2 G1 Z5*101\n");
+    assert_eq!(
+        serial.receive().await.unwrap(),
+        "N0 M110 N0*125\nN1 M84*30\n"
+    );
+}
+
+#[tokio::test]
+async fn test_numbering_reset_after_reconnect() {
+    let (mut gcode, mut serial) = gcode(Some("\n"), true);
+
+    gcode
+        .manual_command("G28".to_string())
+        .await
+        .expect("Unable to send gcode");
+    serial.set_connected(false);
+    serial.set_connected(true);
+    gcode
+        .manual_command("M84".to_string())
+        .await
+        .expect("Unable to send gcode");
+    gcode
+        .manual_command("G28".to_string())
+        .await
+        .expect("Unable to send gcode");
+
+    assert_eq!(serial.receive().await.unwrap(), "N1 G28*18\n");
+    assert_eq!(
+        serial.receive().await.unwrap(),
+        "N0 M110 N0*125\nN1 M84*30\n"
+    );
+    assert_eq!(serial.receive().await.unwrap(), "N2 G28*17\n");
+}
+
+/// Read from the printer's end of the connection until the expected text has
+/// arrived, or give up after a few seconds
+fn read_until(port: &mut TTYPort, expected: &str) -> String {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut read = String::new();
+    let mut buf = [0; 256];
+    while !read.contains(expected) && Instant::now() < deadline {
+        match port.read(&mut buf) {
+            Ok(n) => read.push_str(&String::from_utf8_lossy(&buf[..n])),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => panic!("Unable to read from the serial port: {}", e),
+        }
+    }
+    read
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_resend_request() {
+    let (mut printer_port, odyssey_port) = TTYPort::pair().expect("Unable to open a pty");
+    let mut config = default_test_configuration().printer;
+    config.serial = odyssey_port.name().unwrap();
+
+    let handler = TTYPortHandler::open(&config).expect("Unable to open the serial port");
+    let comms = handler.get_internal_comms().invert();
+    let cancellation_token = CancellationToken::new();
+    let handle = tokio::spawn(Box::new(handler).run(cancellation_token.clone()));
+
+    comms
+        .send("N1 G28*18\nN2 G1 Z5*101\nN3 M84*29\n".to_string())
+        .await
+        .unwrap();
+    let sent = spawn_blocking(move || {
+        let sent = read_until(&mut printer_port, "N3 M84*29\n");
+        printer_port.write_all(b"Resend: 2\n").unwrap();
+        (read_until(&mut printer_port, "N3 M84*29\n"), sent)
+    });
+    let (resent, sent) = sent.await.unwrap();
+
+    assert_eq!(sent, "N1 G28*18\nN2 G1 Z5*101\nN3 M84*29\n");
+    assert_eq!(resent, "N2 G1 Z5*101\nN3 M84*29\n");
+
+    cancellation_token.cancel();
+    handle.await.unwrap().unwrap();
+}
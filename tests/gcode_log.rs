@@ -0,0 +1,23 @@
+use odyssey::{api_objects::GcodeDirection, serial_handler::GcodeLog};
+
+#[test]
+fn test_gcode_log_drops_oldest_lines() {
+    let log = GcodeLog::new(3);
+
+    log.record(GcodeDirection::Sent, "G28\r\nG90\r\n");
+    log.record(GcodeDirection::Received, "ok\n");
+    log.record(GcodeDirection::Sent, "MOVE_PLATE Z=10\r\n");
+
+    let lines: Vec<String> = log.entries().into_iter().map(|entry| entry.line).collect();
+
+    assert_eq!(lines, vec!["G90", "ok", "MOVE_PLATE Z=10"]);
+}
+
+#[test]
+fn test_disabled_gcode_log() {
+    let log = GcodeLog::new(0);
+
+    log.record(GcodeDirection::Sent, "G28\r\n");
+
+    assert!(log.entries().is_empty());
+}
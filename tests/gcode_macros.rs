@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+
+use odyssey::gcode::{expand_macros, substitute};
+
+fn macros(definitions: &[(&str, &str)]) -> HashMap<String, String> {
+    definitions
+        .iter()
+        .map(|(name, body)| (name.to_string(), body.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_expand_nested_macros() {
+    let macros = macros(&[
+        ("drain", "MOVE_PLATE Z={max_z}\n{{wait}}\n"),
+        ("wait", "G4 P1000"),
+    ]);
+
+    let expanded = expand_macros("{{drain}}\nM84", &macros).expect("Unable to expand macros");
+
+    assert_eq!(expanded, "MOVE_PLATE Z={max_z}\nG4 P1000\nM84");
+}
+
+#[test]
+fn test_substitute_after_expansion() {
+    let macros = macros(&[("lift", "MOVE_PLATE Z={z}")]);
+    let substitutions = HashMap::from([("z".to_string(), "10".to_string())]);
+
+    let expanded = expand_macros("{{lift}}", &macros).expect("Unable to expand macros");
+
+    assert_eq!(
+        substitute(&expanded, &substitutions),
+        Ok("MOVE_PLATE Z=10".to_string())
+    );
+}
+
+#[test]
+fn test_expand_unknown_macro() {
+    assert!(expand_macros("{{missing}}", &HashMap::new()).is_err());
+}
+
+#[test]
+fn test_expand_recursive_macro() {
+    let macros = macros(&[("ping", "{{pong}}"), ("pong", "{{ping}}")]);
+
+    assert!(expand_macros("{{ping}}", &macros).is_err());
+}
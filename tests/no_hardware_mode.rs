@@ -44,6 +44,7 @@ fn _no_hardware_mode(temp_uploads: bool) {
         .expect("Config could not be parsed");
 
     configuration.display.frame_buffer = temp_fb.as_os_str().to_str().unwrap().to_owned();
+    configuration.display.print_without_frame_buffer = Some(true);
     configuration.config_file = Some(temp_config.as_os_str().to_str().unwrap().to_owned());
 
     if temp_uploads {
@@ -63,6 +64,7 @@ fn _no_hardware_mode(temp_uploads: bool) {
     odyssey::start_odyssey(build_runtime(), config, Box::new(serial_handler));
 }
 
+#[allow(clippy::needless_late_init, clippy::single_match)]
 pub async fn serial_feedback_loop(
     sender: Sender<String>,
     mut receiver: Receiver<String>,
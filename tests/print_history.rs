@@ -0,0 +1,94 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use odyssey::{
+    api_objects::{FileMetadata, LocationCategory, PrintOutcome, PrintRecord},
+    print_history::{print_stats, PrintHistory},
+};
+use tempfile::TempDir;
+
+use crate::common::default_test_configuration;
+
+mod common;
+
+fn record(outcome: PrintOutcome, layers_completed: usize, used_material: f64) -> PrintRecord {
+    PrintRecord {
+        file_data: FileMetadata {
+            path: "test.sl1".to_string(),
+            name: "test.sl1".to_string(),
+            last_modified: None,
+            file_size: 0,
+            location_category: LocationCategory::Local,
+            parent_path: "uploads".to_string(),
+        },
+        started_at: Some(1000),
+        ended_at: 1600,
+        layers_completed,
+        layer_count: 10,
+        outcome,
+        used_material,
+    }
+}
+
+#[test]
+fn test_print_history_round_trip() {
+    let temp_dir = TempDir::new().expect("Unable to create temp directory for test");
+    let path = temp_dir.path().join("history.jsonl");
+
+    let mut config = default_test_configuration().printer;
+    config.print_history_file = Some(path.to_str().unwrap().to_string());
+    let history = PrintHistory::new(&config);
+
+    assert!(history.load().unwrap().is_empty());
+
+    history.record(&record(PrintOutcome::Completed, 10, 5.0));
+    history.record(&record(PrintOutcome::Failed, 4, 2.0));
+
+    // A record cut short by a power loss is skipped, along with whatever was
+    // appended onto the same line, without losing the earlier records
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"{\"file_data\":").unwrap();
+    drop(file);
+    history.record(&record(PrintOutcome::Cancelled, 1, 0.5));
+
+    let records = history.load().unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].outcome, PrintOutcome::Completed);
+    assert_eq!(records[1].outcome, PrintOutcome::Failed);
+
+    let stats = print_stats(&records);
+    assert_eq!(stats.total_prints, 2);
+    assert_eq!(stats.completed_prints, 1);
+    assert_eq!(stats.failed_prints, 1);
+    assert_eq!(stats.cancelled_prints, 0);
+    assert_eq!(stats.total_print_seconds, 1200);
+    assert_eq!(stats.total_material_used, 7.0);
+    assert_eq!(stats.success_rate, Some(0.5));
+    assert_eq!(stats.most_printed_file.as_deref(), Some("test.sl1"));
+    assert_eq!(stats.most_printed_count, 2);
+}
+
+#[test]
+fn test_most_printed_file() {
+    let printed = |path: &str| {
+        let mut record = record(PrintOutcome::Completed, 10, 5.0);
+        record.file_data.path = path.to_string();
+        record
+    };
+
+    let stats = print_stats(&[]);
+    assert_eq!(stats.success_rate, None);
+    assert_eq!(stats.most_printed_file, None);
+
+    // Of the files tied for most prints, the most recently printed wins
+    let stats = print_stats(&[
+        printed("a.sl1"),
+        printed("b.sl1"),
+        printed("b.sl1"),
+        printed("c.sl1"),
+        printed("a.sl1"),
+    ]);
+    assert_eq!(stats.success_rate, Some(1.0));
+    assert_eq!(stats.most_printed_file.as_deref(), Some("a.sl1"));
+    assert_eq!(stats.most_printed_count, 2);
+}
@@ -0,0 +1,26 @@
+use std::io::ErrorKind;
+
+use odyssey::printfile::{normalize_tags, MAX_TAGS, MAX_TAG_LENGTH};
+
+fn tags(tags: &[&str]) -> Vec<String> {
+    tags.iter().map(|tag| tag.to_string()).collect()
+}
+
+#[test]
+fn test_normalize_tags() {
+    assert_eq!(
+        normalize_tags(tags(&[" minis ", "", "calibration", "minis"])).unwrap(),
+        tags(&["minis", "calibration"])
+    );
+}
+
+#[test]
+fn test_tag_limits() {
+    let too_long = "a".repeat(MAX_TAG_LENGTH + 1);
+    let error = normalize_tags(vec![too_long]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+    let too_many = (0..=MAX_TAGS).map(|i| i.to_string()).collect();
+    let error = normalize_tags(too_many).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
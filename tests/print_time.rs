@@ -0,0 +1,118 @@
+use std::io;
+
+use async_trait::async_trait;
+use odyssey::{
+    api_objects::{FileData, FileMetadata, LocationCategory, PrintMetadata, PrintUserMetadata},
+    configuration::{ResinProfile, TopFadeConfig},
+    printfile::{Layer, PrintFile},
+};
+
+use crate::common::default_test_configuration;
+
+mod common;
+
+/// Layers of 50 microns, with a 2s exposure on the first layer and 1s after
+struct StubFile {
+    layer_count: usize,
+}
+
+#[async_trait]
+impl PrintFile for StubFile {
+    fn from_file(_file_data: FileMetadata) -> Result<Self, io::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "StubFile isn't read from a file",
+        ))
+    }
+    async fn get_layer_data(&mut self, _index: usize) -> Option<Layer> {
+        None
+    }
+    fn get_layer_count(&self) -> usize {
+        self.layer_count
+    }
+    fn get_exposure_time(&self, index: usize) -> f64 {
+        if index == 0 {
+            2.0
+        } else {
+            1.0
+        }
+    }
+    fn get_layer_height(&self) -> u32 {
+        50
+    }
+    fn get_metadata(&self) -> PrintMetadata {
+        PrintMetadata {
+            file_data: FileMetadata {
+                path: "stub.sl1".to_string(),
+                name: "stub.sl1".to_string(),
+                last_modified: None,
+                file_size: 0,
+                location_category: LocationCategory::Local,
+                parent_path: String::new(),
+            },
+            used_material: 0.0,
+            print_time: 0.0,
+            layer_height: 0.05,
+            layer_height_microns: self.get_layer_height(),
+            layer_count: self.layer_count,
+            user_metadata: PrintUserMetadata {
+                print_count: 0,
+                favorite: false,
+                rating: None,
+                tags: Vec::new(),
+            },
+            estimated_print_time: None,
+        }
+    }
+    fn get_thumbnail(&mut self, _width: u32, _height: u32) -> Result<FileData, io::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "StubFile has no thumbnail",
+        ))
+    }
+}
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+}
+
+#[test]
+fn test_recompute_print_time_sums_layers() {
+    let mut config = default_test_configuration().printer;
+    config.default_lift = 1.95;
+    config.default_up_speed = 2.0;
+    config.default_down_speed = 1.0;
+    config.default_settle_time = Some(0.5);
+    config.default_wait_before_exposure = 1.0;
+    config.default_wait_after_exposure = 0.5;
+    config.exposure_multiplier = None;
+    config.wait_before_exposure_multiplier = None;
+    config.wait_after_exposure_multiplier = None;
+    config.top_fade = None;
+
+    let file = StubFile { layer_count: 3 };
+    let settings = file.print_settings(None, &config);
+
+    // Each layer lifts 2mm up at 2mm/s and 1.95mm down at 1mm/s, then waits
+    // 2s around its exposure
+    let per_layer = 1.0 + 1.95 + 2.0;
+    assert_close(
+        file.recompute_print_time(&settings, &config),
+        3.0 * per_layer + 2.0 + 1.0 + 1.0,
+    );
+
+    // Profiles and top fade change the estimate as they would the print
+    let profile = ResinProfile {
+        exposure_multiplier: Some(2.0),
+        ..Default::default()
+    };
+    config.top_fade = Some(TopFadeConfig {
+        layers: 1,
+        final_exposure_factor: 0.5,
+    });
+    let settings = file.print_settings(Some(&profile), &config);
+    assert_close(
+        file.recompute_print_time(&settings, &config),
+        3.0 * per_layer + 4.0 + 2.0 + 1.0,
+    );
+}
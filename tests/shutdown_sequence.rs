@@ -0,0 +1,29 @@
+use odyssey::{
+    gcode::{BacklashCompensation, Gcode},
+    printer::HardwareControl,
+    serial_handler::InternalCommsHandler,
+};
+
+use crate::common::default_test_configuration;
+
+mod common;
+
+#[tokio::test]
+async fn test_failed_substitution_doesnt_stop_shutdown() {
+    let mut config = default_test_configuration().gcode;
+    config.shutdown = "MOVE_PLATE Z={unknown}\nM84\nUVLED_OFF".to_string();
+    let mut serial = InternalCommsHandler::new();
+    let mut gcode = Gcode::new(
+        &config,
+        BacklashCompensation::new(0, 0, 150_000),
+        serial.invert(),
+    );
+
+    assert!(gcode.shutdown().await.is_err());
+
+    let mut sent = Vec::new();
+    for _ in 0..3 {
+        sent.push(serial.receive().await.unwrap());
+    }
+    assert_eq!(sent, ["END_CURE\r\n", "M84\r\n", "UVLED_OFF\r\n"]);
+}
@@ -0,0 +1,61 @@
+use std::{fs, io::ErrorKind, path::Path};
+
+use odyssey::configuration::PrintUploadDirectory;
+use tempfile::TempDir;
+
+fn upload_directory() -> (PrintUploadDirectory, TempDir) {
+    let temp_dir = TempDir::new().expect("Unable to create temp directory for test");
+    let directory = PrintUploadDirectory {
+        label: "default".to_string(),
+        path: temp_dir.path().to_str().unwrap().to_string(),
+        kind: None,
+    };
+    (directory, temp_dir)
+}
+
+#[test]
+fn test_resolve_rejects_escaping_paths() {
+    let (directory, temp_dir) = upload_directory();
+
+    assert_eq!(
+        directory.resolve(Path::new("prints/a.sl1")).unwrap(),
+        temp_dir.path().join("prints/a.sl1")
+    );
+    for path in ["../a.sl1", "prints/../../a.sl1", "/etc/passwd", ""] {
+        let error = directory.resolve(Path::new(path)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput, "{path}");
+    }
+}
+
+#[test]
+fn test_move_and_copy_files() {
+    let (directory, temp_dir) = upload_directory();
+    fs::write(temp_dir.path().join("a.sl1"), b"print").unwrap();
+
+    directory
+        .copy_file(Path::new("a.sl1"), Path::new("copies/b.sl1"))
+        .expect("Unable to copy file");
+    directory
+        .move_file(Path::new("a.sl1"), Path::new("moved/a.sl1"))
+        .expect("Unable to move file");
+
+    assert!(!temp_dir.path().join("a.sl1").exists());
+    assert_eq!(
+        fs::read(temp_dir.path().join("copies/b.sl1")).unwrap(),
+        b"print"
+    );
+    assert_eq!(
+        fs::read(temp_dir.path().join("moved/a.sl1")).unwrap(),
+        b"print"
+    );
+
+    let error = directory
+        .move_file(Path::new("copies/b.sl1"), Path::new("moved/a.sl1"))
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+
+    let error = directory
+        .copy_file(Path::new("moved/a.sl1"), Path::new("../a.sl1"))
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
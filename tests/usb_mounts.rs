@@ -0,0 +1,25 @@
+use odyssey::usb::{mount_pattern, parse_mounts};
+
+#[test]
+fn test_mount_pattern() {
+    let pattern = mount_pattern("/media/usb*/*.sl1").expect("No mount pattern");
+
+    assert_eq!(pattern.as_str(), "/media/usb*");
+    assert_eq!(
+        mount_pattern("/media/stick/*.sl1").map(|pattern| pattern.as_str().to_string()),
+        Some("/media/stick".to_string())
+    );
+    assert!(mount_pattern("").is_none());
+}
+
+#[test]
+fn test_parse_mounts() {
+    let mounts = "/dev/root / ext4 rw,noatime 0 0\n\
+        /dev/sda1 /media/usb0 vfat rw,nosuid 0 0\n\
+        /dev/sdb1 /media/My\\040Stick vfat rw,nosuid 0 0\n";
+
+    assert_eq!(
+        parse_mounts(mounts),
+        vec!["/", "/media/usb0", "/media/My Stick"]
+    );
+}